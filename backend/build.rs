@@ -26,4 +26,162 @@ fn main() {
         eprintln!("Rollup command failed with output:\n{}", String::from_utf8_lossy(&output.stderr));
         std::process::exit(1);
     }
+
+    #[cfg(feature = "embed-assets")]
+    embed_assets::generate(Path::new("../public"));
+}
+
+/// Writes the asset table `webserver::embedded` includes: every file of `public/` with its ETag
+/// and gzip and brotli variants. Stylesheets, scripts and the wasm module also get a copy named
+/// after their content hash, and the files loading them are rewritten to link to those copies, so
+/// browsers can keep them for good while the page itself is revalidated.
+#[cfg(feature = "embed-assets")]
+mod embed_assets {
+    use std::env;
+    use std::fmt::Write as _;
+    use std::fs;
+    use std::io::Write;
+    use std::path::{Path, PathBuf};
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use sha2::{Digest, Sha256};
+
+    const COMPRESSIBLE_EXTENSIONS: [&str; 7] = ["css", "html", "js", "json", "svg", "txt", "wasm"];
+    const HASHED_EXTENSIONS: [&str; 3] = ["css", "js", "wasm"];
+
+    struct Asset {
+        path: String,
+        content: Vec<u8>,
+    }
+
+    pub fn generate(public_path: &Path) {
+        let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+        let content_dir = out_dir.join("embedded_assets");
+        fs::create_dir_all(&content_dir).expect("Failed to create the embedded asset directory");
+
+        let mut files = Vec::new();
+        collect_files(public_path, public_path, &mut files);
+        // Linked files are handled before the ones linking them: the wasm module and images,
+        // then the scripts and stylesheets, then the pages
+        files.sort_by_key(|(path, _)| match extension(path) {
+            "html" => 2,
+            "css" | "js" => 1,
+            _ => 0,
+        });
+
+        let mut renames: Vec<(String, String)> = Vec::new();
+        let mut table = String::from("pub static ASSETS: &[EmbeddedAsset] = &[\n");
+        for (index, (path, file_path)) in files.into_iter().enumerate() {
+            let mut content = fs::read(&file_path).expect("Failed to read a public file");
+            if matches!(extension(&path), "css" | "html" | "js") {
+                content = rewrite_links(content, &renames);
+            }
+            let hash = hex_digest(&content);
+
+            if HASHED_EXTENSIONS.contains(&extension(&path)) {
+                let hashed_path = hashed_path(&path, &hash[..16]);
+                renames.push((format!("/{}", path), format!("/{}", hashed_path)));
+                // Longer paths first so none is rewritten through a prefix of it
+                renames.sort_by_key(|(from, _)| std::cmp::Reverse(from.len()));
+                let asset = Asset { path: hashed_path, content: content.clone() };
+                write_asset(&mut table, &content_dir, &format!("{}-hashed", index), &asset, &hash, true);
+            }
+            let asset = Asset { path, content };
+            write_asset(&mut table, &content_dir, &index.to_string(), &asset, &hash, false);
+        }
+        table.push_str("];\n");
+
+        fs::write(out_dir.join("embedded_assets.rs"), table).expect("Failed to write the embedded asset table");
+    }
+
+    /// Paths relative to the working directory the files are served from, like `public/css/style.css`.
+    fn collect_files(public_path: &Path, directory: &Path, files: &mut Vec<(String, PathBuf)>) {
+        let entries = fs::read_dir(directory).expect("Failed to read the public directory");
+        for entry in entries {
+            let file_path = entry.expect("Failed to read the public directory").path();
+            if file_path.is_dir() {
+                collect_files(public_path, &file_path, files);
+                continue;
+            }
+
+            let relative = file_path.strip_prefix(public_path).unwrap();
+            let path = Path::new("public").join(relative).to_string_lossy().replace('\\', "/");
+            files.push((path, file_path));
+        }
+    }
+
+    fn write_asset(table: &mut String, content_dir: &Path, name: &str, asset: &Asset, hash: &str, immutable: bool) {
+        let include = |suffix: &str, content: &[u8]| {
+            let file_path = content_dir.join(format!("{}{}", name, suffix));
+            fs::write(&file_path, content).expect("Failed to write an embedded asset");
+            format!("include_bytes!({:?})", file_path.to_string_lossy())
+        };
+
+        let (gzip, brotli) = if COMPRESSIBLE_EXTENSIONS.contains(&extension(&asset.path)) {
+            (gzip(&asset.content), brotli(&asset.content))
+        } else {
+            (None, None)
+        };
+        let variant = |suffix: &str, content: Option<Vec<u8>>| match content {
+            Some(content) => format!("Some({})", include(suffix, &content)),
+            None => String::from("None"),
+        };
+
+        writeln!(
+            table,
+            "    EmbeddedAsset {{ path: {:?}, content: {}, gzip: {}, brotli: {}, etag: {:?}, immutable: {} }},",
+            asset.path,
+            include("", &asset.content),
+            variant(".gz", gzip),
+            variant(".br", brotli),
+            format!("\"{}\"", &hash[..32]),
+            immutable
+        )
+        .unwrap();
+    }
+
+    fn rewrite_links(content: Vec<u8>, renames: &[(String, String)]) -> Vec<u8> {
+        let mut text = match String::from_utf8(content) {
+            Ok(text) => text,
+            Err(error) => return error.into_bytes(),
+        };
+        for (from, to) in renames {
+            text = text.replace(from.as_str(), to);
+        }
+        text.into_bytes()
+    }
+
+    /// `public/js/bundle.js` becomes `public/js/bundle.<hash>.js`.
+    fn hashed_path(path: &str, hash: &str) -> String {
+        match path.rsplit_once('.') {
+            Some((stem, extension)) => format!("{}.{}.{}", stem, hash, extension),
+            None => format!("{}.{}", path, hash),
+        }
+    }
+
+    fn extension(path: &str) -> &str {
+        Path::new(path).extension().and_then(|extension| extension.to_str()).unwrap_or_default()
+    }
+
+    fn hex_digest(content: &[u8]) -> String {
+        Sha256::digest(content).iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    /// Variants are only kept when they save something.
+    fn gzip(content: &[u8]) -> Option<Vec<u8>> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(content).unwrap();
+        let compressed = encoder.finish().unwrap();
+        Some(compressed).filter(|compressed| compressed.len() < content.len())
+    }
+
+    fn brotli(content: &[u8]) -> Option<Vec<u8>> {
+        let mut compressed = Vec::new();
+        {
+            let mut encoder = brotli::CompressorWriter::new(&mut compressed, 4096, 11, 22);
+            encoder.write_all(content).unwrap();
+        }
+        Some(compressed).filter(|compressed| compressed.len() < content.len())
+    }
 }
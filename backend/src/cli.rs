@@ -0,0 +1,64 @@
+use clap::{Parser, Subcommand};
+
+use crate::shared::ConfigSource;
+
+#[derive(Parser)]
+#[command(version, about = "File Link signaling server")]
+pub struct Cli {
+    /// Configuration file to load
+    #[arg(long, default_value = "config.toml", global = true)]
+    pub config: String,
+
+    /// Overrides web_bind_addr
+    #[arg(long, global = true)]
+    pub bind_addr: Option<String>,
+
+    /// Overrides web_port
+    #[arg(long, global = true)]
+    pub port: Option<u16>,
+
+    /// Overrides log_level
+    #[arg(long, global = true)]
+    pub log_level: Option<String>,
+
+    /// Overrides any backend_config key, e.g. `--set session_store=sqlite`
+    #[arg(long = "set", value_name = "KEY=VALUE", value_parser = parse_override, global = true)]
+    pub overrides: Vec<(String, String)>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Validates the configuration, reporting every problem found, and exits
+    CheckConfig,
+}
+
+impl Cli {
+    /// Flags take precedence over `--set`, which takes precedence over the environment.
+    pub fn config_source(&self) -> ConfigSource {
+        let mut overrides = self.overrides.clone();
+        if let Some(bind_addr) = &self.bind_addr {
+            overrides.push(("web_bind_addr".to_string(), bind_addr.clone()));
+        }
+        if let Some(port) = self.port {
+            overrides.push(("web_port".to_string(), port.to_string()));
+        }
+        if let Some(log_level) = &self.log_level {
+            overrides.push(("log_level".to_string(), log_level.clone()));
+        }
+
+        ConfigSource {
+            path: self.config.clone(),
+            overrides,
+        }
+    }
+}
+
+fn parse_override(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected KEY=VALUE, got \"{}\"", value)),
+    }
+}
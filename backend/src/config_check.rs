@@ -0,0 +1,209 @@
+use std::net::IpAddr;
+use std::path::Path;
+
+use tracing_subscriber::EnvFilter;
+
+use crate::shared::{config_source, load_config, BackendConfig, Config};
+use crate::webserver::code_generator::resolve_alphabet;
+
+/// Loads the configuration the way the server would and collects every problem with it.
+pub fn check_config() -> Result<(), Vec<String>> {
+    let config = load_config::<Config>(config_source()).map_err(|error| vec![error.to_string()])?;
+    let problems = validate(&config.backend_config);
+    if !problems.is_empty() {
+        return Err(problems);
+    }
+    Ok(())
+}
+
+pub fn print_problems(problems: &[String]) {
+    eprintln!("Configuration from {} is invalid:", config_source().path);
+    for problem in problems {
+        eprintln!("  - {}", problem);
+    }
+}
+
+pub fn validate(backend_config: &BackendConfig) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    if backend_config.web_bind_addr.parse::<IpAddr>().is_err() {
+        problems.push(format!(
+            "web_bind_addr \"{}\" is not an IP address, use for example \"0.0.0.0\" or \"::\"",
+            backend_config.web_bind_addr
+        ));
+    }
+    if backend_config.web_port == 0 {
+        problems.push("web_port must be between 1 and 65535".to_string());
+    }
+    if !backend_config.web_serve_page && !backend_config.web_serve_api {
+        problems.push("web_serve_page and web_serve_api are both false, the server would serve nothing".to_string());
+    }
+    if backend_config.web_serve_page && !cfg!(feature = "embed-assets") && !Path::new(&backend_config.web_root).is_dir() {
+        problems.push(format!("web_root \"{}\" is not a directory, the page could not be served", backend_config.web_root));
+    }
+
+    if backend_config.web_tls_enabled {
+        check_file(&mut problems, "web_tls_certs", &backend_config.web_tls_certs, true);
+        check_file(&mut problems, "web_tls_key", &backend_config.web_tls_key, true);
+    }
+    check_file(&mut problems, "web_tls_ca_cert", &backend_config.web_tls_ca_cert, false);
+    if backend_config.admin_client_cert && (!backend_config.web_tls_enabled || backend_config.web_tls_ca_cert.is_empty()) {
+        problems.push("admin_client_cert needs web_tls_enabled and a web_tls_ca_cert to verify certificates with".to_string());
+    }
+    if backend_config.web_serve_admin && backend_config.admin_token.is_empty() && !backend_config.admin_client_cert {
+        problems.push("web_serve_admin is enabled but neither admin_token nor admin_client_cert is set, nobody could use it".to_string());
+    }
+
+    for (key, value) in [
+        ("session_ttl_secs", backend_config.session_ttl_secs),
+        ("session_idle_timeout_secs", backend_config.session_idle_timeout_secs),
+        ("session_poll_timeout_secs", backend_config.session_poll_timeout_secs),
+        ("session_gc_interval_secs", backend_config.session_gc_interval_secs),
+        ("rate_limit_window_secs", backend_config.rate_limit_window_secs),
+    ] {
+        if value == 0 {
+            problems.push(format!("{} must be at least 1", key));
+        }
+    }
+    if backend_config.session_max_password_attempts == 0 {
+        problems.push("session_max_password_attempts must be at least 1, otherwise every session is locked".to_string());
+    }
+
+    match backend_config.session_code_scheme.as_str() {
+        "random" => {
+            if let Err(error) = resolve_alphabet(&backend_config.session_code_alphabet) {
+                problems.push(format!("session_code_alphabet: {}", error));
+            }
+        }
+        "words" => {}
+        other => problems.push(format!("session_code_scheme \"{}\" is unknown, use \"random\" or \"words\"", other)),
+    }
+    if backend_config.session_code_length == 0 {
+        problems.push("session_code_length must be at least 1".to_string());
+    }
+    if backend_config.session_code_words == 0 {
+        problems.push("session_code_words must be at least 1".to_string());
+    }
+
+    match backend_config.session_store.as_str() {
+        "memory" => {}
+        "sqlite" => {
+            let directory = Path::new(&backend_config.session_store_path).parent();
+            if let Some(directory) = directory.filter(|directory| !directory.as_os_str().is_empty() && !directory.is_dir()) {
+                problems.push(format!(
+                    "session_store_path \"{}\" points into {}, which does not exist",
+                    backend_config.session_store_path,
+                    directory.display()
+                ));
+            }
+        }
+        other => problems.push(format!("session_store \"{}\" is unknown, use \"memory\" or \"sqlite\"", other)),
+    }
+
+    for url in &backend_config.ice_stun_urls {
+        if !url.starts_with("stun:") && !url.starts_with("stuns:") {
+            problems.push(format!("ice_stun_urls entry \"{}\" must start with \"stun:\" or \"stuns:\"", url));
+        }
+    }
+    for url in &backend_config.ice_turn_urls {
+        if !url.starts_with("turn:") && !url.starts_with("turns:") {
+            problems.push(format!("ice_turn_urls entry \"{}\" must start with \"turn:\" or \"turns:\"", url));
+        }
+    }
+    if backend_config.stun_enabled && backend_config.stun_port == 0 {
+        problems.push("stun_port must be between 1 and 65535".to_string());
+    }
+    if backend_config.relay_enabled && backend_config.relay_ice_timeout_secs == 0 {
+        problems.push("relay_ice_timeout_secs must be at least 1".to_string());
+    }
+    if backend_config.store_enabled {
+        if backend_config.store_path.is_empty() {
+            problems.push("store_enabled is set but store_path is empty".to_string());
+        }
+        if backend_config.store_quota_bytes == 0 {
+            problems.push("store_quota_bytes must be at least 1".to_string());
+        }
+        if backend_config.store_ttl_secs == 0 {
+            problems.push("store_ttl_secs must be at least 1".to_string());
+        }
+    }
+    if !backend_config.ice_turn_urls.is_empty() {
+        if backend_config.ice_turn_secret.is_empty() {
+            problems.push("ice_turn_urls are set but ice_turn_secret is empty, no credentials could be issued".to_string());
+        }
+        if backend_config.ice_turn_credential_ttl_secs == 0 {
+            problems.push("ice_turn_credential_ttl_secs must be at least 1".to_string());
+        }
+    }
+
+    if let Err(error) = EnvFilter::try_new(&backend_config.log_level) {
+        problems.push(format!("log_level \"{}\" is not a valid filter: {}", backend_config.log_level, error));
+    }
+    if !matches!(backend_config.log_format.as_str(), "text" | "json") {
+        problems.push(format!("log_format \"{}\" is unknown, use \"text\" or \"json\"", backend_config.log_format));
+    }
+
+    problems
+}
+
+fn check_file(problems: &mut Vec<String>, key: &str, path: &str, required: bool) {
+    if path.is_empty() {
+        if required {
+            problems.push(format!("{} is required when web_tls_enabled is true", key));
+        }
+        return;
+    }
+    if !Path::new(path).is_file() {
+        problems.push(format!("{} \"{}\" does not exist or is not a file", key, path));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use crate::shared::{load_config, Config, ConfigSource};
+
+    const BUNDLED_CONFIG: &str = include_str!("../../config.toml");
+
+    fn load_from(name: &str, content: &str) -> Config {
+        let path = env::temp_dir().join(format!("file-link-config-{}-{}.toml", name, process::id()));
+        fs::write(&path, content).unwrap();
+        let source = ConfigSource { path: path.to_string_lossy().to_string(), overrides: Vec::new() };
+        let config = load_config::<Config>(&source);
+        let _ = fs::remove_file(&path);
+        config.unwrap()
+    }
+
+    #[test]
+    fn defaults_equal_bundled_config() {
+        let bundled = load_from("bundled", BUNDLED_CONFIG);
+        let defaults = load_from("empty", "");
+        assert_eq!(format!("{:?}", defaults.backend_config), format!("{:?}", bundled.backend_config));
+        assert_eq!(defaults.frontend_config.api_address, bundled.frontend_config.api_address);
+    }
+
+    #[test]
+    fn file_values_win_over_defaults() {
+        let config = load_from("partial", "[backend_config]\nweb_port = 9000\nweb_serve_page = \"false\"\n");
+        assert_eq!(config.backend_config.web_port, 9000);
+        assert!(!config.backend_config.web_serve_page);
+        assert_eq!(config.backend_config.session_code_words, load_from("defaults", "").backend_config.session_code_words);
+    }
+
+    #[test]
+    fn overrides_of_missing_keys_are_typed_like_defaults() {
+        let path = env::temp_dir().join(format!("file-link-config-override-{}.toml", process::id()));
+        fs::write(&path, "").unwrap();
+        let source = ConfigSource {
+            path: path.to_string_lossy().to_string(),
+            overrides: vec![("web_port".to_string(), "9001".to_string()), ("admin_token".to_string(), "1234".to_string())],
+        };
+        let config = load_config::<Config>(&source);
+        let _ = fs::remove_file(&path);
+
+        let config = config.unwrap();
+        assert_eq!(config.backend_config.web_port, 9001);
+        assert_eq!(config.backend_config.admin_token, "1234");
+    }
+}
@@ -1,12 +1,37 @@
+mod cli;
+mod config_check;
 mod webserver;
 
+/// Where the page sends its API requests, compiled into the wasm module from this file.
+#[path = "../../frontend/src/constants.rs"]
+mod frontend_constants;
+
+use std::process;
+
+use clap::Parser;
+
+use cli::{Cli, Command};
+
 pub mod shared {
     use serde::{Deserialize, Serialize};
     use toml;
+    use utoipa::ToSchema;
     include!("../../shared/api_protocol.rs");
     include!("../../shared/config.rs");
+    include!("../../shared/session_code.rs");
 }
 
 fn main() {
-    webserver::webserver::webserver::run();
-}
\ No newline at end of file
+    let cli = Cli::parse();
+    shared::set_config_source(cli.config_source());
+
+    if let Err(problems) = config_check::check_config() {
+        config_check::print_problems(&problems);
+        process::exit(1);
+    }
+
+    match cli.command {
+        Some(Command::CheckConfig) => println!("Configuration from {} is valid", shared::config_source().path),
+        None => webserver::webserver::webserver::run(),
+    }
+}
@@ -0,0 +1,122 @@
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use rocket::http::Status;
+use rocket::mtls::Certificate;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::json::Json;
+use rocket::{delete, get, post, State};
+use subtle::ConstantTimeEq;
+use tracing::warn;
+
+use crate::shared::{AdminPurge, AdminPurgeResult, BACKEND_CONFIG};
+
+use super::logging::{redact, RequestSpan};
+use super::session::{push_signal, SessionSignal};
+use super::session_manager::SessionManager;
+
+/// Request guard for operator routes. Passes with the configured admin token as an
+/// `Authorization: Bearer` header, or with a client certificate signed by the mutual-TLS CA
+/// when `admin_client_cert` is enabled.
+pub struct Admin;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let backend_config = &*BACKEND_CONFIG;
+        if backend_config.admin_client_cert && request.guard::<Certificate<'_>>().await.is_success() {
+            return Outcome::Success(Admin);
+        }
+
+        let token = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "));
+        let admin_token = backend_config.admin_token.as_bytes();
+        match token {
+            Some(token) if !admin_token.is_empty() && bool::from(token.as_bytes().ct_eq(admin_token)) => Outcome::Success(Admin),
+            _ => Outcome::Error((Status::Unauthorized, ())),
+        }
+    }
+}
+
+#[get("/api/admin/sessions")]
+pub fn admin_list_sessions(_admin: Admin, session_manager: &State<Arc<RwLock<SessionManager>>>) -> Result<String, Status> {
+    let session_manager = session_manager.read();
+    if session_manager.is_err() {
+        return Err(Status::InternalServerError);
+    }
+    let session_manager = session_manager.unwrap();
+
+    let result = session_manager.list_sessions();
+    let result = serde_json::to_string(&result).unwrap();
+    Ok(result)
+}
+
+#[delete("/api/admin/sessions/<session_id>")]
+pub async fn admin_close_session(
+    _admin: Admin,
+    request_span: RequestSpan,
+    session_manager: &State<Arc<RwLock<SessionManager>>>,
+    session_id: String,
+) -> Result<(), Status> {
+    let condvar_details = {
+        let session_manager = session_manager.write();
+        if session_manager.is_err() {
+            return Err(Status::InternalServerError);
+        }
+        let mut session_manager = session_manager.unwrap();
+
+        let condvar_details = request_span.in_scope(|| session_manager.remove_session(&session_id));
+        if condvar_details.is_none() {
+            return Err(Status::NotFound);
+        }
+        condvar_details.unwrap()
+    };
+
+    request_span.in_scope(|| warn!(code = %redact(&session_id), "session revoked by admin"));
+    push_signal(&condvar_details, SessionSignal::Closed).await;
+    Ok(())
+}
+
+#[post("/api/admin/sessions/purge", data = "<purge>")]
+pub async fn admin_purge_sessions(
+    _admin: Admin,
+    request_span: RequestSpan,
+    session_manager: &State<Arc<RwLock<SessionManager>>>,
+    purge: Json<AdminPurge>,
+) -> Result<String, Status> {
+    let purge = purge.into_inner();
+    // Purging without any filter would close every session, that is what a restart is for
+    if purge.older_than_secs.is_none() && purge.owner_ip.is_none() {
+        return Err(Status::BadRequest);
+    }
+
+    let older_than = purge.older_than_secs.map(Duration::from_secs);
+    let owner_ip = match purge.owner_ip.map(|owner_ip| owner_ip.parse::<IpAddr>()) {
+        Some(Ok(owner_ip)) => Some(owner_ip),
+        Some(Err(_)) => return Err(Status::BadRequest),
+        None => None,
+    };
+
+    let purged = {
+        let session_manager = session_manager.write();
+        if session_manager.is_err() {
+            return Err(Status::InternalServerError);
+        }
+        let mut session_manager = session_manager.unwrap();
+        request_span.in_scope(|| session_manager.purge_sessions(older_than, owner_ip))
+    };
+
+    request_span.in_scope(|| warn!(closed = purged.len(), "sessions purged by admin"));
+    for condvar_details in &purged {
+        push_signal(condvar_details, SessionSignal::Closed).await;
+    }
+
+    let result = AdminPurgeResult { closed: purged.len() };
+    let result = serde_json::to_string(&result).unwrap();
+    Ok(result)
+}
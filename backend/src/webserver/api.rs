@@ -0,0 +1,274 @@
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{delete, get, post, put, State};
+use tracing::debug;
+
+use crate::shared::{
+    ClientGetDetails, ClientGetDetailsResult, ClientJoin, ClientJoinResult, HostCreate, HostCreateResult, HostPollResult,
+    HostUpdate, HostUpdateResult, BACKEND_CONFIG,
+};
+
+use super::api_error::ApiFailure;
+use super::logging::{redact, RequestSpan};
+use super::metrics::{GaugeGuard, METRICS};
+use super::owner_token::OwnerToken;
+use super::rate_limiter::RateLimit;
+use super::session::{pop_signal, push_signal, Session, SessionPassword, SessionSignal};
+use super::session_manager::{authorize, SessionManager};
+
+#[utoipa::path(
+    get,
+    path = "/api/v2/sessions/poll/{session_id}",
+    tag = "host",
+    params(("session_id" = String, Path, description = "Session code")),
+    security(("owner_token" = [])),
+    responses(
+        (status = 200, description = "A client answered the offer", body = HostPollResult),
+        (status = 403, description = "Wrong owner token", body = ApiError),
+        (status = 404, description = "Unknown session", body = ApiError),
+        (status = 408, description = "No answer before the poll timeout, poll again", body = ApiError),
+        (status = 410, description = "The session was closed or expired", body = ApiError),
+    )
+)]
+#[get("/api/v2/sessions/poll/<session_id>")]
+pub async fn poll_session(
+    owner_token: OwnerToken,
+    request_span: RequestSpan,
+    session_manager: &State<Arc<RwLock<SessionManager>>>,
+    session_id: String,
+) -> Result<Json<HostPollResult>, ApiFailure> {
+    request_span.in_scope(|| debug!(code = %redact(&session_id), "polling session"));
+
+    let condvar_details = {
+        let session_manager = session_manager.read();
+        if session_manager.is_err() {
+            return Err(Status::InternalServerError.into());
+        }
+        let session_manager = session_manager.unwrap();
+        if !session_manager.is_session_owner(&owner_token, &session_id) {
+            return Err(Status::Forbidden.into());
+        }
+
+        let condvar_details = session_manager.get_condvar_details(&session_id);
+        if condvar_details.is_none() {
+            return Err(Status::NotFound.into());
+        }
+        session_manager.touch_session(&session_id);
+        condvar_details.unwrap()
+    };
+
+    let _poll_waiter = GaugeGuard::new(&METRICS.poll_waiters);
+    let poll_timeout = Duration::from_secs(BACKEND_CONFIG.session_poll_timeout_secs);
+    let signal = tokio::time::timeout(poll_timeout, async {
+        loop {
+            // Long-polling hosts have no use for trickled candidates or leave notifications
+            let signal = pop_signal(&condvar_details).await;
+            if !matches!(signal, SessionSignal::Candidate(_, _) | SessionSignal::Leave(_)) {
+                return signal;
+            }
+        }
+    }).await;
+
+    let (offer_id, connection_details) = match signal {
+        Ok(SessionSignal::Answer(offer_id, connection_details)) => (offer_id, connection_details),
+        Ok(_) => return Err(Status::Gone.into()),
+        Err(_) => return Err(Status::RequestTimeout.into()),
+    };
+    if let Ok(mut session_manager) = session_manager.write() {
+        session_manager.answer_delivered(&session_id, offer_id);
+    }
+    Ok(Json(HostPollResult { offer_id, connection_details }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v2/sessions",
+    tag = "host",
+    request_body = HostCreate,
+    responses(
+        (status = 200, description = "Session created, keep the owner token for later requests", body = HostCreateResult),
+        (status = 400, description = "Malformed body", body = ApiError),
+        (status = 429, description = "Rate limited", body = ApiError),
+        (status = 503, description = "No unused session code could be generated", body = ApiError),
+    )
+)]
+#[post("/api/v2/sessions", data = "<session_create>")]
+pub async fn create_session(
+    _rate_limit: RateLimit,
+    request_span: RequestSpan,
+    owner_ip: Option<IpAddr>,
+    session_manager: &State<Arc<RwLock<SessionManager>>>,
+    session_create: Json<HostCreate>,
+) -> Result<Json<HostCreateResult>, ApiFailure> {
+    let session_create = session_create.into_inner();
+    let password = SessionPassword::hash(session_create.password.clone()).await;
+    let session = Session::from(session_create, password, owner_ip);
+
+    let session_manager = session_manager.write();
+    if session_manager.is_err() {
+        return Err(Status::InternalServerError.into());
+    }
+    let mut session_manager = session_manager.unwrap();
+
+    let result = request_span.in_scope(|| session_manager.create_session(session));
+    match result {
+        Some(result) => Ok(Json(result)),
+        None => Err(Status::ServiceUnavailable.into()),
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v2/sessions/{session_id}",
+    tag = "host",
+    params(("session_id" = String, Path, description = "Session code")),
+    request_body = HostUpdate,
+    security(("owner_token" = [])),
+    responses(
+        (status = 200, description = "New offer published", body = HostUpdateResult),
+        (status = 403, description = "Wrong owner token", body = ApiError),
+        (status = 404, description = "Unknown session", body = ApiError),
+    )
+)]
+#[put("/api/v2/sessions/<session_id>", data = "<session_update>")]
+pub fn update_session(
+    owner_token: OwnerToken,
+    session_manager: &State<Arc<RwLock<SessionManager>>>,
+    session_id: String,
+    session_update: Json<HostUpdate>,
+) -> Result<Json<HostUpdateResult>, ApiFailure> {
+    let session_manager = session_manager.write();
+    if session_manager.is_err() {
+        return Err(Status::InternalServerError.into());
+    }
+    let mut session_manager = session_manager.unwrap();
+    if !session_manager.is_session_owner(&owner_token, &session_id) {
+        return Err(Status::Forbidden.into());
+    }
+
+    let result = session_manager.update_session(&session_id, session_update.into_inner().connection_details);
+    match result {
+        Some(result) => Ok(Json(result)),
+        None => Err(Status::NotFound.into()),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v2/sessions/{session_id}",
+    tag = "host",
+    params(("session_id" = String, Path, description = "Session code")),
+    security(("owner_token" = [])),
+    responses(
+        (status = 200, description = "Session closed"),
+        (status = 403, description = "Wrong owner token", body = ApiError),
+        (status = 404, description = "Unknown session", body = ApiError),
+    )
+)]
+#[delete("/api/v2/sessions/<session_id>")]
+pub async fn close_session(
+    owner_token: OwnerToken,
+    request_span: RequestSpan,
+    session_manager: &State<Arc<RwLock<SessionManager>>>,
+    session_id: String,
+) -> Result<(), ApiFailure> {
+    let condvar_details = {
+        let session_manager = session_manager.write();
+        if session_manager.is_err() {
+            return Err(Status::InternalServerError.into());
+        }
+        let mut session_manager = session_manager.unwrap();
+        if !session_manager.is_session_owner(&owner_token, &session_id) {
+            return Err(Status::Forbidden.into());
+        }
+
+        let condvar_details = request_span.in_scope(|| session_manager.remove_session(&session_id));
+        if condvar_details.is_none() {
+            return Err(Status::NotFound.into());
+        }
+        condvar_details.unwrap()
+    };
+
+    push_signal(&condvar_details, SessionSignal::Closed).await;
+    Ok(())
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v2/sessions/details",
+    tag = "client",
+    request_body = ClientGetDetails,
+    responses(
+        (status = 200, description = "The host's current offer", body = ClientGetDetailsResult),
+        (status = 401, description = "Unknown session, wrong password or locked session", body = ApiError),
+        (status = 409, description = "The offer was already answered", body = ApiError),
+        (status = 429, description = "Rate limited", body = ApiError),
+    )
+)]
+#[post("/api/v2/sessions/details", data = "<session_join>")]
+pub async fn get_session_details(
+    _rate_limit: RateLimit,
+    session_manager: &State<Arc<RwLock<SessionManager>>>,
+    session_join: Json<ClientGetDetails>,
+) -> Result<Json<ClientGetDetailsResult>, ApiFailure> {
+    authorize(session_manager, &session_join.code, &session_join.password).await?;
+
+    let session_manager = session_manager.read();
+    if session_manager.is_err() {
+        return Err(Status::InternalServerError.into());
+    }
+    let session_manager = session_manager.unwrap();
+    session_manager.touch_session(&session_join.code);
+
+    let result = session_manager.get_connection_details(&session_join.code);
+    let result = match result {
+        Some(result) => result,
+        None => return Err(Status::Unauthorized.into()),
+    };
+    if !session_manager.is_offer_available(&session_join.code) {
+        return Err(Status::Conflict.into());
+    }
+    Ok(Json(result))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v2/sessions/join",
+    tag = "client",
+    request_body = ClientJoin,
+    responses(
+        (status = 200, description = "Answer handed to the host", body = ClientJoinResult),
+        (status = 401, description = "Unknown session, wrong password or locked session", body = ApiError),
+        (status = 404, description = "The session expired", body = ApiError),
+        (status = 409, description = "The offer was already answered or replaced", body = ApiError),
+        (status = 429, description = "Rate limited", body = ApiError),
+    )
+)]
+#[post("/api/v2/sessions/join", data = "<session_join>")]
+pub async fn join_session(
+    _rate_limit: RateLimit,
+    request_span: RequestSpan,
+    session_manager: &State<Arc<RwLock<SessionManager>>>,
+    session_join: Json<ClientJoin>,
+) -> Result<Json<ClientJoinResult>, ApiFailure> {
+    let session_join = session_join.into_inner();
+    authorize(session_manager, &session_join.code, &session_join.password).await?;
+
+    let (condvar_details, join_result) = {
+        let session_manager = session_manager.write();
+        if session_manager.is_err() {
+            return Err(Status::InternalServerError.into());
+        }
+        let mut session_manager = session_manager.unwrap();
+        let connection_details = session_join.connection_details.clone();
+        request_span.in_scope(|| session_manager.join_session(&session_join.code, session_join.offer_id, connection_details))?
+    };
+
+    let answer = SessionSignal::Answer(session_join.offer_id, session_join.connection_details);
+    push_signal(&condvar_details, answer).await;
+    Ok(Json(join_result))
+}
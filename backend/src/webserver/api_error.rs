@@ -0,0 +1,54 @@
+use rocket::http::Status;
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json::Json;
+use rocket::{catch, Request};
+
+use crate::shared::ApiError;
+
+/// Failure of a v2 API request, answered with its status and an [`ApiError`] body.
+#[derive(Debug)]
+pub struct ApiFailure {
+    pub status: Status,
+    pub error: ApiError,
+}
+
+impl From<Status> for ApiFailure {
+    fn from(status: Status) -> Self {
+        let (code, message) = match status.code {
+            400 => ("bad_request", "The request body is malformed"),
+            401 => ("unauthorized", "The session does not exist or the password is wrong"),
+            403 => ("forbidden", "The owner token does not match the session"),
+            404 => ("not_found", "The session does not exist"),
+            408 => ("timeout", "No client joined before the poll timed out"),
+            409 => ("offer_taken", "The offer was already answered by another client"),
+            410 => ("session_gone", "The session was closed or expired"),
+            413 => ("too_large", "The request body exceeds the size limit"),
+            422 => ("invalid_body", "The request body does not match the expected structure"),
+            429 => ("rate_limited", "Too many requests, try again later"),
+            503 => ("unavailable", "The server cannot handle the request right now"),
+            507 => ("storage_full", "The storage quota for uploaded files is used up"),
+            _ => ("internal_error", "The server failed to handle the request"),
+        };
+        ApiFailure {
+            status,
+            error: ApiError {
+                code: code.to_string(),
+                message: message.to_string(),
+            },
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ApiFailure {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        Response::build_from(Json(self.error).respond_to(request)?)
+            .status(self.status)
+            .ok()
+    }
+}
+
+/// Answers failed guards and unmatched routes under `/api/v2` with an `ApiError` body as well.
+#[catch(default)]
+pub fn api_catcher(status: Status, _request: &Request) -> ApiFailure {
+    ApiFailure::from(status)
+}
@@ -0,0 +1,153 @@
+use std::collections::HashSet;
+
+use rand::seq::SliceRandom;
+
+use crate::shared::{session_code_checksum_char, session_code_checksum_number, SESSION_CODE_WORD_SEPARATOR};
+
+const ALPHANUMERIC_ALPHABET: &str = "abcdefghijklmnopqrstuvwxyz0123456789";
+/// Leaves out characters that are easily confused when read aloud or handwritten, like 0/o and 1/l/i.
+const UNAMBIGUOUS_ALPHABET: &str = "23456789abcdefghjkmnpqrstuvwxyz";
+const WORDS: &str = include_str!("session_code_words.txt");
+
+/// Produces session codes, every code carries a checksum clients verify before joining.
+pub enum CodeGenerator {
+    /// `length` characters from `alphabet` followed by a checksum character, as in "k3v9qzt2mw4".
+    Random { alphabet: Vec<char>, length: usize },
+    /// A checksum number followed by `count` words, as in "61-purple-sailboat".
+    Words { words: Vec<&'static str>, count: usize },
+}
+
+impl CodeGenerator {
+    pub fn random(alphabet: &str, length: usize) -> Self {
+        let alphabet = resolve_alphabet(alphabet).unwrap_or_else(|error| panic!("{}", error));
+        if length == 0 {
+            panic!("Session code length must be positive");
+        }
+        CodeGenerator::Random { alphabet, length }
+    }
+
+    pub fn words(count: usize) -> Self {
+        if count == 0 {
+            panic!("Session code word count must be positive");
+        }
+        CodeGenerator::Words { words: WORDS.lines().collect(), count }
+    }
+
+    pub fn generate(&self) -> String {
+        let mut rng = rand::thread_rng();
+        match self {
+            CodeGenerator::Random { alphabet, length } => {
+                let mut code: String = (0..*length)
+                    .map(|_| *alphabet.choose(&mut rng).unwrap())
+                    .collect();
+                code.push(session_code_checksum_char(&code));
+                code
+            }
+            CodeGenerator::Words { words, count } => {
+                let words: Vec<&str> = (0..*count)
+                    .map(|_| *words.choose(&mut rng).unwrap())
+                    .collect();
+                let words = words.join(&SESSION_CODE_WORD_SEPARATOR.to_string());
+                format!("{}{}{}", session_code_checksum_number(&words), SESSION_CODE_WORD_SEPARATOR, words)
+            }
+        }
+    }
+}
+
+/// Expands the named alphabets, any other value is taken as the list of characters to use.
+/// Codes end up in `/receive/<code>` links, so only characters those carry unescaped are
+/// accepted, without the word separator.
+pub fn resolve_alphabet(alphabet: &str) -> Result<Vec<char>, String> {
+    let alphabet: Vec<char> = match alphabet {
+        "alphanumeric" => ALPHANUMERIC_ALPHABET.chars().collect(),
+        "unambiguous" => UNAMBIGUOUS_ALPHABET.chars().collect(),
+        alphabet => alphabet.chars().collect(),
+    };
+    if alphabet.len() < 2 {
+        return Err("Session code alphabet needs at least two characters".to_string());
+    }
+    if let Some(character) = alphabet.iter().find(|character| !character.is_ascii_alphanumeric() && !matches!(character, '_' | '~')) {
+        return Err(format!("Session code alphabet may only hold ASCII letters, digits, '_' and '~', not {:?}", character));
+    }
+    let mut seen = HashSet::new();
+    if let Some(character) = alphabet.iter().find(|character| !seen.insert(**character)) {
+        return Err(format!("Session code alphabet holds {:?} more than once", character));
+    }
+    Ok(alphabet)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::is_valid_session_code;
+
+    #[test]
+    fn documented_codes_are_valid() {
+        assert!(is_valid_session_code("k3v9qzt2mw4"));
+        assert!(is_valid_session_code("61-purple-sailboat"));
+    }
+
+    #[test]
+    fn generated_codes_are_valid() {
+        let generators = [
+            CodeGenerator::random("unambiguous", 10),
+            CodeGenerator::random("alphanumeric", 6),
+            CodeGenerator::words(4),
+        ];
+        for generator in &generators {
+            for _ in 0..100 {
+                let code = generator.generate();
+                assert!(is_valid_session_code(&code), "{} is not valid", code);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_wrong_checksums() {
+        assert!(!is_valid_session_code(""));
+        assert!(!is_valid_session_code("4"));
+        assert!(!is_valid_session_code("k3v9qzt2mw5"));
+        assert!(!is_valid_session_code("62-purple-sailboat"));
+        assert!(!is_valid_session_code("-purple-sailboat"));
+    }
+
+    #[test]
+    fn catches_typos() {
+        let body = "k3v9qzt2mw";
+        let checksum = session_code_checksum_char(body);
+        let with_checksum = |body: &str| format!("{}{}", body, checksum);
+
+        let mut typos = 0;
+        let mut missed = 0;
+        for (index, original) in body.char_indices() {
+            for replacement in UNAMBIGUOUS_ALPHABET.chars().filter(|character| *character != original) {
+                let mut typo = body.to_string();
+                typo.replace_range(index..index + 1, &replacement.to_string());
+                typos += 1;
+                if is_valid_session_code(&with_checksum(&typo)) {
+                    missed += 1;
+                }
+            }
+        }
+        // One checksum character out of 31 lets about one substitution in 31 through
+        assert!(missed * 10 < typos, "{} of {} typos passed", missed, typos);
+
+        for index in 0..body.len() - 1 {
+            let mut swapped: Vec<char> = body.chars().collect();
+            swapped.swap(index, index + 1);
+            let swapped: String = swapped.into_iter().collect();
+            assert!(!is_valid_session_code(&with_checksum(&swapped)), "swap {} passed", swapped);
+        }
+    }
+
+    #[test]
+    fn rejects_alphabets_unfit_for_links() {
+        assert!(resolve_alphabet("unambiguous").is_ok());
+        assert!(resolve_alphabet("ab_~").is_ok());
+        assert!(resolve_alphabet("a").is_err());
+        assert!(resolve_alphabet("abca").is_err());
+        assert!(resolve_alphabet("ab/c").is_err());
+        assert!(resolve_alphabet("ab-c").is_err());
+        assert!(resolve_alphabet("abé").is_err());
+    }
+}
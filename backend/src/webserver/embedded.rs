@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use once_cell::sync::Lazy;
+use rocket::http::{ContentType, Status};
+use rocket::response::{self, Responder, Response};
+use rocket::{get, Request};
+
+/// A file of `public/` compiled into the binary by the build script, with its precompressed
+/// variants where they came out smaller.
+pub struct EmbeddedAsset {
+    pub path: &'static str,
+    pub content: &'static [u8],
+    pub gzip: Option<&'static [u8]>,
+    pub brotli: Option<&'static [u8]>,
+    pub etag: &'static str,
+    /// Set for the content hashed copies the page links to, which never change under their name.
+    pub immutable: bool,
+}
+
+include!(concat!(env!("OUT_DIR"), "/embedded_assets.rs"));
+
+const INDEX_ASSET_PATH: &str = "public/index.html";
+const CACHE_IMMUTABLE: &str = "public, max-age=31536000, immutable";
+const CACHE_REVALIDATE: &str = "no-cache";
+
+static ASSETS_BY_PATH: Lazy<HashMap<&'static str, &'static EmbeddedAsset>> =
+    Lazy::new(|| ASSETS.iter().map(|asset| (asset.path, asset)).collect());
+
+/// Always, the page is part of the binary.
+pub fn page_available() -> bool {
+    true
+}
+
+#[get("/")]
+pub fn root() -> Option<EmbeddedFile> {
+    EmbeddedFile::find(INDEX_ASSET_PATH)
+}
+
+/// Paths not embedded get the page, like with the files on disk.
+#[get("/<path..>")]
+pub fn catch_all(path: PathBuf) -> Option<EmbeddedFile> {
+    path.to_str()
+        .and_then(|path| EmbeddedFile::find(&path.replace('\\', "/")))
+        .or_else(|| EmbeddedFile::find(INDEX_ASSET_PATH))
+}
+
+pub struct EmbeddedFile {
+    asset: &'static EmbeddedAsset,
+}
+
+impl EmbeddedFile {
+    fn find(path: &str) -> Option<Self> {
+        ASSETS_BY_PATH.get(path).map(|asset| Self { asset })
+    }
+}
+
+/// Answers revalidations with 304 and picks the smallest encoding the browser accepts.
+impl<'r> Responder<'r, 'static> for EmbeddedFile {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let asset = self.asset;
+        let mut response = Response::build();
+        response
+            .raw_header("ETag", asset.etag)
+            .raw_header("Cache-Control", if asset.immutable { CACHE_IMMUTABLE } else { CACHE_REVALIDATE })
+            .raw_header("Vary", "Accept-Encoding");
+        let extension = Path::new(asset.path).extension().and_then(|extension| extension.to_str());
+        if let Some(content_type) = extension.and_then(ContentType::from_extension) {
+            response.header(content_type);
+        }
+
+        let if_none_match = request.headers().get("If-None-Match");
+        if if_none_match.flat_map(|tags| tags.split(',')).any(|tag| tag.trim() == asset.etag || tag.trim() == "*") {
+            return response.status(Status::NotModified).ok();
+        }
+
+        let (body, encoding) = match (asset.brotli, asset.gzip) {
+            (Some(brotli), _) if accepts_encoding(request, "br") => (brotli, Some("br")),
+            (_, Some(gzip)) if accepts_encoding(request, "gzip") => (gzip, Some("gzip")),
+            _ => (asset.content, None),
+        };
+        if let Some(encoding) = encoding {
+            response.raw_header("Content-Encoding", encoding);
+        }
+        response.sized_body(body.len(), Cursor::new(body)).ok()
+    }
+}
+
+/// Whether `Accept-Encoding` lists the encoding without refusing it through `q=0`.
+fn accepts_encoding(request: &Request<'_>, encoding: &str) -> bool {
+    request
+        .headers()
+        .get("Accept-Encoding")
+        .flat_map(|encodings| encodings.split(','))
+        .any(|accepted| {
+            let mut parameters = accepted.split(';').map(str::trim);
+            let name = parameters.next().unwrap_or_default();
+            let refused = parameters.any(|parameter| matches!(parameter, "q=0" | "q=0.0" | "q=0.00" | "q=0.000"));
+            name.eq_ignore_ascii_case(encoding) && !refused
+        })
+}
@@ -0,0 +1,709 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rocket::data::{Data, ToByteUnit};
+use rocket::http::{ContentType, Status};
+use rocket::serde::json::Json;
+use rocket::{delete, get, post, put, State};
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+use tracing::{error, info};
+
+use crate::shared::{ClientGetDetails, StoredFileCreate, StoredFileInfo, StoredShare};
+
+use super::api_error::ApiFailure;
+use super::logging::redact;
+use super::metrics::METRICS;
+use super::owner_token::OwnerToken;
+use super::rate_limiter::RateLimit;
+use super::session::SessionPassword;
+use super::session_manager::SessionManager;
+
+const MANIFEST_FILE: &str = "manifest.json";
+/// Upper bound of one encrypted chunk, the page uploads 1 MiB of plaintext at a time.
+const MAX_CHUNK_BYTES: u64 = 2 * 1024 * 1024;
+const MAX_UUID_LENGTH: usize = 64;
+
+#[derive(Clone, Serialize, Deserialize)]
+struct StoredFileRecord {
+    uuid: String,
+    name: String,
+    size: u64,
+    chunks: u32,
+    bytes: u64,
+    complete: bool,
+    /// Set while a chunk is written, so no other upload of the file gets in between.
+    #[serde(skip)]
+    uploading: bool,
+}
+
+/// Everything kept about the files of one session, written next to the chunks as `manifest.json`.
+#[derive(Clone, Serialize, Deserialize)]
+struct ShareRecord {
+    password: SessionPassword,
+    access_token: String,
+    expires_at: u64,
+    files: Vec<StoredFileRecord>,
+    /// Counted like the session's, recipients are locked out at the session's `max_password_attempts`.
+    #[serde(default)]
+    failed_attempts: u32,
+}
+
+impl ShareRecord {
+    fn bytes(&self) -> u64 {
+        self.files.iter().map(|file| file.bytes).sum()
+    }
+
+    fn is_expired(&self, now: u64) -> bool {
+        now >= self.expires_at
+    }
+}
+
+/// Keeps files the host uploaded, encrypted in the browser, so recipients can download them after
+/// the host left. Files live under `<root>/<code>/<uuid>/<chunk>` until the share expires.
+pub struct FileStore {
+    root: PathBuf,
+    quota_bytes: u64,
+    ttl: Duration,
+    shares: Mutex<HashMap<String, ShareRecord>>,
+    /// Orders manifest writes, so an older state never overwrites a newer one.
+    manifest_writes: tokio::sync::Mutex<()>,
+}
+
+impl FileStore {
+    /// Loads the shares left from a previous run, expired ones are deleted on the way.
+    pub fn open(root: &str, quota_bytes: u64, ttl: Duration) -> io::Result<Self> {
+        let root = PathBuf::from(root);
+        fs::create_dir_all(&root)?;
+
+        let now = unix_now();
+        let mut shares = HashMap::new();
+        for entry in fs::read_dir(&root)? {
+            let path = entry?.path();
+            let code = match path.file_name().and_then(|name| name.to_str()) {
+                Some(code) if path.is_dir() => code.to_string(),
+                _ => continue,
+            };
+            let record = fs::read(path.join(MANIFEST_FILE))
+                .ok()
+                .and_then(|manifest| serde_json::from_slice::<ShareRecord>(&manifest).ok());
+            match record {
+                Some(record) if !record.is_expired(now) => {
+                    shares.insert(code, record);
+                }
+                _ => remove_dir(&path),
+            }
+        }
+
+        let file_store = Self {
+            root,
+            quota_bytes,
+            ttl,
+            shares: Mutex::new(shares),
+            manifest_writes: tokio::sync::Mutex::new(()),
+        };
+        let shares = file_store.codes().len();
+        file_store.update_metrics();
+        info!(shares, "loaded stored files");
+        Ok(file_store)
+    }
+
+    pub fn codes(&self) -> Vec<String> {
+        match self.shares.lock() {
+            Ok(shares) => shares.keys().cloned().collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// Announces a file, creating the session's share on its first one. Returns whether the share is new.
+    async fn add_file(&self, code: &str, password: SessionPassword, file_create: StoredFileCreate) -> Result<bool, Status> {
+        let valid_uuid = !file_create.uuid.is_empty()
+            && file_create.uuid.len() <= MAX_UUID_LENGTH
+            && file_create.uuid.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+        if !valid_uuid {
+            return Err(Status::BadRequest);
+        }
+
+        let created = {
+            let mut shares = self.shares.lock().map_err(|_| Status::InternalServerError)?;
+            let created = !shares.contains_key(code);
+            let share = shares.entry(code.to_string()).or_insert_with(|| ShareRecord {
+                password,
+                access_token: OwnerToken::generate().as_str().to_string(),
+                expires_at: unix_now() + self.ttl.as_secs(),
+                files: Vec::new(),
+                failed_attempts: 0,
+            });
+            if share.files.iter().any(|file| file.uuid == file_create.uuid) {
+                return Err(Status::Conflict);
+            }
+
+            share.files.push(StoredFileRecord {
+                uuid: file_create.uuid,
+                name: file_create.name,
+                size: file_create.size,
+                chunks: 0,
+                bytes: 0,
+                complete: false,
+                uploading: false,
+            });
+            created
+        };
+        self.save_manifest(code).await?;
+        if created {
+            info!(code = %redact(code), "share stored");
+        }
+        Ok(created)
+    }
+
+    /// Appends the next chunk of a file, chunks arrive strictly in order. Its bytes count against
+    /// the quota from the start, the write itself runs without holding the lock.
+    async fn put_chunk(&self, code: &str, uuid: &str, index: u32, chunk: &[u8]) -> Result<(), Status> {
+        {
+            let mut shares = self.shares.lock().map_err(|_| Status::InternalServerError)?;
+            let used_bytes: u64 = shares.values().map(ShareRecord::bytes).sum();
+            if used_bytes + chunk.len() as u64 > self.quota_bytes {
+                return Err(Status::InsufficientStorage);
+            }
+
+            let file = find_file(&mut shares, code, uuid)?;
+            if file.complete || file.uploading || file.chunks != index {
+                return Err(Status::Conflict);
+            }
+            file.uploading = true;
+            file.bytes += chunk.len() as u64;
+        }
+
+        let directory = self.root.join(code).join(uuid);
+        let written = match tokio::fs::create_dir_all(&directory).await {
+            Ok(_) => tokio::fs::write(directory.join(index.to_string()), chunk).await,
+            Err(error) => Err(error),
+        };
+        {
+            let mut shares = self.shares.lock().map_err(|_| Status::InternalServerError)?;
+            let file = find_file(&mut shares, code, uuid)?;
+            file.uploading = false;
+            match &written {
+                Ok(_) => file.chunks += 1,
+                Err(_) => file.bytes -= chunk.len() as u64,
+            }
+        }
+        if let Err(error) = written {
+            error!(code = %redact(code), %error, "failed to write chunk");
+            return Err(Status::InternalServerError);
+        }
+
+        self.update_metrics();
+        self.save_manifest(code).await
+    }
+
+    /// Makes a file visible to recipients, no more chunks are accepted afterwards.
+    async fn complete_file(&self, code: &str, uuid: &str) -> Result<(), Status> {
+        {
+            let mut shares = self.shares.lock().map_err(|_| Status::InternalServerError)?;
+            let file = find_file(&mut shares, code, uuid)?;
+            if file.uploading {
+                return Err(Status::Conflict);
+            }
+            file.complete = true;
+        }
+        self.save_manifest(code).await
+    }
+
+    /// Checks the password the session had when its files were stored, like [`super::session_manager::authorize`]
+    /// unknown codes and locked shares cost as much as wrong passwords.
+    async fn authorize(&self, code: &str, password: &str, max_attempts: u32) -> Result<StoredShare, Status> {
+        let share = {
+            let shares = self.shares.lock().map_err(|_| Status::InternalServerError)?;
+            shares
+                .get(code)
+                .filter(|share| !share.is_expired(unix_now()) && share.failed_attempts < max_attempts)
+                .cloned()
+        };
+
+        let verified = match &share {
+            Some(share) => share.password.clone().matches(password.to_string()).await,
+            None => {
+                SessionPassword::decoy().clone().matches(password.to_string()).await;
+                false
+            }
+        };
+        if !verified {
+            METRICS.password_failures.inc();
+            if share.is_some() {
+                self.record_failed_attempt(code).await;
+            }
+            return Err(Status::Unauthorized);
+        }
+
+        let share = share.unwrap();
+        let files = share.files
+            .iter()
+            .filter(|file| file.complete)
+            .map(|file| StoredFileInfo {
+                uuid: file.uuid.clone(),
+                name: file.name.clone(),
+                size: file.size,
+                chunks: file.chunks,
+            })
+            .collect();
+        Ok(StoredShare {
+            files,
+            access_token: share.access_token.clone(),
+            expires_in_secs: share.expires_at.saturating_sub(unix_now()),
+        })
+    }
+
+    async fn record_failed_attempt(&self, code: &str) {
+        let counted = match self.shares.lock() {
+            Ok(mut shares) => match shares.get_mut(code) {
+                Some(share) => {
+                    share.failed_attempts += 1;
+                    true
+                }
+                None => false,
+            },
+            Err(_) => false,
+        };
+        if counted {
+            let _ = self.save_manifest(code).await;
+        }
+    }
+
+    async fn read_chunk(&self, code: &str, uuid: &str, index: u32, access_token: &str) -> Result<Vec<u8>, Status> {
+        {
+            let shares = self.shares.lock().map_err(|_| Status::InternalServerError)?;
+            let share = shares.get(code).filter(|share| !share.is_expired(unix_now())).ok_or(Status::NotFound)?;
+            if !bool::from(share.access_token.as_bytes().ct_eq(access_token.as_bytes())) {
+                return Err(Status::Forbidden);
+            }
+            let file = share.files.iter().find(|file| file.uuid == uuid && file.complete).ok_or(Status::NotFound)?;
+            if index >= file.chunks {
+                return Err(Status::NotFound);
+            }
+        }
+
+        // Read without the lock, a share expiring meanwhile just turns into a 404
+        tokio::fs::read(self.root.join(code).join(uuid).join(index.to_string())).await.map_err(|_| Status::NotFound)
+    }
+
+    pub fn remove_share(&self, code: &str) -> bool {
+        let removed = match self.shares.lock() {
+            Ok(mut shares) => shares.remove(code).is_some(),
+            Err(_) => false,
+        };
+        if removed {
+            remove_dir(&self.root.join(code));
+            self.update_metrics();
+            info!(code = %redact(code), "stored share removed");
+        }
+        removed
+    }
+
+    /// Deletes every share past its expiry and returns their codes, so they can be released for new sessions.
+    pub fn remove_expired(&self) -> Vec<String> {
+        let now = unix_now();
+        let expired: Vec<String> = match self.shares.lock() {
+            Ok(mut shares) => {
+                let expired: Vec<String> = shares
+                    .iter()
+                    .filter(|(_, share)| share.is_expired(now))
+                    .map(|(code, _)| code.clone())
+                    .collect();
+                for code in &expired {
+                    shares.remove(code);
+                }
+                expired
+            }
+            Err(_) => return Vec::new(),
+        };
+
+        for code in &expired {
+            remove_dir(&self.root.join(code));
+            info!(code = %redact(code), "stored share expired");
+        }
+        if !expired.is_empty() {
+            self.update_metrics();
+        }
+        expired
+    }
+
+    /// Writes the current state of a share, taken once it is this call's turn to write.
+    async fn save_manifest(&self, code: &str) -> Result<(), Status> {
+        let _turn = self.manifest_writes.lock().await;
+        let manifest = {
+            let shares = self.shares.lock().map_err(|_| Status::InternalServerError)?;
+            match shares.get(code) {
+                Some(share) => serde_json::to_vec(share).unwrap(),
+                // Removed meanwhile, its directory is gone too
+                None => return Ok(()),
+            }
+        };
+
+        let directory = self.root.join(code);
+        let written = match tokio::fs::create_dir_all(&directory).await {
+            Ok(_) => tokio::fs::write(directory.join(MANIFEST_FILE), manifest).await,
+            Err(error) => Err(error),
+        };
+        written.map_err(|error| {
+            error!(code = %redact(code), %error, "failed to write stored share manifest");
+            Status::InternalServerError
+        })
+    }
+
+    fn update_metrics(&self) {
+        if let Ok(shares) = self.shares.lock() {
+            let bytes: u64 = shares.values().map(ShareRecord::bytes).sum();
+            METRICS.stored_bytes.set(bytes as i64);
+        }
+    }
+}
+
+fn find_file<'a>(shares: &'a mut HashMap<String, ShareRecord>, code: &str, uuid: &str) -> Result<&'a mut StoredFileRecord, Status> {
+    let share = shares.get_mut(code).ok_or(Status::NotFound)?;
+    share.files.iter_mut().find(|file| file.uuid == uuid).ok_or(Status::NotFound)
+}
+
+fn remove_dir(path: &Path) {
+    if let Err(error) = fs::remove_dir_all(path) {
+        error!(path = %path.display(), %error, "failed to remove stored files");
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+/// Checks ownership and returns the password recipients of the stored files have to know.
+fn owned_session_password(session_manager: &RwLock<SessionManager>, owner_token: &OwnerToken, code: &str) -> Result<SessionPassword, Status> {
+    let session_manager = session_manager.read().map_err(|_| Status::InternalServerError)?;
+    if !session_manager.is_session_owner(owner_token, code) {
+        return Err(Status::Forbidden);
+    }
+    let session = session_manager.get_session(code).ok_or(Status::NotFound)?;
+    Ok(session.password.clone())
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v2/stored/{session_id}/files",
+    tag = "store",
+    params(("session_id" = String, Path, description = "Session code")),
+    request_body = StoredFileCreate,
+    security(("owner_token" = [])),
+    responses(
+        (status = 200, description = "File announced, upload its chunks next"),
+        (status = 400, description = "Invalid file id", body = ApiError),
+        (status = 403, description = "Wrong owner token", body = ApiError),
+        (status = 409, description = "A file with this id was already announced", body = ApiError),
+    )
+)]
+#[post("/api/v2/stored/<session_id>/files", data = "<file_create>")]
+pub async fn create_stored_file(
+    owner_token: OwnerToken,
+    session_manager: &State<Arc<RwLock<SessionManager>>>,
+    file_store: &State<Arc<FileStore>>,
+    session_id: String,
+    file_create: Json<StoredFileCreate>,
+) -> Result<(), ApiFailure> {
+    let password = owned_session_password(session_manager, &owner_token, &session_id)?;
+    let created = file_store.add_file(&session_id, password, file_create.into_inner()).await?;
+    if created {
+        // The share outlives the session, its code must not be handed to a new one meanwhile
+        if let Ok(mut session_manager) = session_manager.write() {
+            session_manager.reserve_code(&session_id);
+        }
+    }
+    Ok(())
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v2/stored/{session_id}/files/{uuid}/{index}",
+    tag = "store",
+    params(
+        ("session_id" = String, Path, description = "Session code"),
+        ("uuid" = String, Path, description = "File id"),
+        ("index" = u32, Path, description = "Chunk number, starting at 0"),
+    ),
+    request_body(content = Vec<u8>, content_type = "application/octet-stream", description = "Encrypted chunk of at most 2 MiB"),
+    security(("owner_token" = [])),
+    responses(
+        (status = 200, description = "Chunk stored"),
+        (status = 403, description = "Wrong owner token", body = ApiError),
+        (status = 404, description = "Unknown file", body = ApiError),
+        (status = 409, description = "Not the next chunk, or the file is complete", body = ApiError),
+        (status = 413, description = "Chunk too large", body = ApiError),
+        (status = 507, description = "Storage quota used up", body = ApiError),
+    )
+)]
+#[put("/api/v2/stored/<session_id>/files/<uuid>/<index>", data = "<chunk>")]
+pub async fn upload_stored_chunk(
+    owner_token: OwnerToken,
+    session_manager: &State<Arc<RwLock<SessionManager>>>,
+    file_store: &State<Arc<FileStore>>,
+    session_id: String,
+    uuid: String,
+    index: u32,
+    chunk: Data<'_>,
+) -> Result<(), ApiFailure> {
+    owned_session_password(session_manager, &owner_token, &session_id)?;
+
+    let chunk = chunk.open(MAX_CHUNK_BYTES.bytes()).into_bytes().await.map_err(|_| Status::BadRequest)?;
+    if !chunk.is_complete() {
+        return Err(Status::PayloadTooLarge.into());
+    }
+    file_store.put_chunk(&session_id, &uuid, index, &chunk).await?;
+    Ok(())
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v2/stored/{session_id}/files/{uuid}/complete",
+    tag = "store",
+    params(
+        ("session_id" = String, Path, description = "Session code"),
+        ("uuid" = String, Path, description = "File id"),
+    ),
+    security(("owner_token" = [])),
+    responses(
+        (status = 200, description = "File offered to recipients"),
+        (status = 403, description = "Wrong owner token", body = ApiError),
+        (status = 404, description = "Unknown file", body = ApiError),
+    )
+)]
+#[post("/api/v2/stored/<session_id>/files/<uuid>/complete")]
+pub async fn complete_stored_file(
+    owner_token: OwnerToken,
+    session_manager: &State<Arc<RwLock<SessionManager>>>,
+    file_store: &State<Arc<FileStore>>,
+    session_id: String,
+    uuid: String,
+) -> Result<(), ApiFailure> {
+    owned_session_password(session_manager, &owner_token, &session_id)?;
+    file_store.complete_file(&session_id, &uuid).await?;
+    Ok(())
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v2/stored/{session_id}",
+    tag = "store",
+    params(("session_id" = String, Path, description = "Session code")),
+    security(("owner_token" = [])),
+    responses(
+        (status = 200, description = "Stored files deleted"),
+        (status = 403, description = "Wrong owner token", body = ApiError),
+        (status = 404, description = "Nothing stored for this session", body = ApiError),
+    )
+)]
+#[delete("/api/v2/stored/<session_id>")]
+pub fn delete_stored_share(
+    owner_token: OwnerToken,
+    session_manager: &State<Arc<RwLock<SessionManager>>>,
+    file_store: &State<Arc<FileStore>>,
+    session_id: String,
+) -> Result<(), ApiFailure> {
+    owned_session_password(session_manager, &owner_token, &session_id)?;
+    if !file_store.remove_share(&session_id) {
+        return Err(Status::NotFound.into());
+    }
+    if let Ok(mut session_manager) = session_manager.write() {
+        session_manager.release_code(&session_id);
+    }
+    Ok(())
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v2/stored/details",
+    tag = "store",
+    request_body = ClientGetDetails,
+    responses(
+        (status = 200, description = "Completely uploaded files and the token to download them", body = StoredShare),
+        (status = 401, description = "Nothing stored under this code or wrong password", body = ApiError),
+        (status = 429, description = "Rate limited", body = ApiError),
+    )
+)]
+#[post("/api/v2/stored/details", data = "<share_access>")]
+pub async fn get_stored_share(
+    _rate_limit: RateLimit,
+    session_manager: &State<Arc<RwLock<SessionManager>>>,
+    file_store: &State<Arc<FileStore>>,
+    share_access: Json<ClientGetDetails>,
+) -> Result<Json<StoredShare>, ApiFailure> {
+    let max_attempts = session_manager.read().map_err(|_| Status::InternalServerError)?.max_password_attempts();
+    let share = file_store.authorize(&share_access.code, &share_access.password, max_attempts).await?;
+    Ok(Json(share))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v2/stored/{session_id}/files/{uuid}/{index}",
+    tag = "store",
+    params(
+        ("session_id" = String, Path, description = "Session code"),
+        ("uuid" = String, Path, description = "File id"),
+        ("index" = u32, Path, description = "Chunk number, starting at 0"),
+        ("access_token" = String, Query, description = "Token from `StoredShare`"),
+    ),
+    responses(
+        (status = 200, description = "Encrypted chunk", body = Vec<u8>, content_type = "application/octet-stream"),
+        (status = 403, description = "Wrong access token", body = ApiError),
+        (status = 404, description = "Unknown share, file or chunk", body = ApiError),
+    )
+)]
+#[get("/api/v2/stored/<session_id>/files/<uuid>/<index>?<access_token>")]
+pub async fn download_stored_chunk(
+    file_store: &State<Arc<FileStore>>,
+    session_id: String,
+    uuid: String,
+    index: u32,
+    access_token: String,
+) -> Result<(ContentType, Vec<u8>), ApiFailure> {
+    let chunk = file_store.read_chunk(&session_id, &uuid, index, &access_token).await?;
+    Ok((ContentType::Binary, chunk))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUOTA_BYTES: u64 = 16;
+    const TTL: Duration = Duration::from_secs(3600);
+    const MAX_ATTEMPTS: u32 = 3;
+
+    /// A store root in the temp directory, removed again with the fixture.
+    struct Fixture {
+        root: PathBuf,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            let root = std::env::temp_dir().join(format!("file-link-files-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            Self { root }
+        }
+
+        fn open(&self, ttl: Duration) -> FileStore {
+            FileStore::open(self.root.to_str().unwrap(), QUOTA_BYTES, ttl).unwrap()
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    fn file(uuid: &str) -> StoredFileCreate {
+        StoredFileCreate {
+            uuid: uuid.to_string(),
+            name: "name".to_string(),
+            size: 8,
+        }
+    }
+
+    #[rocket::async_test]
+    async fn serves_completed_files() {
+        let fixture = Fixture::new("serve");
+        let file_store = fixture.open(TTL);
+        assert_eq!(file_store.add_file("ABC", SessionPassword::new("secret"), file("f")).await, Ok(true));
+        file_store.put_chunk("ABC", "f", 0, b"first").await.unwrap();
+        file_store.put_chunk("ABC", "f", 1, b"second").await.unwrap();
+        file_store.complete_file("ABC", "f").await.unwrap();
+
+        let share = file_store.authorize("ABC", "secret", MAX_ATTEMPTS).await.unwrap();
+        assert_eq!(share.files.len(), 1);
+        assert_eq!(share.files[0].chunks, 2);
+        assert_eq!(file_store.read_chunk("ABC", "f", 1, &share.access_token).await.unwrap(), b"second");
+        assert_eq!(file_store.read_chunk("ABC", "f", 2, &share.access_token).await, Err(Status::NotFound));
+        assert_eq!(file_store.read_chunk("ABC", "f", 0, "wrong").await, Err(Status::Forbidden));
+    }
+
+    #[rocket::async_test]
+    async fn rejects_chunks_over_quota() {
+        let fixture = Fixture::new("quota");
+        let file_store = fixture.open(TTL);
+        file_store.add_file("ABC", SessionPassword::new(""), file("f")).await.unwrap();
+        file_store.add_file("DEF", SessionPassword::new(""), file("g")).await.unwrap();
+        file_store.put_chunk("ABC", "f", 0, &[0; 10]).await.unwrap();
+
+        // The quota is shared by every share
+        assert_eq!(file_store.put_chunk("DEF", "g", 0, &[0; 7]).await, Err(Status::InsufficientStorage));
+        file_store.put_chunk("DEF", "g", 0, &[0; 6]).await.unwrap();
+    }
+
+    #[rocket::async_test]
+    async fn rejects_chunks_out_of_order() {
+        let fixture = Fixture::new("order");
+        let file_store = fixture.open(TTL);
+        file_store.add_file("ABC", SessionPassword::new(""), file("f")).await.unwrap();
+
+        assert_eq!(file_store.put_chunk("ABC", "f", 1, b"late").await, Err(Status::Conflict));
+        file_store.put_chunk("ABC", "f", 0, b"first").await.unwrap();
+        assert_eq!(file_store.put_chunk("ABC", "f", 0, b"again").await, Err(Status::Conflict));
+        file_store.complete_file("ABC", "f").await.unwrap();
+        assert_eq!(file_store.put_chunk("ABC", "f", 1, b"after").await, Err(Status::Conflict));
+    }
+
+    #[rocket::async_test]
+    async fn locks_out_after_failed_attempts() {
+        let fixture = Fixture::new("lockout");
+        let file_store = fixture.open(TTL);
+        file_store.add_file("ABC", SessionPassword::new("secret"), file("f")).await.unwrap();
+
+        for _ in 0..MAX_ATTEMPTS {
+            assert_eq!(file_store.authorize("ABC", "wrong", MAX_ATTEMPTS).await.err(), Some(Status::Unauthorized));
+        }
+        assert_eq!(file_store.authorize("ABC", "secret", MAX_ATTEMPTS).await.err(), Some(Status::Unauthorized));
+        // The count is kept in the manifest, reopening does not reset it
+        assert_eq!(fixture.open(TTL).authorize("ABC", "secret", MAX_ATTEMPTS).await.err(), Some(Status::Unauthorized));
+    }
+
+    #[rocket::async_test]
+    async fn reopens_shares_from_manifest() {
+        let fixture = Fixture::new("reopen");
+        let file_store = fixture.open(TTL);
+        file_store.add_file("ABC", SessionPassword::new("secret"), file("f")).await.unwrap();
+        file_store.put_chunk("ABC", "f", 0, b"first").await.unwrap();
+        file_store.complete_file("ABC", "f").await.unwrap();
+        file_store.add_file("ABC", SessionPassword::new("secret"), file("unfinished")).await.unwrap();
+        drop(file_store);
+
+        let file_store = fixture.open(TTL);
+        assert_eq!(file_store.codes(), vec!["ABC".to_string()]);
+        let share = file_store.authorize("ABC", "secret", MAX_ATTEMPTS).await.unwrap();
+        let uuids: Vec<&str> = share.files.iter().map(|file| file.uuid.as_str()).collect();
+        assert_eq!(uuids, vec!["f"]);
+        assert_eq!(file_store.read_chunk("ABC", "f", 0, &share.access_token).await.unwrap(), b"first");
+    }
+
+    #[rocket::async_test]
+    async fn removes_expired_shares() {
+        let fixture = Fixture::new("expiry");
+        let file_store = fixture.open(Duration::ZERO);
+        file_store.add_file("ABC", SessionPassword::new(""), file("f")).await.unwrap();
+        file_store.put_chunk("ABC", "f", 0, b"first").await.unwrap();
+
+        assert_eq!(file_store.authorize("ABC", "", MAX_ATTEMPTS).await.err(), Some(Status::Unauthorized));
+        assert_eq!(file_store.remove_expired(), vec!["ABC".to_string()]);
+        assert!(file_store.codes().is_empty());
+        assert!(!fixture.root.join("ABC").exists());
+    }
+
+    #[rocket::async_test]
+    async fn drops_expired_shares_on_open() {
+        let fixture = Fixture::new("expiry-open");
+        let file_store = fixture.open(Duration::ZERO);
+        file_store.add_file("ABC", SessionPassword::new(""), file("f")).await.unwrap();
+        drop(file_store);
+
+        assert!(fixture.open(TTL).codes().is_empty());
+        assert!(!fixture.root.join("ABC").exists());
+    }
+}
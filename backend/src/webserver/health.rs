@@ -0,0 +1,89 @@
+use std::sync::{Arc, RwLock};
+
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{get, State};
+
+use crate::shared::{Readiness, VersionInfo, API_PROTOCOL_VERSION, BACKEND_CONFIG};
+
+#[cfg(feature = "embed-assets")]
+use super::embedded::page_available;
+use super::session_manager::SessionManager;
+#[cfg(not(feature = "embed-assets"))]
+use super::static_files::page_available;
+
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "operations",
+    responses((status = 200, description = "The process is alive"))
+)]
+#[get("/healthz")]
+pub fn healthz() -> &'static str {
+    "ok"
+}
+
+/// Ready once the session store answers and, when the page is served, its assets were found.
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "operations",
+    responses(
+        (status = 200, description = "Ready to serve traffic", body = Readiness),
+        (status = 503, description = "A dependency is unusable", body = Readiness),
+    )
+)]
+#[get("/readyz")]
+pub fn readyz(session_manager: &State<Arc<RwLock<SessionManager>>>) -> (Status, Json<Readiness>) {
+    let session_store = match session_manager.read() {
+        Ok(session_manager) => session_manager.is_store_ready(),
+        Err(_) => false,
+    };
+    let static_assets = !BACKEND_CONFIG.web_serve_page || page_available();
+
+    let status = if session_store && static_assets {
+        Status::Ok
+    } else {
+        Status::ServiceUnavailable
+    };
+    (status, Json(Readiness { session_store, static_assets }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/version",
+    tag = "operations",
+    responses((status = 200, description = "Server version and enabled features", body = VersionInfo))
+)]
+#[get("/api/version")]
+pub fn version() -> Json<VersionInfo> {
+    Json(VersionInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        protocol_version: API_PROTOCOL_VERSION,
+        features: enabled_features(),
+    })
+}
+
+fn enabled_features() -> Vec<String> {
+    let backend_config = &*BACKEND_CONFIG;
+    let toggles = [
+        ("page", backend_config.web_serve_page),
+        ("api", backend_config.web_serve_api),
+        ("metrics", backend_config.web_serve_metrics),
+        ("admin", backend_config.web_serve_admin),
+        ("tls", backend_config.web_tls_enabled),
+        ("turn", !backend_config.ice_turn_urls.is_empty()),
+        ("stun", backend_config.stun_enabled),
+        ("relay", backend_config.relay_enabled),
+        ("store", backend_config.store_enabled),
+    ];
+
+    let mut features: Vec<String> = toggles
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(feature, _)| feature.to_string())
+        .collect();
+    features.push(format!("session_store:{}", backend_config.session_store));
+    features.push(format!("session_code:{}", backend_config.session_code_scheme));
+    features
+}
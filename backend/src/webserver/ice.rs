@@ -0,0 +1,107 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::{engine::general_purpose, Engine};
+use hmac::{Hmac, Mac};
+use rocket::get;
+use rocket::http::uri::Host;
+use rocket::serde::json::Json;
+use sha1::Sha1;
+
+use crate::shared::{IceServer, IceServers, BACKEND_CONFIG};
+
+use super::rate_limiter::RateLimit;
+
+/// Stands in for a user name, TURN servers only check the expiry in front of it.
+const TURN_USER: &str = "file-link";
+
+/// STUN and TURN servers for `RTCConfiguration.iceServers`. The built-in STUN responder is
+/// advertised under the host name the browser reached this server with, and peers learn how long
+/// to try a direct connection before switching to the server relay.
+#[utoipa::path(
+    get,
+    path = "/api/ice-servers",
+    tag = "client",
+    responses(
+        (status = 200, description = "ICE servers, TURN entries with fresh credentials", body = IceServers),
+        (status = 429, description = "Rate limited", body = ApiError),
+    )
+)]
+#[get("/api/ice-servers")]
+pub fn ice_servers(_rate_limit: RateLimit, host: Option<&Host<'_>>) -> Json<IceServers> {
+    let backend_config = &*BACKEND_CONFIG;
+    let mut ice_servers = Vec::new();
+    let mut ttl_secs = 0;
+
+    if let Some(host) = host.filter(|_| backend_config.stun_enabled) {
+        ice_servers.push(IceServer {
+            urls: vec![format!("stun:{}:{}", host.domain(), backend_config.stun_port)],
+            username: None,
+            credential: None,
+        });
+    }
+    if !backend_config.ice_stun_urls.is_empty() {
+        ice_servers.push(IceServer {
+            urls: backend_config.ice_stun_urls.clone(),
+            username: None,
+            credential: None,
+        });
+    }
+    if !backend_config.ice_turn_urls.is_empty() {
+        ttl_secs = backend_config.ice_turn_credential_ttl_secs;
+        let (username, credential) = turn_credentials(&backend_config.ice_turn_secret, Duration::from_secs(ttl_secs));
+        ice_servers.push(IceServer {
+            urls: backend_config.ice_turn_urls.clone(),
+            username: Some(username),
+            credential: Some(credential),
+        });
+    }
+
+    let relay_timeout_secs = match backend_config.relay_enabled {
+        true => backend_config.relay_ice_timeout_secs,
+        false => 0,
+    };
+
+    Json(IceServers { ice_servers, ttl_secs, relay_timeout_secs })
+}
+
+/// Credentials of the TURN REST scheme (coturn's `use-auth-secret`): the user name is the expiry
+/// as a unix timestamp, the password the base64 HMAC-SHA1 of the user name keyed with the shared secret.
+fn turn_credentials(secret: &str, ttl: Duration) -> (String, String) {
+    let expiry = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        + ttl;
+    let username = format!("{}:{}", expiry.as_secs(), TURN_USER);
+    let credential = turn_credential(secret, &username);
+    (username, credential)
+}
+
+fn turn_credential(secret: &str, username: &str) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(username.as_bytes());
+    general_purpose::STANDARD.encode(mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn credential_is_base64_hmac_sha1() {
+        // RFC 2202, test case 2
+        assert_eq!(turn_credential("Jefe", "what do ya want for nothing?"), "7/zfauXrL6LSdBbV8YTfnCWafHk=");
+        assert_eq!(turn_credential("my-turn-secret", "1700000000:file-link"), "V1oJpH2GMyquBxHNSXQzWNRSXuc=");
+    }
+
+    #[test]
+    fn username_carries_expiry() {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let (username, credential) = turn_credentials("my-turn-secret", Duration::from_secs(3600));
+
+        let (expiry, user) = username.split_once(':').unwrap();
+        let expiry: u64 = expiry.parse().unwrap();
+        assert!((now + 3600..=now + 3601).contains(&expiry));
+        assert_eq!(user, TURN_USER);
+        assert_eq!(credential, turn_credential("my-turn-secret", &username));
+    }
+}
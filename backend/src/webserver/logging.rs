@@ -0,0 +1,146 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+
+use once_cell::sync::{Lazy, OnceCell};
+use rand::Rng;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::{Data, Response};
+use tracing::{info, info_span, Span};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Registry};
+
+use crate::shared::BACKEND_CONFIG;
+
+const REQUEST_ID_HEADER: &str = "X-Request-Id";
+/// Longer client request IDs are replaced, like ones with characters outside `[A-Za-z0-9-]`.
+const MAX_REQUEST_ID_LENGTH: usize = 64;
+
+/// Keyed per process, so redacted codes correlate within one run but cannot be reversed
+/// by hashing every possible code.
+static REDACTION_KEY: Lazy<RandomState> = Lazy::new(RandomState::new);
+
+static FILTER_HANDLE: OnceCell<reload::Handle<EnvFilter, Registry>> = OnceCell::new();
+
+pub fn init() {
+    let backend_config = &*BACKEND_CONFIG;
+    let filter = EnvFilter::try_new(&backend_config.log_level).expect("Invalid log level");
+    let (filter, filter_handle) = reload::Layer::new(filter);
+    let subscriber = tracing_subscriber::registry().with(filter);
+
+    match backend_config.log_format.as_str() {
+        "json" => subscriber.with(fmt::layer().json()).init(),
+        "text" => subscriber.with(fmt::layer()).init(),
+        other => panic!("Unknown log format: {}", other),
+    }
+    let _ = FILTER_HANDLE.set(filter_handle);
+}
+
+/// Swaps the level filter of the running subscriber.
+pub fn set_level(level: &str) -> Result<(), String> {
+    let filter = EnvFilter::try_new(level).map_err(|error| error.to_string())?;
+    let filter_handle = FILTER_HANDLE.get().ok_or("Logging is not initialized")?;
+    filter_handle.reload(filter).map_err(|error| error.to_string())
+}
+
+/// Stands in for a session code in logs.
+pub fn redact(code: &str) -> String {
+    format!("{:016x}", REDACTION_KEY.hash_one(code))
+}
+
+/// Span covering one request, tagged with the request ID that is echoed back in `X-Request-Id`.
+#[derive(Clone)]
+pub struct RequestSpan {
+    pub request_id: String,
+    pub span: Span,
+}
+
+impl RequestSpan {
+    fn new(request: &Request<'_>) -> Self {
+        let request_id = request_id(request.headers().get_one(REQUEST_ID_HEADER));
+        let span = info_span!("request", request_id = %request_id, method = %request.method());
+        Self { request_id, span }
+    }
+
+    pub fn in_scope<T>(&self, f: impl FnOnce() -> T) -> T {
+        self.span.in_scope(f)
+    }
+}
+
+/// Keeps the client's request ID when it is a plain token, it ends up in logs and response headers.
+/// Anything else is replaced by a generated one.
+fn request_id(header: Option<&str>) -> String {
+    match header {
+        Some(request_id)
+            if !request_id.is_empty()
+                && request_id.len() <= MAX_REQUEST_ID_LENGTH
+                && request_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') =>
+        {
+            request_id.to_string()
+        }
+        _ => format!("{:016x}", rand::thread_rng().gen::<u64>()),
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequestSpan {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(request.local_cache(|| RequestSpan::new(request)).clone())
+    }
+}
+
+/// Opens a span per request and logs its outcome once the response is ready.
+pub struct RequestLogger;
+
+#[rocket::async_trait]
+impl Fairing for RequestLogger {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request logging",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        let request_span = RequestSpan::new(request);
+        request.local_cache(|| request_span);
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let request_span = request.local_cache(|| RequestSpan::new(request));
+        // Log the route template, the request path may carry a session code
+        let route = match request.route() {
+            Some(route) => route.uri.as_str(),
+            None => "unmatched",
+        };
+
+        request_span.in_scope(|| info!(route, status = response.status().code, "request completed"));
+        response.set_header(Header::new(REQUEST_ID_HEADER, request_span.request_id.clone()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_plain_request_ids() {
+        assert_eq!(request_id(Some("3f2a-Build-42")), "3f2a-Build-42");
+        let longest = "a".repeat(MAX_REQUEST_ID_LENGTH);
+        assert_eq!(request_id(Some(&longest)), longest);
+    }
+
+    #[test]
+    fn replaces_unsafe_request_ids() {
+        let too_long = "a".repeat(MAX_REQUEST_ID_LENGTH + 1);
+        for header in [None, Some(""), Some("id\nforged=1"), Some("id with spaces"), Some("\u{1b}[31m"), Some(too_long.as_str())] {
+            let generated = request_id(header);
+            assert_eq!(generated.len(), 16, "{:?}", header);
+            assert!(generated.chars().all(|c| c.is_ascii_hexdigit()));
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use std::time::Instant;
+
+use once_cell::sync::Lazy;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Status;
+use rocket::{get, Data, Request, Response};
+
+pub static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+pub struct Metrics {
+    registry: Registry,
+    pub sessions_created: IntCounter,
+    pub active_sessions: IntGauge,
+    pub joins: IntCounter,
+    pub password_failures: IntCounter,
+    pub poll_waiters: IntGauge,
+    pub active_relays: IntGauge,
+    pub relayed_bytes: IntCounter,
+    pub stored_bytes: IntGauge,
+    request_duration: HistogramVec,
+    responses: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("file_link".to_string()), None).unwrap();
+
+        let sessions_created = IntCounter::new("sessions_created_total", "Sessions created").unwrap();
+        let active_sessions = IntGauge::new("active_sessions", "Sessions currently held by the server").unwrap();
+        let joins = IntCounter::new("joins_total", "Clients that joined a session").unwrap();
+        let password_failures = IntCounter::new("password_failures_total", "Rejected session passwords").unwrap();
+        let poll_waiters = IntGauge::new("poll_waiters", "Hosts currently waiting on a long poll").unwrap();
+        let active_relays = IntGauge::new("active_relays", "Peers currently exchanging data through the relay").unwrap();
+        let relayed_bytes = IntCounter::new("relayed_bytes_total", "Bytes forwarded by the relay").unwrap();
+        let stored_bytes = IntGauge::new("stored_bytes", "Bytes of uploaded chunks kept on disk").unwrap();
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new("request_duration_seconds", "Request latency per route"),
+            &["method", "route"],
+        ).unwrap();
+        let responses = IntCounterVec::new(
+            Opts::new("responses_total", "Responses per status code"),
+            &["status"],
+        ).unwrap();
+
+        registry.register(Box::new(sessions_created.clone())).unwrap();
+        registry.register(Box::new(active_sessions.clone())).unwrap();
+        registry.register(Box::new(joins.clone())).unwrap();
+        registry.register(Box::new(password_failures.clone())).unwrap();
+        registry.register(Box::new(poll_waiters.clone())).unwrap();
+        registry.register(Box::new(active_relays.clone())).unwrap();
+        registry.register(Box::new(relayed_bytes.clone())).unwrap();
+        registry.register(Box::new(stored_bytes.clone())).unwrap();
+        registry.register(Box::new(request_duration.clone())).unwrap();
+        registry.register(Box::new(responses.clone())).unwrap();
+
+        Self {
+            registry,
+            sessions_created,
+            active_sessions,
+            joins,
+            password_failures,
+            poll_waiters,
+            active_relays,
+            relayed_bytes,
+            stored_bytes,
+            request_duration,
+            responses,
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer).unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+}
+
+/// Keeps a gauge raised for as long as it is alive, so cancelled requests still lower it.
+pub struct GaugeGuard(IntGauge);
+
+impl GaugeGuard {
+    pub fn new(gauge: &IntGauge) -> Self {
+        gauge.inc();
+        Self(gauge.clone())
+    }
+}
+
+impl Drop for GaugeGuard {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+/// Records latency per route and counts responses per status.
+pub struct MetricsFairing;
+
+#[rocket::async_trait]
+impl Fairing for MetricsFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Prometheus metrics",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        request.local_cache(Instant::now);
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let started = request.local_cache(Instant::now);
+        let route = match request.route() {
+            Some(route) => route.uri.as_str(),
+            None => "unmatched",
+        };
+
+        METRICS.request_duration
+            .with_label_values(&[request.method().as_str(), route])
+            .observe(started.elapsed().as_secs_f64());
+        METRICS.responses
+            .with_label_values(&[&response.status().code.to_string()])
+            .inc();
+    }
+}
+
+#[get("/metrics")]
+pub fn metrics() -> Result<String, Status> {
+    Ok(METRICS.encode())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::shared::HostCreate;
+    use crate::webserver::code_generator::CodeGenerator;
+    use crate::webserver::session::{Session, SessionPassword};
+    use crate::webserver::session_manager::SessionManager;
+    use crate::webserver::session_store::MemorySessionStore;
+
+    /// The value of a sample in the text exposition format.
+    fn sample(encoded: &str, name: &str) -> Option<f64> {
+        encoded
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' ')?.parse().ok())
+    }
+
+    #[test]
+    fn counts_created_sessions() {
+        let code_generator = CodeGenerator::random("unambiguous", 10);
+        let mut session_manager = SessionManager::new(
+            Duration::from_secs(3600),
+            Duration::from_secs(600),
+            3,
+            Arc::new(MemorySessionStore),
+            code_generator,
+        );
+        let before = sample(&METRICS.encode(), "file_link_sessions_created_total").unwrap_or(0.0);
+
+        let session_create = HostCreate {
+            connection_details: "offer".to_string(),
+            compression_level: 3,
+            password: String::new(),
+        };
+        session_manager.create_session(Session::from(session_create, SessionPassword::new(""), None)).unwrap();
+
+        // Other tests create sessions too, the counter only ever grows
+        let encoded = METRICS.encode();
+        assert!(sample(&encoded, "file_link_sessions_created_total").unwrap() >= before + 1.0);
+        assert!(sample(&encoded, "file_link_active_sessions").is_some());
+    }
+}
@@ -1,4 +1,27 @@
+pub mod admin;
+pub mod api;
+pub mod api_error;
+pub mod code_generator;
+#[cfg(feature = "embed-assets")]
+pub mod embedded;
+pub mod file_store;
+pub mod health;
+pub mod ice;
+pub mod logging;
+pub mod metrics;
+pub mod openapi;
+pub mod owner_token;
+pub mod rate_limiter;
+pub mod relay;
+pub mod reload;
 pub mod routing;
+pub mod security_headers;
 pub mod session;
 pub mod session_manager;
+pub mod session_store;
+pub mod signaling;
+#[cfg(not(feature = "embed-assets"))]
+pub mod static_files;
+pub mod stun;
+#[allow(clippy::module_inception)]
 pub mod webserver;
\ No newline at end of file
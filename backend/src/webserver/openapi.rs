@@ -0,0 +1,87 @@
+use rocket::get;
+use rocket::serde::json::Json;
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+use crate::shared::{
+    ApiError, ClientGetDetails, ClientGetDetailsResult, ClientJoin, ClientJoinResult, HostCreate, HostCreateResult,
+    HostPollResult, HostUpdate, HostUpdateResult, IceServer, IceServers, Readiness, SignalingMessage, StoredFileCreate,
+    StoredFileInfo, StoredShare, VersionInfo,
+};
+
+use super::{api, file_store, health, ice};
+
+/// OpenAPI document of the v2 REST API. The unversioned `/api` routes accept the same bodies
+/// encoded a second time as a JSON string and answer errors without a body.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "File Link signaling API",
+        description = "Hosts publish WebRTC offers in sessions, clients fetch and answer them. \
+            Besides the REST routes, `/api/sessions/{session_id}/signaling` and \
+            `/api/sessions/{session_id}/signaling/host` carry `SignalingMessage`s over WebSocket."
+    ),
+    paths(
+        api::create_session,
+        api::poll_session,
+        api::update_session,
+        api::close_session,
+        api::get_session_details,
+        api::join_session,
+        ice::ice_servers,
+        file_store::create_stored_file,
+        file_store::upload_stored_chunk,
+        file_store::complete_stored_file,
+        file_store::delete_stored_share,
+        file_store::get_stored_share,
+        file_store::download_stored_chunk,
+        health::healthz,
+        health::readyz,
+        health::version
+    ),
+    components(schemas(
+        HostCreate,
+        HostCreateResult,
+        HostUpdate,
+        HostUpdateResult,
+        HostPollResult,
+        ClientGetDetails,
+        ClientGetDetailsResult,
+        ClientJoin,
+        ClientJoinResult,
+        IceServer,
+        IceServers,
+        StoredFileCreate,
+        StoredFileInfo,
+        StoredShare,
+        ApiError,
+        SignalingMessage,
+        VersionInfo,
+        Readiness
+    )),
+    modifiers(&OwnerTokenScheme),
+    tags(
+        (name = "host", description = "Creating, updating and closing sessions"),
+        (name = "client", description = "Joining sessions"),
+        (name = "store", description = "Files kept on the server for recipients arriving after the host left"),
+        (name = "operations", description = "Probes and server information")
+    )
+)]
+pub struct ApiDoc;
+
+/// Declares the bearer token handed out in `HostCreateResult::owner_token`.
+struct OwnerTokenScheme;
+
+impl Modify for OwnerTokenScheme {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            let scheme = HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build();
+            components.add_security_scheme("owner_token", SecurityScheme::Http(scheme));
+        }
+    }
+}
+
+#[get("/api/openapi.json")]
+pub fn openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
@@ -0,0 +1,53 @@
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+
+const OWNER_TOKEN_LENGTH: usize = 32;
+
+/// Secret handed to the host on session creation, proving ownership on later requests.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct OwnerToken(String);
+
+impl OwnerToken {
+    pub fn generate() -> Self {
+        let token: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(OWNER_TOKEN_LENGTH)
+            .map(char::from)
+            .collect();
+        Self(token)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn matches(&self, other: &OwnerToken) -> bool {
+        self.0.as_bytes().ct_eq(other.0.as_bytes()).into()
+    }
+}
+
+/// Reads the token from an `Authorization: Bearer` header, or from the `token` query
+/// parameter for WebSocket upgrades where browsers cannot set headers.
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for OwnerToken {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let header = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if let Some(token) = header {
+            return Outcome::Success(OwnerToken(token.to_string()));
+        }
+
+        match request.query_value::<String>("token") {
+            Some(Ok(token)) => Outcome::Success(OwnerToken(token)),
+            _ => Outcome::Error((Status::Forbidden, ())),
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+
+/// Fixed window request counter per client address.
+pub struct RateLimiter {
+    limits: Mutex<(Duration, u32)>,
+    clients: Mutex<HashMap<IpAddr, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    pub fn new(window: Duration, max_requests: u32) -> Self {
+        Self {
+            limits: Mutex::new((window, max_requests)),
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Applies new limits, windows already running keep their start.
+    pub fn set_limits(&self, window: Duration, max_requests: u32) {
+        if let Ok(mut limits) = self.limits.lock() {
+            *limits = (window, max_requests);
+        }
+    }
+
+    fn limits(&self) -> Option<(Duration, u32)> {
+        self.limits.lock().ok().map(|limits| *limits)
+    }
+
+    pub fn check(&self, address: IpAddr) -> bool {
+        self.check_at(address, Instant::now())
+    }
+
+    fn check_at(&self, address: IpAddr, now: Instant) -> bool {
+        let (window, max_requests) = match self.limits() {
+            Some(limits) => limits,
+            None => return false,
+        };
+        let mut clients = match self.clients.lock() {
+            Ok(clients) => clients,
+            Err(_) => return false,
+        };
+
+        let (window_start, requests) = clients.entry(address).or_insert((now, 0));
+        if now.duration_since(*window_start) >= window {
+            *window_start = now;
+            *requests = 0;
+        }
+
+        *requests += 1;
+        *requests <= max_requests
+    }
+
+    pub fn remove_stale(&self) {
+        let window = match self.limits() {
+            Some((window, _)) => window,
+            None => return,
+        };
+        if let Ok(mut clients) = self.clients.lock() {
+            let now = Instant::now();
+            clients.retain(|_, (window_start, _)| now.duration_since(*window_start) < window);
+        }
+    }
+}
+
+/// Request guard counting the request against the client's budget, fails with 429 once it is spent.
+pub struct RateLimit;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RateLimit {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let rate_limiter = match request.rocket().state::<Arc<RateLimiter>>() {
+            Some(rate_limiter) => rate_limiter,
+            None => return Outcome::Error((Status::InternalServerError, ())),
+        };
+        let address = match request.client_ip() {
+            Some(address) => address,
+            None => return Outcome::Error((Status::BadRequest, ())),
+        };
+
+        if !rate_limiter.check(address) {
+            return Outcome::Error((Status::TooManyRequests, ()));
+        }
+        Outcome::Success(RateLimit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(60);
+    const CLIENT: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 1));
+    const OTHER_CLIENT: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 2));
+
+    #[test]
+    fn refuses_requests_beyond_limit() {
+        let rate_limiter = RateLimiter::new(WINDOW, 3);
+        let now = Instant::now();
+        let allowed: Vec<bool> = (0..5).map(|_| rate_limiter.check_at(CLIENT, now)).collect();
+        assert_eq!(allowed, [true, true, true, false, false]);
+    }
+
+    #[test]
+    fn starts_new_window_once_elapsed() {
+        let rate_limiter = RateLimiter::new(WINDOW, 1);
+        let start = Instant::now();
+        assert!(rate_limiter.check_at(CLIENT, start));
+        assert!(!rate_limiter.check_at(CLIENT, start + WINDOW - Duration::from_millis(1)));
+        assert!(rate_limiter.check_at(CLIENT, start + WINDOW));
+        assert!(!rate_limiter.check_at(CLIENT, start + WINDOW));
+    }
+
+    #[test]
+    fn counts_clients_separately() {
+        let rate_limiter = RateLimiter::new(WINDOW, 1);
+        let now = Instant::now();
+        assert!(rate_limiter.check_at(CLIENT, now));
+        assert!(!rate_limiter.check_at(CLIENT, now));
+        assert!(rate_limiter.check_at(OTHER_CLIENT, now));
+    }
+
+    #[test]
+    fn applies_new_limits_to_running_windows() {
+        let rate_limiter = RateLimiter::new(WINDOW, 1);
+        let now = Instant::now();
+        assert!(rate_limiter.check_at(CLIENT, now));
+        assert!(!rate_limiter.check_at(CLIENT, now));
+        rate_limiter.set_limits(WINDOW, 3);
+        assert!(rate_limiter.check_at(CLIENT, now));
+    }
+
+    #[test]
+    fn removes_elapsed_windows() {
+        let rate_limiter = RateLimiter::new(WINDOW, 1);
+        let now = Instant::now();
+        rate_limiter.check_at(CLIENT, now.checked_sub(WINDOW).unwrap());
+        rate_limiter.check_at(OTHER_CLIENT, now);
+        rate_limiter.remove_stale();
+
+        let clients = rate_limiter.clients.lock().unwrap();
+        assert!(!clients.contains_key(&CLIENT));
+        assert!(clients.contains_key(&OTHER_CLIENT));
+    }
+}
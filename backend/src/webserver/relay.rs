@@ -0,0 +1,302 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use rocket::futures::stream::{SplitSink, SplitStream};
+use rocket::futures::{SinkExt, StreamExt};
+use rocket::http::Status;
+use rocket::{get, State};
+use rocket_ws::result::Error;
+use rocket_ws::stream::DuplexStream;
+use rocket_ws::{Channel, Message, WebSocket};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{info, Instrument};
+
+use crate::shared::SignalingMessage;
+
+use super::logging::{redact, RequestSpan};
+use super::metrics::{GaugeGuard, METRICS};
+use super::owner_token::OwnerToken;
+use super::session_manager::SessionManager;
+use super::signaling::send_message;
+
+/// How long the first side of a relay waits for the other one.
+const PAIR_TIMEOUT: Duration = Duration::from_secs(30);
+/// Frames buffered per direction, a slow receiver holds the sender back beyond that.
+const FRAME_BUFFER: usize = 16;
+
+#[derive(Clone, Copy, PartialEq)]
+enum RelaySide {
+    Host,
+    Client,
+}
+
+/// One side's view of a relay pipe: frames for the other side go into `sender`, its frames arrive on `receiver`.
+struct RelayEnd {
+    sender: mpsc::Sender<Message>,
+    receiver: mpsc::Receiver<Message>,
+}
+
+/// The end left behind by `side`, which waits on `paired` for the other side to take it.
+struct WaitingEnd {
+    side: RelaySide,
+    end: RelayEnd,
+    paired: oneshot::Sender<()>,
+}
+
+/// Pairs the host and the client of an offer whose peer-to-peer connection failed, so they can
+/// exchange their data channel traffic through the server instead.
+#[derive(Default)]
+pub struct RelayHub {
+    waiting: Mutex<HashMap<(String, u32), WaitingEnd>>,
+}
+
+impl RelayHub {
+    /// The first side in leaves the other end behind and gets notified once it is picked up.
+    fn join(&self, key: &(String, u32), side: RelaySide) -> Result<(RelayEnd, Option<oneshot::Receiver<()>>), Status> {
+        let mut waiting = self.waiting.lock().map_err(|_| Status::InternalServerError)?;
+        match waiting.remove(key) {
+            Some(other) if other.side != side => {
+                let _ = other.paired.send(());
+                Ok((other.end, None))
+            }
+            Some(other) => {
+                waiting.insert(key.clone(), other);
+                Err(Status::Conflict)
+            }
+            None => {
+                let (own_sender, other_receiver) = mpsc::channel(FRAME_BUFFER);
+                let (other_sender, own_receiver) = mpsc::channel(FRAME_BUFFER);
+                let (paired, paired_receiver) = oneshot::channel();
+                let end = RelayEnd { sender: other_sender, receiver: other_receiver };
+                waiting.insert(key.clone(), WaitingEnd { side, end, paired });
+                Ok((RelayEnd { sender: own_sender, receiver: own_receiver }, Some(paired_receiver)))
+            }
+        }
+    }
+
+    /// Withdraws an end nobody picked up.
+    fn leave(&self, key: &(String, u32), side: RelaySide) {
+        if let Ok(mut waiting) = self.waiting.lock() {
+            if waiting.get(key).is_some_and(|waiting_end| waiting_end.side == side) {
+                waiting.remove(key);
+            }
+        }
+    }
+}
+
+#[get("/api/sessions/<session_id>/relay/<offer_id>/host")]
+pub fn host_relay(
+    owner_token: OwnerToken,
+    request_span: RequestSpan,
+    ws: WebSocket,
+    session_manager: &State<Arc<RwLock<SessionManager>>>,
+    relay_hub: &State<Arc<RelayHub>>,
+    session_id: String,
+    offer_id: u32,
+) -> Result<Channel<'static>, Status> {
+    {
+        let session_manager = session_manager.read();
+        if session_manager.is_err() {
+            return Err(Status::InternalServerError);
+        }
+        let session_manager = session_manager.unwrap();
+        if !session_manager.is_session_owner(&owner_token, &session_id) {
+            return Err(Status::Forbidden);
+        }
+        if !session_manager.is_offer_claimed(&session_id, offer_id) {
+            return Err(Status::NotFound);
+        }
+    }
+
+    let relay_hub = relay_hub.inner().clone();
+    Ok(ws.channel(move |mut stream| Box::pin(async move {
+        relay(&mut stream, &relay_hub, (session_id, offer_id), RelaySide::Host).await
+    }.instrument(request_span.span))))
+}
+
+/// Only the client that claimed the offer may use the relay, it proves so with the relay token it got
+/// on joining. Like the owner token, it goes in the `token` query parameter.
+#[get("/api/sessions/<session_id>/relay/<offer_id>")]
+pub fn client_relay(
+    relay_token: OwnerToken,
+    request_span: RequestSpan,
+    ws: WebSocket,
+    session_manager: &State<Arc<RwLock<SessionManager>>>,
+    relay_hub: &State<Arc<RelayHub>>,
+    session_id: String,
+    offer_id: u32,
+) -> Result<Channel<'static>, Status> {
+    {
+        let session_manager = session_manager.read();
+        if session_manager.is_err() {
+            return Err(Status::InternalServerError);
+        }
+        let session_manager = session_manager.unwrap();
+        if !session_manager.is_relay_token(&session_id, offer_id, &relay_token) {
+            return Err(Status::Forbidden);
+        }
+    }
+
+    let relay_hub = relay_hub.inner().clone();
+    Ok(ws.channel(move |mut stream| Box::pin(async move {
+        relay(&mut stream, &relay_hub, (session_id, offer_id), RelaySide::Client).await
+    }.instrument(request_span.span))))
+}
+
+async fn relay(stream: &mut DuplexStream, relay_hub: &RelayHub, key: (String, u32), side: RelaySide) -> Result<(), Error> {
+    let (end, paired) = match relay_hub.join(&key, side) {
+        Ok(joined) => joined,
+        Err(status) => return send_message(stream, &SignalingMessage::Error { status: status.code }).await,
+    };
+
+    if let Some(paired) = paired {
+        let outcome = tokio::select! {
+            outcome = wait_for_peer(paired, PAIR_TIMEOUT) => outcome,
+            // Anything but a close frame is unexpected before the relay is up
+            _ = stream.next() => Err(Status::BadRequest),
+        };
+        if let Err(status) = outcome {
+            relay_hub.leave(&key, side);
+            return send_message(stream, &SignalingMessage::Error { status: status.code }).await;
+        }
+    }
+
+    send_message(stream, &SignalingMessage::Relay).await?;
+    let _active_relay = GaugeGuard::new(&METRICS.active_relays);
+    if side == RelaySide::Host {
+        info!(code = %redact(&key.0), offer_id = key.1, "relay established");
+    }
+
+    // Each direction runs on its own, so a stalled receiver never blocks the other way
+    let (sink, source) = stream.split();
+    let RelayEnd { sender, receiver } = end;
+    tokio::select! {
+        _ = forward_to_peer(source, sender) => {}
+        _ = forward_from_peer(sink, receiver) => {}
+    }
+    Ok(())
+}
+
+/// Resolves once the other side picked up the end left behind, or fails after `timeout`.
+async fn wait_for_peer(paired: oneshot::Receiver<()>, timeout: Duration) -> Result<(), Status> {
+    match tokio::time::timeout(timeout, paired).await {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(_)) => Err(Status::InternalServerError),
+        Err(_) => Err(Status::RequestTimeout),
+    }
+}
+
+async fn forward_to_peer(mut source: SplitStream<&mut DuplexStream>, sender: mpsc::Sender<Message>) {
+    while let Some(Ok(message)) = source.next().await {
+        match message {
+            Message::Text(_) | Message::Binary(_) => {
+                METRICS.relayed_bytes.inc_by(message.len() as u64);
+                if sender.send(message).await.is_err() {
+                    break;
+                }
+            }
+            Message::Close(_) => break,
+            _ => {}
+        }
+    }
+}
+
+async fn forward_from_peer(mut sink: SplitSink<&mut DuplexStream, Message>, mut receiver: mpsc::Receiver<Message>) {
+    while let Some(message) = receiver.recv().await {
+        if sink.send(message).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::HostCreate;
+    use crate::webserver::code_generator::CodeGenerator;
+    use crate::webserver::session::{Session, SessionPassword};
+    use crate::webserver::session_store::MemorySessionStore;
+
+    fn key(code: &str, offer_id: u32) -> (String, u32) {
+        (code.to_string(), offer_id)
+    }
+
+    #[rocket::async_test]
+    async fn pairs_host_and_client_of_an_offer() {
+        let relay_hub = RelayHub::default();
+        let (mut host, paired) = relay_hub.join(&key("ABC", 0), RelaySide::Host).unwrap();
+        let (mut client, none) = relay_hub.join(&key("ABC", 0), RelaySide::Client).unwrap();
+        assert!(none.is_none());
+        assert_eq!(wait_for_peer(paired.unwrap(), PAIR_TIMEOUT).await, Ok(()));
+
+        host.sender.send(Message::text("to client")).await.unwrap();
+        client.sender.send(Message::text("to host")).await.unwrap();
+        assert_eq!(client.receiver.recv().await, Some(Message::text("to client")));
+        assert_eq!(host.receiver.recv().await, Some(Message::text("to host")));
+    }
+
+    #[rocket::async_test]
+    async fn keeps_offers_apart() {
+        let relay_hub = RelayHub::default();
+        let (_host, paired) = relay_hub.join(&key("ABC", 0), RelaySide::Host).unwrap();
+        let (_other_offer, other_paired) = relay_hub.join(&key("ABC", 1), RelaySide::Client).unwrap();
+        let (_other_code, other_code_paired) = relay_hub.join(&key("DEF", 0), RelaySide::Client).unwrap();
+        assert!(paired.is_some() && other_paired.is_some() && other_code_paired.is_some());
+
+        // A second host for the same offer does not take over the waiting one
+        assert_eq!(relay_hub.join(&key("ABC", 0), RelaySide::Host).err(), Some(Status::Conflict));
+        assert!(relay_hub.join(&key("ABC", 0), RelaySide::Client).unwrap().1.is_none());
+    }
+
+    #[rocket::async_test]
+    async fn times_out_without_peer() {
+        let relay_hub = RelayHub::default();
+        let (_host, paired) = relay_hub.join(&key("ABC", 0), RelaySide::Host).unwrap();
+        assert_eq!(wait_for_peer(paired.unwrap(), Duration::from_millis(10)).await, Err(Status::RequestTimeout));
+
+        // Once withdrawn, the next side in waits itself instead of pairing with the stale end
+        relay_hub.leave(&key("ABC", 0), RelaySide::Host);
+        assert!(relay_hub.join(&key("ABC", 0), RelaySide::Client).unwrap().1.is_some());
+    }
+
+    #[rocket::async_test]
+    async fn holds_back_sender_beyond_buffer() {
+        let relay_hub = RelayHub::default();
+        let (host, _) = relay_hub.join(&key("ABC", 0), RelaySide::Host).unwrap();
+        let (mut client, _) = relay_hub.join(&key("ABC", 0), RelaySide::Client).unwrap();
+
+        for _ in 0..FRAME_BUFFER {
+            host.sender.try_send(Message::binary(vec![0])).unwrap();
+        }
+        assert!(host.sender.try_send(Message::binary(vec![0])).is_err());
+        client.receiver.recv().await.unwrap();
+        host.sender.try_send(Message::binary(vec![0])).unwrap();
+    }
+
+    #[test]
+    fn accepts_only_the_relay_token_of_the_offer() {
+        let code_generator = CodeGenerator::random("unambiguous", 10);
+        let mut session_manager = SessionManager::new(
+            Duration::from_secs(3600),
+            Duration::from_secs(600),
+            3,
+            Arc::new(MemorySessionStore),
+            code_generator,
+        );
+        let session_create = HostCreate {
+            connection_details: "offer".to_string(),
+            compression_level: 3,
+            password: String::new(),
+        };
+        let session = Session::from(session_create, SessionPassword::new(""), None);
+        let code = session_manager.create_session(session).unwrap().code;
+        let (_, join_result) = session_manager.join_session(&code, 0, "answer".to_string()).unwrap();
+        let relay_token: OwnerToken = serde_json::from_value(serde_json::json!(join_result.relay_token)).unwrap();
+
+        assert!(session_manager.is_relay_token(&code, 0, &relay_token));
+        assert!(!session_manager.is_relay_token(&code, 0, &OwnerToken::generate()));
+        assert!(!session_manager.is_relay_token(&code, 1, &relay_token));
+        assert!(!session_manager.is_relay_token("unknown", 0, &relay_token));
+    }
+}
@@ -0,0 +1,316 @@
+use std::fs;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use rocket::config::TlsConfig;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::{error, info, warn};
+
+use super::logging;
+use super::metrics::METRICS;
+use super::rate_limiter::RateLimiter;
+use super::session_manager::SessionManager;
+use super::webserver::webserver::create_tls_config;
+use crate::config_check::validate;
+use crate::shared::{config_source, load_config, BackendConfig, Config, BACKEND_CONFIG};
+
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Modification times of the configuration file and of the TLS files it points to.
+#[derive(PartialEq)]
+struct Fingerprint {
+    config: Option<SystemTime>,
+    tls: Vec<(String, Option<SystemTime>)>,
+}
+
+impl Fingerprint {
+    fn take(tls_paths: &[String]) -> Self {
+        let tls = tls_paths
+            .iter()
+            .map(|path| (path.clone(), modified(path)))
+            .collect();
+        Fingerprint {
+            config: modified(&config_source().path),
+            tls,
+        }
+    }
+}
+
+/// Watches the configuration file and SIGHUP and applies what can change while running.
+/// New TLS settings are sent to `tls_changes`, the server relaunches its listener with them.
+/// That cuts open WebSockets, so it only happens when the content of a TLS file changed.
+pub fn spawn_config_watcher(
+    session_manager: Arc<RwLock<SessionManager>>,
+    rate_limiter: Arc<RateLimiter>,
+    tls_changes: UnboundedSender<TlsConfig>,
+) {
+    tokio::spawn(async move {
+        let mut tls_paths = tls_files(&BACKEND_CONFIG);
+        let mut applied = Fingerprint::take(&tls_paths);
+        let mut applied_tls = tls_contents(&tls_paths);
+        let mut interval = tokio::time::interval(WATCH_INTERVAL);
+        let mut hangup = hangup_signal();
+
+        loop {
+            let hung_up = tokio::select! {
+                _ = interval.tick() => false,
+                _ = hangup.recv() => true,
+            };
+
+            // Compared against the last attempt, so a broken file is reported once and not every tick
+            let fingerprint = Fingerprint::take(&tls_paths);
+            if fingerprint == applied && !hung_up {
+                continue;
+            }
+
+            let backend_config = match load_config::<Config>(config_source()) {
+                Ok(config) => config.backend_config,
+                Err(error) => {
+                    error!(%error, "configuration reload failed, keeping the current configuration");
+                    applied = fingerprint;
+                    continue;
+                }
+            };
+            tls_paths = tls_files(&backend_config);
+            applied = Fingerprint::take(&tls_paths);
+
+            let problems = validate(&backend_config);
+            if !problems.is_empty() {
+                for problem in &problems {
+                    error!(problem = %problem, "invalid configuration");
+                }
+                error!("configuration reload failed, keeping the current configuration");
+                continue;
+            }
+
+            apply(&backend_config, &session_manager, &rate_limiter);
+
+            let tls = tls_contents(&tls_paths);
+            if BACKEND_CONFIG.web_tls_enabled && backend_config.web_tls_enabled && tls != applied_tls {
+                if let Some(tls_config) = create_tls_config(&backend_config) {
+                    // Rocket cannot swap the certificate of a running listener, so this behaves like a restart
+                    warn!(active_relays = METRICS.active_relays.get(), "TLS files changed, relaunching the listener, open signaling and relay WebSockets are cut");
+                    let _ = tls_changes.send(tls_config);
+                }
+            }
+            applied_tls = tls;
+
+            for key in restart_required(&BACKEND_CONFIG, &backend_config) {
+                warn!(key, "setting changed, it takes effect after a restart");
+            }
+            info!("configuration reloaded");
+        }
+    });
+}
+
+fn apply(backend_config: &BackendConfig, session_manager: &Arc<RwLock<SessionManager>>, rate_limiter: &RateLimiter) {
+    if let Err(error) = logging::set_level(&backend_config.log_level) {
+        error!(%error, "failed to apply log_level");
+    }
+
+    let window = Duration::from_secs(backend_config.rate_limit_window_secs);
+    rate_limiter.set_limits(window, backend_config.rate_limit_max_requests);
+
+    if let Ok(mut session_manager) = session_manager.write() {
+        session_manager.set_limits(
+            Duration::from_secs(backend_config.session_ttl_secs),
+            Duration::from_secs(backend_config.session_idle_timeout_secs),
+            backend_config.session_max_password_attempts,
+        );
+    }
+}
+
+/// Settings that differ from the ones the server was started with and cannot be applied while running.
+fn restart_required(running: &BackendConfig, backend_config: &BackendConfig) -> Vec<&'static str> {
+    let mut keys = Vec::new();
+
+    macro_rules! compare {
+        ($($key:ident),*) => {
+            $(if backend_config.$key != running.$key {
+                keys.push(stringify!($key));
+            })*
+        };
+    }
+    compare!(
+        web_bind_addr,
+        web_port,
+        web_serve_page,
+        web_serve_api,
+        web_serve_metrics,
+        web_serve_admin,
+        web_tls_enabled,
+        web_root,
+        web_ip_header,
+        session_poll_timeout_secs,
+        session_gc_interval_secs,
+        session_code_scheme,
+        session_code_alphabet,
+        session_code_length,
+        session_code_words,
+        session_store,
+        session_store_path,
+        ice_stun_urls,
+        ice_turn_urls,
+        ice_turn_secret,
+        ice_turn_credential_ttl_secs,
+        stun_enabled,
+        stun_port,
+        relay_enabled,
+        relay_ice_timeout_secs,
+        store_enabled,
+        store_path,
+        store_quota_bytes,
+        store_ttl_secs,
+        log_format,
+        admin_token,
+        admin_client_cert
+    );
+    keys
+}
+
+fn tls_files(backend_config: &BackendConfig) -> Vec<String> {
+    [&backend_config.web_tls_certs, &backend_config.web_tls_key, &backend_config.web_tls_ca_cert]
+        .into_iter()
+        .filter(|path| !path.is_empty())
+        .cloned()
+        .collect()
+}
+
+/// Compared before relaunching, a file touched or rewritten with the same certificate keeps
+/// the listener.
+fn tls_contents(tls_paths: &[String]) -> Vec<Option<Vec<u8>>> {
+    tls_paths.iter().map(|path| fs::read(path).ok()).collect()
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+#[cfg(unix)]
+struct Hangup(Option<tokio::signal::unix::Signal>);
+
+#[cfg(unix)]
+fn hangup_signal() -> Hangup {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let signal = signal(SignalKind::hangup())
+        .map_err(|error| warn!(%error, "cannot listen for SIGHUP, only file changes trigger a reload"))
+        .ok();
+    Hangup(signal)
+}
+
+#[cfg(unix)]
+impl Hangup {
+    async fn recv(&mut self) {
+        match &mut self.0 {
+            Some(signal) => {
+                signal.recv().await;
+                info!("received SIGHUP");
+            }
+            None => std::future::pending().await,
+        }
+    }
+}
+
+#[cfg(not(unix))]
+struct Hangup;
+
+#[cfg(not(unix))]
+fn hangup_signal() -> Hangup {
+    Hangup
+}
+
+#[cfg(not(unix))]
+impl Hangup {
+    async fn recv(&mut self) {
+        std::future::pending().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::path::PathBuf;
+    use std::{env, process};
+
+    use crate::shared::ConfigSource;
+    use crate::webserver::code_generator::CodeGenerator;
+    use crate::webserver::session_store::MemorySessionStore;
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("file-link-reload-{}-{}", name, process::id()))
+    }
+
+    fn backend_config(name: &str, content: &str) -> BackendConfig {
+        let path = temp_path(name);
+        fs::write(&path, content).unwrap();
+        let source = ConfigSource { path: path.to_string_lossy().to_string(), overrides: Vec::new() };
+        let config = load_config::<Config>(&source);
+        let _ = fs::remove_file(&path);
+        config.unwrap().backend_config
+    }
+
+    #[test]
+    fn unchanged_config_needs_no_restart() {
+        let running = backend_config("running", "");
+        assert!(restart_required(&running, &backend_config("same", "")).is_empty());
+    }
+
+    #[test]
+    fn lists_only_settings_needing_a_restart() {
+        let running = backend_config("running-diff", "");
+        let changed = backend_config(
+            "changed",
+            "[backend_config]\nweb_port = 9000\nstore_quota_bytes = 1\nlog_level = \"debug\"\nsession_ttl_secs = 1\nrate_limit_max_requests = 1\n",
+        );
+        assert_eq!(restart_required(&running, &changed), vec!["web_port", "store_quota_bytes"]);
+    }
+
+    #[test]
+    fn applies_limits_while_running() {
+        let running = backend_config("running-apply", "");
+        let code_generator = CodeGenerator::random("unambiguous", 10);
+        let session_manager = Arc::new(RwLock::new(SessionManager::new(
+            Duration::from_secs(running.session_ttl_secs),
+            Duration::from_secs(running.session_idle_timeout_secs),
+            running.session_max_password_attempts,
+            Arc::new(MemorySessionStore),
+            code_generator,
+        )));
+        let rate_limiter = RateLimiter::new(Duration::from_secs(running.rate_limit_window_secs), 100);
+
+        let changed = backend_config(
+            "apply",
+            "[backend_config]\nsession_max_password_attempts = 7\nrate_limit_max_requests = 1\n",
+        );
+        apply(&changed, &session_manager, &rate_limiter);
+
+        assert_eq!(session_manager.read().unwrap().max_password_attempts(), 7);
+        let address = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        assert!(rate_limiter.check(address));
+        assert!(!rate_limiter.check(address));
+    }
+
+    #[test]
+    fn fingerprint_follows_tls_files() {
+        let path = temp_path("cert.pem");
+        fs::write(&path, "certificate").unwrap();
+        let tls_paths = vec![path.to_string_lossy().to_string()];
+        let before = Fingerprint::take(&tls_paths);
+        assert!(Fingerprint::take(&tls_paths) == before);
+
+        let earlier = SystemTime::now() - Duration::from_secs(60);
+        fs::File::options().write(true).open(&path).unwrap().set_modified(earlier).unwrap();
+        assert!(Fingerprint::take(&tls_paths) != before);
+
+        // Touched without new content, the listener is kept
+        let contents = tls_contents(&tls_paths);
+        fs::write(&path, "certificate").unwrap();
+        assert_eq!(tls_contents(&tls_paths), contents);
+        fs::write(&path, "renewed").unwrap();
+        assert_ne!(tls_contents(&tls_paths), contents);
+
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,63 @@
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::{Request, Response};
+
+use crate::frontend_constants::{HOST_ADDRESS, PORT};
+
+/// Redoc loads its bundle from a CDN and starts it with an inline script, the policy is left off there.
+const DOCS_PATH: &str = "/api/docs";
+
+/// Hardens every response on top of Rocket's default shield. The policy lets the page compile
+/// its wasm module, keep the inline styles Yew sets and the Bootstrap stylesheet, and reach the
+/// API at the address the page was built with, over HTTP and WebSockets. No referrer is sent, the
+/// path of a receive page is its share code.
+pub struct SecurityHeaders {
+    content_security_policy: String,
+    tls_enabled: bool,
+}
+
+impl SecurityHeaders {
+    pub fn new(tls_enabled: bool) -> Self {
+        // Built like the page's `get_host_address` and `get_signaling_address`
+        let api_address = format!("{}:{}", HOST_ADDRESS, PORT);
+        let api_socket_address = format!("{}:{}", HOST_ADDRESS.replacen("http", "ws", 1), PORT);
+        let content_security_policy = format!(
+            "default-src 'self'; \
+             script-src 'self' 'wasm-unsafe-eval'; \
+             style-src 'self' 'unsafe-inline' https://stackpath.bootstrapcdn.com; \
+             img-src 'self' data:; \
+             connect-src 'self' {} {}; \
+             object-src 'none'; \
+             base-uri 'none'; \
+             form-action 'self'; \
+             frame-ancestors 'self'",
+            api_address, api_socket_address
+        );
+
+        Self {
+            content_security_policy,
+            tls_enabled,
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for SecurityHeaders {
+    fn info(&self) -> Info {
+        Info {
+            name: "Security headers",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if !request.uri().path().starts_with(DOCS_PATH) {
+            response.set_header(Header::new("Content-Security-Policy", self.content_security_policy.clone()));
+        }
+        response.set_header(Header::new("Referrer-Policy", "no-referrer"));
+        response.set_header(Header::new("X-Content-Type-Options", "nosniff"));
+        if self.tls_enabled {
+            response.set_header(Header::new("Strict-Transport-Security", "max-age=31536000"));
+        }
+    }
+}
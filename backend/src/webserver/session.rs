@@ -1,32 +1,393 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{collections::{HashMap, VecDeque}, net::IpAddr, sync::{atomic::{AtomicU32, Ordering}, Arc}, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
 
+use argon2::password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
 use async_condvar_fair::Condvar;
-use tokio::sync::Mutex;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{watch, Mutex};
 
 use crate::shared::HostCreate;
 
-pub type CondvarDetails = (Condvar, Mutex<Option<String>>);
+use super::owner_token::OwnerToken;
+use super::session_store::SessionRecord;
+
+#[derive(Clone, Debug)]
+pub enum SessionSignal {
+    Answer(u32, String),
+    Candidate(u32, String),
+    Leave(u32),
+    Expired,
+    Closed,
+}
+
+impl SessionSignal {
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, SessionSignal::Expired | SessionSignal::Closed)
+    }
+}
+
+/// Candidates kept or queued per offer from either side, the other side may not be reading.
+const MAX_OFFER_CANDIDATES: usize = 32;
+
+pub type CondvarDetails = (Condvar, Mutex<VecDeque<SessionSignal>>);
+
+pub async fn push_signal(condvar_details: &CondvarDetails, signal: SessionSignal) {
+    let mut signals = condvar_details.1.lock().await;
+    if signal.is_terminal() {
+        signals.clear();
+    }
+    signals.push_back(signal);
+    condvar_details.0.notify_all();
+}
+
+/// Waits for the next signal addressed to the host. Terminal signals stay queued so every
+/// waiter observes them.
+pub async fn pop_signal(condvar_details: &CondvarDetails) -> SessionSignal {
+    let mut signals = condvar_details.1.lock().await;
+    while signals.is_empty() {
+        signals = condvar_details.0.wait((signals, &condvar_details.1)).await;
+    }
+    let signal = signals.pop_front().unwrap();
+    if signal.is_terminal() {
+        signals.push_front(signal.clone());
+    }
+    signal
+}
+
+/// Verified whenever there is no real hash to check, so a miss takes as long as a wrong password.
+static DECOY_PASSWORD: Lazy<SessionPassword> = Lazy::new(|| SessionPassword::new("decoy"));
+
+/// Argon2 hash of a session password, the plaintext is never kept around.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SessionPassword(Option<String>);
+
+impl SessionPassword {
+    pub fn new(password: &str) -> Self {
+        if password.is_empty() {
+            return Self(None);
+        }
+
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .expect("Failed to hash session password");
+        Self(Some(hash.to_string()))
+    }
+
+    /// Hashes on the blocking pool, Argon2 takes tens of milliseconds and about 19 MiB per call.
+    pub async fn hash(password: String) -> Self {
+        tokio::task::spawn_blocking(move || Self::new(&password))
+            .await
+            .expect("Failed to hash session password")
+    }
+
+    /// [`Self::verify`] on the blocking pool.
+    pub async fn matches(self, password: String) -> bool {
+        tokio::task::spawn_blocking(move || self.verify(&password))
+            .await
+            .unwrap_or(false)
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.0.is_some()
+    }
+
+    pub fn decoy() -> &'static SessionPassword {
+        &DECOY_PASSWORD
+    }
+
+    /// Checks a password against the hash. The comparison runs in constant time.
+    pub fn verify(&self, password: &str) -> bool {
+        let hash = match &self.0 {
+            Some(hash) => hash,
+            None if password.is_empty() => return true,
+            None => {
+                DECOY_PASSWORD.verify(password);
+                return false;
+            }
+        };
+
+        match PasswordHash::new(hash) {
+            Ok(hash) => Argon2::default().verify_password(password.as_bytes(), &hash).is_ok(),
+            Err(_) => false,
+        }
+    }
+}
 
 pub struct Session {
     pub compression_level: u8,
-    pub password: String,
+    pub password: SessionPassword,
     pub connection_details_host: String,
-    pub address: SocketAddr,
+    pub offer_id: u32,
+    pub offer_claimed: bool,
+    pub owner_token: OwnerToken,
+    pub owner_ip: Option<IpAddr>,
     pub condvar_details: Arc<CondvarDetails>,
+    pub offer_watch: watch::Sender<u32>,
+    pub offer_candidates: HashMap<u32, Vec<String>>,
+    pub client_candidates: HashMap<u32, usize>,
+    pub pending_answer: Option<(u32, String)>,
+    /// Handed to the client of each claimed offer, so nobody else can take its side of the relay.
+    pub relay_tokens: HashMap<u32, OwnerToken>,
+    pub created_at: Instant,
+    last_activity: std::sync::Mutex<Instant>,
+    failed_attempts: AtomicU32,
 }
 
 impl Session {
-    pub fn from(session_create: HostCreate, address: SocketAddr) -> Self {
+    /// The password is hashed beforehand with [`SessionPassword::hash`].
+    pub fn from(session_create: HostCreate, password: SessionPassword, owner_ip: Option<IpAddr>) -> Self {
+        let now = Instant::now();
         Self {
             compression_level: session_create.compression_level,
-            password: session_create.password,
+            password,
             connection_details_host: session_create.connection_details,
-            address,
-            condvar_details: Arc::new((Condvar::new(), Mutex::new(None))),
+            offer_id: 0,
+            offer_claimed: false,
+            owner_token: OwnerToken::generate(),
+            owner_ip,
+            condvar_details: Arc::new((Condvar::new(), Mutex::new(VecDeque::new()))),
+            offer_watch: watch::Sender::new(0),
+            offer_candidates: HashMap::new(),
+            client_candidates: HashMap::new(),
+            pending_answer: None,
+            relay_tokens: HashMap::new(),
+            created_at: now,
+            last_activity: std::sync::Mutex::new(now),
+            failed_attempts: AtomicU32::new(0),
+        }
+    }
+
+    /// Rebuilds a stored session. An answer the host had not picked up yet is queued again,
+    /// and the idle timeout starts over.
+    pub fn from_record(record: SessionRecord) -> Self {
+        let now = Instant::now();
+        let age = Duration::from_secs(unix_now().saturating_sub(record.created_at_unix_secs));
+        let signals: VecDeque<SessionSignal> = record.pending_answer
+            .iter()
+            .map(|(offer_id, answer)| SessionSignal::Answer(*offer_id, answer.clone()))
+            .collect();
+
+        Self {
+            compression_level: record.compression_level,
+            password: record.password,
+            connection_details_host: record.connection_details_host,
+            offer_id: record.offer_id,
+            offer_claimed: record.offer_claimed,
+            owner_token: record.owner_token,
+            owner_ip: record.owner_ip,
+            condvar_details: Arc::new((Condvar::new(), Mutex::new(signals))),
+            offer_watch: watch::Sender::new(record.offer_id),
+            offer_candidates: HashMap::new(),
+            client_candidates: HashMap::new(),
+            pending_answer: record.pending_answer,
+            relay_tokens: record.relay_tokens,
+            created_at: now.checked_sub(age).unwrap_or(now),
+            last_activity: std::sync::Mutex::new(now),
+            failed_attempts: AtomicU32::new(record.failed_attempts),
+        }
+    }
+
+    pub fn to_record(&self) -> SessionRecord {
+        let age = Instant::now().duration_since(self.created_at).as_secs();
+        SessionRecord {
+            compression_level: self.compression_level,
+            password: self.password.clone(),
+            connection_details_host: self.connection_details_host.clone(),
+            offer_id: self.offer_id,
+            offer_claimed: self.offer_claimed,
+            owner_token: self.owner_token.clone(),
+            owner_ip: self.owner_ip,
+            created_at_unix_secs: unix_now().saturating_sub(age),
+            failed_attempts: self.failed_attempts.load(Ordering::Relaxed),
+            pending_answer: self.pending_answer.clone(),
+            relay_tokens: self.relay_tokens.clone(),
         }
     }
 
     pub fn has_password(&self) -> bool {
-        !self.password.is_empty()
+        self.password.is_set()
+    }
+
+    /// Replaces the host's offer once the previous one has been answered, so the next
+    /// client can join the same session.
+    pub fn update_offer(&mut self, connection_details: String) -> u32 {
+        self.connection_details_host = connection_details;
+        self.offer_id += 1;
+        self.offer_claimed = false;
+        self.pending_answer = None;
+        self.offer_watch.send_replace(self.offer_id);
+        self.offer_id
+    }
+
+    /// Offers are claimed in order, so every offer before the current one went to a client.
+    pub fn is_offer_claimed(&self, offer_id: u32) -> bool {
+        offer_id < self.offer_id || (offer_id == self.offer_id && self.offer_claimed)
+    }
+
+    /// Returns the relay token of the claimed offer, which only the claiming client gets.
+    pub fn claim_offer(&mut self, offer_id: u32, answer: String) -> Option<OwnerToken> {
+        if self.offer_claimed || self.offer_id != offer_id {
+            return None;
+        }
+        self.offer_claimed = true;
+        self.pending_answer = Some((offer_id, answer));
+        let relay_token = OwnerToken::generate();
+        self.relay_tokens.insert(offer_id, relay_token.clone());
+        Some(relay_token)
+    }
+
+    pub fn is_relay_token(&self, offer_id: u32, relay_token: &OwnerToken) -> bool {
+        match self.relay_tokens.get(&offer_id) {
+            Some(expected) => expected.matches(relay_token),
+            None => false,
+        }
+    }
+
+    /// The host picked up the answer, so a restart must not hand it out again.
+    pub fn answer_delivered(&mut self, offer_id: u32) {
+        if matches!(&self.pending_answer, Some((pending_offer_id, _)) if *pending_offer_id == offer_id) {
+            self.pending_answer = None;
+        }
+    }
+
+    /// Stores an ICE candidate the host gathered for one of its offers and wakes the
+    /// clients waiting on the offer watch so they can forward it.
+    pub fn add_offer_candidate(&mut self, offer_id: u32, candidate: String) -> bool {
+        if offer_id > self.offer_id {
+            return false;
+        }
+
+        let candidates = self.offer_candidates.entry(offer_id).or_default();
+        if candidates.len() >= MAX_OFFER_CANDIDATES {
+            return false;
+        }
+        candidates.push(candidate);
+        self.offer_watch.send_modify(|_| {});
+        true
+    }
+
+    /// Counts a candidate the client of an offer sends the host, refusing it once the offer
+    /// has its share queued.
+    pub fn add_client_candidate(&mut self, offer_id: u32) -> bool {
+        let count = self.client_candidates.entry(offer_id).or_default();
+        if *count >= MAX_OFFER_CANDIDATES {
+            return false;
+        }
+        *count += 1;
+        true
+    }
+
+    pub fn record_failed_attempt(&self) {
+        self.failed_attempts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A session refuses every password once its attempt budget is spent.
+    pub fn is_locked(&self, max_attempts: u32) -> bool {
+        self.failed_attempts.load(Ordering::Relaxed) >= max_attempts
+    }
+
+    pub fn touch(&self) {
+        if let Ok(mut last_activity) = self.last_activity.lock() {
+            *last_activity = Instant::now();
+        }
+    }
+
+    pub fn idle_time(&self, now: Instant) -> Option<Duration> {
+        match self.last_activity.lock() {
+            Ok(last_activity) => Some(now.duration_since(*last_activity)),
+            Err(_) => None,
+        }
+    }
+
+    pub fn is_expired(&self, now: Instant, ttl: Duration, idle_timeout: Duration) -> bool {
+        if now.duration_since(self.created_at) >= ttl {
+            return true;
+        }
+
+        match self.idle_time(now) {
+            Some(idle_time) => idle_time >= idle_timeout,
+            None => true,
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_password_is_not_hashed() {
+        let password = SessionPassword::new("");
+        assert!(!password.is_set());
+        assert!(password.verify(""));
+        assert!(!password.verify("secret"));
+    }
+
+    #[test]
+    fn verifies_against_argon2_hash() {
+        let password = SessionPassword::new("secret");
+        let hash = password.0.clone().unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(!hash.contains("secret"));
+
+        assert!(password.verify("secret"));
+        assert!(!password.verify("Secret"));
+        assert!(!password.verify(""));
+    }
+
+    #[test]
+    fn salts_every_hash() {
+        let first = SessionPassword::new("secret");
+        let second = SessionPassword::new("secret");
+        assert_ne!(first.0, second.0);
+        assert!(second.verify("secret"));
+    }
+
+    #[test]
+    fn stored_hash_still_verifies() {
+        let password = SessionPassword::new("secret");
+        let stored = serde_json::to_string(&password).unwrap();
+        let restored: SessionPassword = serde_json::from_str(&stored).unwrap();
+        assert!(restored.verify("secret"));
+        assert!(!restored.verify("wrong"));
+    }
+
+    #[test]
+    fn rejects_malformed_hash() {
+        let password = SessionPassword(Some("not a hash".to_string()));
+        assert!(!password.verify("not a hash"));
+    }
+
+    #[test]
+    fn delivered_answer_is_not_restored() {
+        let session_create = HostCreate {
+            connection_details: "offer".to_string(),
+            compression_level: 3,
+            password: String::new(),
+        };
+        let mut session = Session::from(session_create, SessionPassword::new(""), None);
+        assert!(session.claim_offer(0, "answer".to_string()).is_some());
+        assert!(Session::from_record(session.to_record()).pending_answer.is_some());
+
+        session.answer_delivered(1);
+        assert!(session.pending_answer.is_some());
+        session.answer_delivered(0);
+        assert!(Session::from_record(session.to_record()).pending_answer.is_none());
+    }
+
+    #[rocket::async_test]
+    async fn hashes_and_matches_on_blocking_pool() {
+        let password = SessionPassword::hash("secret".to_string()).await;
+        assert!(password.clone().matches("secret".to_string()).await);
+        assert!(!password.matches("wrong".to_string()).await);
     }
 }
@@ -1,69 +1,468 @@
-use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+use std::{collections::{HashMap, HashSet}, net::IpAddr, sync::{Arc, RwLock}, time::{Duration, Instant}};
 
-use super::session::{Session, CondvarDetails};
-use crate::shared::{HostCreate, HostCreateResult, ClientGetDetailsResult};
+use super::code_generator::CodeGenerator;
+use super::logging::redact;
+use super::metrics::METRICS;
+use super::owner_token::OwnerToken;
+use super::session::{Session, SessionPassword, CondvarDetails};
+use super::session_store::{SessionStore, StoreWriter};
+use crate::shared::{AdminSessionInfo, HostCreateResult, HostUpdateResult, ClientGetDetailsResult, ClientJoinResult};
 
-use rand::Rng;
+use rocket::http::Status;
+use tokio::sync::watch;
+use tracing::{error, info};
 
 pub struct SessionManager {
     sessions: HashMap<String, Session>,
+    session_ttl: Duration,
+    session_idle_timeout: Duration,
+    max_password_attempts: u32,
+    store: Arc<dyn SessionStore>,
+    store_writer: StoreWriter,
+    code_generator: CodeGenerator,
+    reserved_codes: HashSet<String>,
 }
 
+/// Codes drawn before giving up, only reached when the code space is nearly exhausted.
+const MAX_CODE_ATTEMPTS: usize = 100;
+
 impl SessionManager {
-    pub fn new() -> Self {
+    pub fn new(session_ttl: Duration, session_idle_timeout: Duration, max_password_attempts: u32, store: Arc<dyn SessionStore>, code_generator: CodeGenerator) -> Self {
+        let sessions = match store.load() {
+            Ok(records) => records
+                .into_iter()
+                .map(|(code, record)| (code, Session::from_record(record)))
+                .collect(),
+            Err(error) => {
+                error!(%error, "failed to load sessions");
+                HashMap::new()
+            }
+        };
+
+        METRICS.active_sessions.set(sessions.len() as i64);
+        info!(sessions = sessions.len(), "loaded sessions");
         Self {
-            sessions: HashMap::new(),
+            sessions,
+            session_ttl,
+            session_idle_timeout,
+            max_password_attempts,
+            store_writer: StoreWriter::spawn(store.clone()),
+            store,
+            code_generator,
+            reserved_codes: HashSet::new(),
         }
     }
 
-    pub fn create_session(&mut self, session_create: HostCreate, address: SocketAddr) -> HostCreateResult {
-        let code = self.generate_code();
-        let session = Session::from(session_create, address);
+    pub fn set_limits(&mut self, session_ttl: Duration, session_idle_timeout: Duration, max_password_attempts: u32) {
+        self.session_ttl = session_ttl;
+        self.session_idle_timeout = session_idle_timeout;
+        self.max_password_attempts = max_password_attempts;
+    }
+
+    pub fn create_session(&mut self, session: Session) -> Option<HostCreateResult> {
+        let code = self.generate_code()?;
+        let offer_id = session.offer_id;
+        let owner_token = session.owner_token.as_str().to_string();
         self.sessions.insert(code.clone(), session);
-        HostCreateResult { code }
+        self.persist_session(&code);
+        METRICS.sessions_created.inc();
+        METRICS.active_sessions.set(self.sessions.len() as i64);
+        info!(code = %redact(&code), "session created");
+        Some(HostCreateResult { code, offer_id, owner_token })
     }
 
-    pub fn is_session_owner(&self, address: &SocketAddr, code: &str) -> bool {
-        let session = self.sessions.get(code);
+    pub fn update_session(&mut self, code: &str, connection_details: String) -> Option<HostUpdateResult> {
+        let session = self.get_session_mut(code)?;
+        session.touch();
+        let offer_id = session.update_offer(connection_details);
+        self.persist_session(code);
+        Some(HostUpdateResult { offer_id })
+    }
+
+    pub fn is_session_owner(&self, owner_token: &OwnerToken, code: &str) -> bool {
+        let session = self.get_session(code);
         if session.is_none() {
             return false;
         }
         let session = session.unwrap();
-        session.address == *address
+        session.owner_token.matches(owner_token)
     }
 
     pub fn get_condvar_details(&self, code: &str) -> Option<Arc<CondvarDetails>> {
-        let session = self.sessions.get(code)?;
+        let session = self.get_session(code)?;
         Some(session.condvar_details.clone())
     }
 
-    pub fn is_session_code_valid(&self, code: &str) -> bool {
-        self.sessions.contains_key(code)
+    pub fn max_password_attempts(&self) -> u32 {
+        self.max_password_attempts
     }
 
-    pub fn get_connection_details(&self, code: &str, password: &str) -> Option<ClientGetDetailsResult> {
-        let session = self.sessions.get(code)?;
-        if session.password != password {
+    pub fn get_session_password(&self, code: &str) -> Option<SessionPassword> {
+        let session = self.get_session(code)?;
+        if session.is_locked(self.max_password_attempts) {
             return None;
         }
-        
-        let result = ClientGetDetailsResult { connection_details: session.connection_details_host.clone() };
+        Some(session.password.clone())
+    }
+
+    pub fn record_failed_attempt(&self, code: &str) {
+        if let Some(session) = self.get_session(code) {
+            session.record_failed_attempt();
+            self.persist_session(code);
+        }
+    }
+
+    pub fn get_connection_details(&self, code: &str) -> Option<ClientGetDetailsResult> {
+        let session = self.get_session(code)?;
+        let result = ClientGetDetailsResult {
+            offer_id: session.offer_id,
+            connection_details: session.connection_details_host.clone(),
+        };
         Some(result)
     }
 
+    pub fn is_offer_available(&self, code: &str) -> bool {
+        match self.get_session(code) {
+            Some(session) => !session.offer_claimed,
+            None => false,
+        }
+    }
+
+    pub fn is_offer_claimed(&self, code: &str, offer_id: u32) -> bool {
+        match self.get_session(code) {
+            Some(session) => session.is_offer_claimed(offer_id),
+            None => false,
+        }
+    }
+
+    pub fn is_relay_token(&self, code: &str, offer_id: u32, relay_token: &OwnerToken) -> bool {
+        match self.get_session(code) {
+            Some(session) => session.is_relay_token(offer_id, relay_token),
+            None => false,
+        }
+    }
+
+    /// Claims the offer a client answered and returns what is needed to hand the answer to the host.
+    /// Callers check the password with [`authorize`] first.
+    pub fn join_session(&mut self, code: &str, offer_id: u32, answer: String) -> Result<(Arc<CondvarDetails>, ClientJoinResult), Status> {
+        let session = self.get_session_mut(code).ok_or(Status::NotFound)?;
+        session.touch();
+
+        let relay_token = session.claim_offer(offer_id, answer).ok_or(Status::Conflict)?;
+
+        let join_result = ClientJoinResult {
+            compression_level: session.compression_level,
+            has_password: session.has_password(),
+            connection_details: session.connection_details_host.clone(),
+            relay_token: relay_token.as_str().to_string(),
+        };
+        let condvar_details = session.condvar_details.clone();
+        self.persist_session(code);
+        METRICS.joins.inc();
+        info!(code = %redact(code), offer_id, "session joined");
+        Ok((condvar_details, join_result))
+    }
+
+    pub fn answer_delivered(&mut self, code: &str, offer_id: u32) {
+        if let Some(session) = self.get_session_mut(code) {
+            session.answer_delivered(offer_id);
+            self.persist_session(code);
+        }
+    }
+
+    pub fn add_offer_candidate(&mut self, code: &str, offer_id: u32, candidate: String) -> bool {
+        match self.get_session_mut(code) {
+            Some(session) => session.add_offer_candidate(offer_id, candidate),
+            None => false,
+        }
+    }
+
+    /// The condvar details to queue a client's candidate on, unless its offer sent too many.
+    pub fn add_client_candidate(&mut self, code: &str, offer_id: u32) -> Option<Arc<CondvarDetails>> {
+        let session = self.get_session_mut(code)?;
+        if !session.add_client_candidate(offer_id) {
+            return None;
+        }
+        Some(session.condvar_details.clone())
+    }
+
+    pub fn get_offer_candidates(&self, code: &str, offer_id: u32, skip: usize) -> Vec<String> {
+        let candidates = self.get_session(code).and_then(|session| session.offer_candidates.get(&offer_id));
+        match candidates {
+            Some(candidates) => candidates.iter().skip(skip).cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Drops what was kept for a client that left and returns the condvar details to notify the host.
+    pub fn leave_session(&mut self, code: &str, offer_id: u32) -> Option<Arc<CondvarDetails>> {
+        let session = self.get_session_mut(code)?;
+        session.offer_candidates.remove(&offer_id);
+        session.client_candidates.remove(&offer_id);
+        session.relay_tokens.remove(&offer_id);
+        Some(session.condvar_details.clone())
+    }
+
+    pub fn subscribe_offers(&self, code: &str) -> Option<watch::Receiver<u32>> {
+        let session = self.get_session(code)?;
+        Some(session.offer_watch.subscribe())
+    }
+
     pub fn get_session(&self, code: &str) -> Option<&Session> {
-        self.sessions.get(code)
+        let session = self.sessions.get(code)?;
+        if session.is_expired(Instant::now(), self.session_ttl, self.session_idle_timeout) {
+            return None;
+        }
+        Some(session)
+    }
+
+    pub fn get_session_mut(&mut self, code: &str) -> Option<&mut Session> {
+        let session = self.sessions.get_mut(code)?;
+        if session.is_expired(Instant::now(), self.session_ttl, self.session_idle_timeout) {
+            return None;
+        }
+        Some(session)
     }
 
-    fn generate_code(&self) -> String {
-        const CODE_CHAR_SET: &str = "abcdefghijklmnopqrstuvwxyz0123456789";
+    pub fn touch_session(&self, code: &str) {
+        if let Some(session) = self.get_session(code) {
+            session.touch();
+        }
+    }
 
-        let code: String = (0..10)
-            .map(|_| {
-                let idx = rand::thread_rng().gen_range(0..CODE_CHAR_SET.len());
-                CODE_CHAR_SET.chars().nth(idx).unwrap()
+    pub fn remove_session(&mut self, code: &str) -> Option<Arc<CondvarDetails>> {
+        let session = self.sessions.remove(code)?;
+        self.forget_session(code);
+        METRICS.active_sessions.set(self.sessions.len() as i64);
+        info!(code = %redact(code), "session closed");
+        Some(session.condvar_details)
+    }
+
+    pub fn list_sessions(&self) -> Vec<AdminSessionInfo> {
+        let now = Instant::now();
+        self.sessions
+            .iter()
+            .filter(|(_, session)| !session.is_expired(now, self.session_ttl, self.session_idle_timeout))
+            .map(|(code, session)| AdminSessionInfo {
+                code: code.clone(),
+                age_secs: now.duration_since(session.created_at).as_secs(),
+                idle_secs: session.idle_time(now).unwrap_or_default().as_secs(),
+                has_password: session.has_password(),
+                compression_level: session.compression_level,
+                offer_id: session.offer_id,
+                offer_claimed: session.offer_claimed,
+                owner_ip: session.owner_ip.map(|owner_ip| owner_ip.to_string()),
             })
+            .collect()
+    }
+
+    /// Removes every session matching all given filters and returns their condvar details.
+    pub fn purge_sessions(&mut self, older_than: Option<Duration>, owner_ip: Option<IpAddr>) -> Vec<Arc<CondvarDetails>> {
+        let now = Instant::now();
+        let codes: Vec<String> = self.sessions
+            .iter()
+            .filter(|(_, session)| match older_than {
+                Some(older_than) => now.duration_since(session.created_at) >= older_than,
+                None => true,
+            })
+            .filter(|(_, session)| match owner_ip {
+                Some(owner_ip) => session.owner_ip == Some(owner_ip),
+                None => true,
+            })
+            .map(|(code, _)| code.clone())
+            .collect();
+
+        codes
+            .iter()
+            .filter_map(|code| self.remove_session(code))
+            .collect()
+    }
+
+    /// Removes every session that outlived its TTL or idle timeout and returns their
+    /// condvar details, so pending pollers can be woken once the lock is released.
+    pub fn remove_expired_sessions(&mut self) -> Vec<Arc<CondvarDetails>> {
+        let now = Instant::now();
+        let expired_codes: Vec<String> = self.sessions
+            .iter()
+            .filter(|(_, session)| session.is_expired(now, self.session_ttl, self.session_idle_timeout))
+            .map(|(code, _)| code.clone())
             .collect();
+
+        for code in &expired_codes {
+            self.forget_session(code);
+            info!(code = %redact(code), "session expired");
+        }
+
+        let expired = expired_codes
+            .iter()
+            .filter_map(|code| self.sessions.remove(code))
+            .map(|session| session.condvar_details)
+            .collect();
+        METRICS.active_sessions.set(self.sessions.len() as i64);
+        expired
+    }
+
+    pub fn is_store_ready(&self) -> bool {
+        match self.store.check() {
+            Ok(()) => true,
+            Err(error) => {
+                error!(%error, "session store check failed");
+                false
+            }
+        }
+    }
+
+    fn persist_session(&self, code: &str) {
+        let session = match self.sessions.get(code) {
+            Some(session) => session,
+            None => return,
+        };
+        self.store_writer.save(code, session.to_record());
+    }
+
+    fn forget_session(&self, code: &str) {
+        self.store_writer.remove(code);
+    }
+
+    /// Keeps a code away from new sessions while something else, like stored files, still answers to it.
+    pub fn reserve_code(&mut self, code: &str) {
+        self.reserved_codes.insert(code.to_string());
+    }
+
+    pub fn release_code(&mut self, code: &str) {
+        self.reserved_codes.remove(code);
+    }
+
+    /// Draws codes until one is not taken by a session, expired sessions included since
+    /// their hosts may still be polling, nor reserved.
+    fn generate_code(&self) -> Option<String> {
+        let code = (0..MAX_CODE_ATTEMPTS)
+            .map(|_| self.code_generator.generate())
+            .find(|code| !self.sessions.contains_key(code) && !self.reserved_codes.contains(code));
+        if code.is_none() {
+            error!("failed to generate an unused session code");
+        }
         code
     }
 }
+
+/// Verifies a client's password without holding the lock, hashing is deliberately slow.
+/// Unknown codes, locked sessions and wrong passwords all answer 401 after the same amount
+/// of work, so codes cannot be enumerated.
+pub async fn authorize(session_manager: &RwLock<SessionManager>, code: &str, password: &str) -> Result<(), Status> {
+    let session_password = {
+        let session_manager = session_manager.read().map_err(|_| Status::InternalServerError)?;
+        session_manager.get_session_password(code)
+    };
+
+    let verified = match session_password {
+        Some(session_password) => session_password.matches(password.to_string()).await,
+        None => {
+            SessionPassword::decoy().clone().matches(password.to_string()).await;
+            false
+        }
+    };
+    if !verified {
+        METRICS.password_failures.inc();
+        if let Ok(session_manager) = session_manager.read() {
+            session_manager.record_failed_attempt(code);
+        }
+        return Err(Status::Unauthorized);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::HostCreate;
+    use crate::webserver::session_store::MemorySessionStore;
+
+    const TTL: Duration = Duration::from_secs(3600);
+    const IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+    const MAX_ATTEMPTS: u32 = 3;
+
+    fn session_manager(session_ttl: Duration) -> RwLock<SessionManager> {
+        let code_generator = CodeGenerator::random("unambiguous", 10);
+        RwLock::new(SessionManager::new(session_ttl, IDLE_TIMEOUT, MAX_ATTEMPTS, Arc::new(MemorySessionStore), code_generator))
+    }
+
+    fn session(password: &str) -> Session {
+        let session_create = HostCreate {
+            connection_details: "offer".to_string(),
+            compression_level: 3,
+            password: password.to_string(),
+        };
+        Session::from(session_create, SessionPassword::new(password), None)
+    }
+
+    fn create_session(session_manager: &RwLock<SessionManager>, password: &str) -> String {
+        session_manager.write().unwrap().create_session(session(password)).unwrap().code
+    }
+
+    #[test]
+    fn session_expires_after_ttl() {
+        let session = session("");
+        let now = Instant::now();
+        assert!(!session.is_expired(now + TTL - Duration::from_secs(1), TTL, TTL * 2));
+        assert!(session.is_expired(now + TTL, TTL, TTL * 2));
+    }
+
+    #[test]
+    fn session_expires_when_idle() {
+        let session = session("");
+        let now = Instant::now();
+        assert!(!session.is_expired(now + IDLE_TIMEOUT - Duration::from_secs(1), TTL, IDLE_TIMEOUT));
+        assert!(session.is_expired(now + IDLE_TIMEOUT, TTL, IDLE_TIMEOUT));
+    }
+
+    #[test]
+    fn expired_sessions_are_gone() {
+        let session_manager = session_manager(Duration::ZERO);
+        let code = create_session(&session_manager, "");
+        let session_manager = session_manager.read().unwrap();
+        assert!(session_manager.get_session(&code).is_none());
+        assert!(session_manager.get_session_password(&code).is_none());
+    }
+
+    #[test]
+    fn collects_expired_sessions() {
+        let session_manager = session_manager(Duration::ZERO);
+        create_session(&session_manager, "");
+        create_session(&session_manager, "");
+        let mut session_manager = session_manager.write().unwrap();
+        assert_eq!(session_manager.remove_expired_sessions().len(), 2);
+        assert!(session_manager.list_sessions().is_empty());
+        assert!(session_manager.remove_expired_sessions().is_empty());
+    }
+
+    #[rocket::async_test]
+    async fn authorizes_right_password() {
+        let session_manager = session_manager(TTL);
+        let code = create_session(&session_manager, "secret");
+        assert_eq!(authorize(&session_manager, &code, "secret").await, Ok(()));
+        assert_eq!(authorize(&session_manager, &code, "wrong").await, Err(Status::Unauthorized));
+        assert_eq!(authorize(&session_manager, "unknown", "secret").await, Err(Status::Unauthorized));
+    }
+
+    #[rocket::async_test]
+    async fn locks_out_after_failed_attempts() {
+        let session_manager = session_manager(TTL);
+        let code = create_session(&session_manager, "secret");
+        for _ in 0..MAX_ATTEMPTS {
+            assert_eq!(authorize(&session_manager, &code, "wrong").await, Err(Status::Unauthorized));
+        }
+        assert_eq!(authorize(&session_manager, &code, "secret").await, Err(Status::Unauthorized));
+    }
+
+    #[rocket::async_test]
+    async fn lockout_follows_new_limits() {
+        let session_manager = session_manager(TTL);
+        let code = create_session(&session_manager, "secret");
+        for _ in 0..MAX_ATTEMPTS {
+            let _ = authorize(&session_manager, &code, "wrong").await;
+        }
+        session_manager.write().unwrap().set_limits(TTL, IDLE_TIMEOUT, MAX_ATTEMPTS + 1);
+        assert_eq!(authorize(&session_manager, &code, "secret").await, Ok(()));
+    }
+}
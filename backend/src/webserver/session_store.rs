@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::IpAddr;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+
+use tracing::error;
+
+use super::logging::redact;
+use super::owner_token::OwnerToken;
+use super::session::SessionPassword;
+
+/// Everything about a session that outlives a restart. Runtime state such as the signal
+/// queue and the offer watch is rebuilt from it.
+#[derive(Serialize, Deserialize)]
+pub struct SessionRecord {
+    pub compression_level: u8,
+    pub password: SessionPassword,
+    pub connection_details_host: String,
+    pub offer_id: u32,
+    pub offer_claimed: bool,
+    pub owner_token: OwnerToken,
+    pub owner_ip: Option<IpAddr>,
+    pub created_at_unix_secs: u64,
+    pub failed_attempts: u32,
+    pub pending_answer: Option<(u32, String)>,
+    #[serde(default)]
+    pub relay_tokens: HashMap<u32, OwnerToken>,
+}
+
+pub trait SessionStore: Send + Sync {
+    fn load(&self) -> Result<Vec<(String, SessionRecord)>, Box<dyn Error>>;
+    fn save(&self, code: &str, record: &SessionRecord) -> Result<(), Box<dyn Error>>;
+    fn remove(&self, code: &str) -> Result<(), Box<dyn Error>>;
+    /// Fails when the backing storage cannot currently be used.
+    fn check(&self) -> Result<(), Box<dyn Error>>;
+}
+
+enum StoreWrite {
+    Save(String, SessionRecord),
+    Remove(String),
+}
+
+/// Hands saves and removals to a thread of their own, so callers holding the session lock
+/// never wait on the backing storage. Writes are applied in the order they were queued.
+pub struct StoreWriter {
+    writes: Sender<StoreWrite>,
+}
+
+impl StoreWriter {
+    pub fn spawn(store: Arc<dyn SessionStore>) -> Self {
+        let (writes, queue) = mpsc::channel();
+        thread::spawn(move || {
+            for write in queue {
+                match write {
+                    StoreWrite::Save(code, record) => {
+                        if let Err(error) = store.save(&code, &record) {
+                            error!(code = %redact(&code), %error, "failed to persist session");
+                        }
+                    }
+                    StoreWrite::Remove(code) => {
+                        if let Err(error) = store.remove(&code) {
+                            error!(code = %redact(&code), %error, "failed to remove stored session");
+                        }
+                    }
+                }
+            }
+        });
+        Self { writes }
+    }
+
+    pub fn save(&self, code: &str, record: SessionRecord) {
+        let _ = self.writes.send(StoreWrite::Save(code.to_string(), record));
+    }
+
+    pub fn remove(&self, code: &str) {
+        let _ = self.writes.send(StoreWrite::Remove(code.to_string()));
+    }
+}
+
+/// Keeps nothing beyond the process, sessions only live in the `SessionManager`.
+pub struct MemorySessionStore;
+
+impl SessionStore for MemorySessionStore {
+    fn load(&self) -> Result<Vec<(String, SessionRecord)>, Box<dyn Error>> {
+        Ok(Vec::new())
+    }
+
+    fn save(&self, _code: &str, _record: &SessionRecord) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn remove(&self, _code: &str) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn check(&self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+pub struct SqliteSessionStore {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteSessionStore {
+    pub fn open(path: &str) -> Result<Self, Box<dyn Error>> {
+        let connection = Connection::open(path)?;
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS sessions (code TEXT PRIMARY KEY, record TEXT NOT NULL)",
+            [],
+        )?;
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+}
+
+impl SessionStore for SqliteSessionStore {
+    fn load(&self) -> Result<Vec<(String, SessionRecord)>, Box<dyn Error>> {
+        let connection = self.connection.lock().map_err(|_| "Session store lock poisoned")?;
+        let mut statement = connection.prepare("SELECT code, record FROM sessions")?;
+        let rows = statement.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+
+        let mut records = Vec::new();
+        for row in rows {
+            let (code, record) = row?;
+            records.push((code, serde_json::from_str::<SessionRecord>(&record)?));
+        }
+        Ok(records)
+    }
+
+    fn save(&self, code: &str, record: &SessionRecord) -> Result<(), Box<dyn Error>> {
+        let record = serde_json::to_string(record)?;
+        let connection = self.connection.lock().map_err(|_| "Session store lock poisoned")?;
+        connection.execute(
+            "INSERT OR REPLACE INTO sessions (code, record) VALUES (?1, ?2)",
+            params![code, record],
+        )?;
+        Ok(())
+    }
+
+    fn remove(&self, code: &str) -> Result<(), Box<dyn Error>> {
+        let connection = self.connection.lock().map_err(|_| "Session store lock poisoned")?;
+        connection.execute("DELETE FROM sessions WHERE code = ?1", params![code])?;
+        Ok(())
+    }
+
+    fn check(&self) -> Result<(), Box<dyn Error>> {
+        let connection = self.connection.lock().map_err(|_| "Session store lock poisoned")?;
+        connection.query_row("SELECT COUNT(*) FROM sessions", [], |row| row.get::<_, i64>(0))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+    use crate::webserver::session::Session;
+
+    const TTL: Duration = Duration::from_secs(3600);
+    const IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+
+    struct Fixture {
+        directory: PathBuf,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            let directory = std::env::temp_dir().join(format!("file-link-sessions-{}-{}", name, std::process::id()));
+            fs::create_dir_all(&directory).unwrap();
+            Self { directory }
+        }
+
+        fn open(&self) -> SqliteSessionStore {
+            SqliteSessionStore::open(self.directory.join("sessions.db").to_str().unwrap()).unwrap()
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.directory);
+        }
+    }
+
+    fn record(age: Duration) -> SessionRecord {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        SessionRecord {
+            compression_level: 3,
+            password: SessionPassword::new(""),
+            connection_details_host: "offer".to_string(),
+            offer_id: 2,
+            offer_claimed: true,
+            owner_token: OwnerToken::generate(),
+            owner_ip: None,
+            created_at_unix_secs: (now - age).as_secs(),
+            failed_attempts: 1,
+            pending_answer: Some((2, "answer".to_string())),
+            relay_tokens: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn sessions_survive_reopening() {
+        let fixture = Fixture::new("reopen");
+        let saved = record(Duration::ZERO);
+        let store = fixture.open();
+        store.save("ABC", &saved).unwrap();
+        drop(store);
+
+        let store = fixture.open();
+        store.check().unwrap();
+        let records = store.load().unwrap();
+        assert_eq!(records.len(), 1);
+        let (code, loaded) = &records[0];
+        assert_eq!(code, "ABC");
+        assert_eq!(loaded.compression_level, 3);
+        assert_eq!(loaded.connection_details_host, "offer");
+        assert_eq!(loaded.offer_id, 2);
+        assert!(loaded.offer_claimed);
+        assert!(loaded.owner_token.matches(&saved.owner_token));
+        assert_eq!(loaded.created_at_unix_secs, saved.created_at_unix_secs);
+        assert_eq!(loaded.failed_attempts, 1);
+        assert_eq!(loaded.pending_answer, Some((2, "answer".to_string())));
+    }
+
+    #[test]
+    fn removed_sessions_stay_gone() {
+        let fixture = Fixture::new("remove");
+        let store = fixture.open();
+        store.save("ABC", &record(Duration::ZERO)).unwrap();
+        store.save("DEF", &record(Duration::ZERO)).unwrap();
+        store.remove("ABC").unwrap();
+        drop(store);
+
+        let codes: Vec<String> = fixture.open().load().unwrap().into_iter().map(|(code, _)| code).collect();
+        assert_eq!(codes, vec!["DEF".to_string()]);
+    }
+
+    #[test]
+    fn reloaded_sessions_keep_their_age() {
+        let fixture = Fixture::new("expiry");
+        let store = fixture.open();
+        store.save("OLD", &record(TTL + Duration::from_secs(60))).unwrap();
+        store.save("NEW", &record(Duration::from_secs(60))).unwrap();
+        drop(store);
+
+        let now = Instant::now();
+        for (code, record) in fixture.open().load().unwrap() {
+            let session = Session::from_record(record);
+            assert_eq!(session.is_expired(now, TTL, IDLE_TIMEOUT), code == "OLD", "{}", code);
+        }
+    }
+
+    #[test]
+    fn writer_applies_writes_in_order() {
+        let fixture = Fixture::new("writer");
+        let store: Arc<dyn SessionStore> = Arc::new(fixture.open());
+        let writer = StoreWriter::spawn(store.clone());
+        writer.save("ABC", record(Duration::ZERO));
+        writer.remove("ABC");
+        writer.save("DEF", record(Duration::ZERO));
+        drop(writer);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let codes = loop {
+            let codes: Vec<String> = store.load().unwrap().into_iter().map(|(code, _)| code).collect();
+            if !codes.is_empty() || Instant::now() >= deadline {
+                break codes;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        assert_eq!(codes, vec!["DEF".to_string()]);
+    }
+}
@@ -0,0 +1,289 @@
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use rocket::futures::{SinkExt, StreamExt};
+use rocket::http::Status;
+use rocket::{get, State};
+use rocket_ws::{Channel, Message, WebSocket};
+use rocket_ws::result::Error;
+use rocket_ws::stream::DuplexStream;
+
+use tracing::Instrument;
+
+use crate::shared::{ClientGetDetailsResult, SignalingMessage, BACKEND_CONFIG};
+
+use super::logging::RequestSpan;
+use super::session::{pop_signal, push_signal, SessionSignal};
+use super::owner_token::OwnerToken;
+use super::rate_limiter::RateLimit;
+use super::session_manager::{authorize, SessionManager};
+
+#[get("/api/sessions/<session_id>/signaling/host")]
+pub fn host_channel(
+    owner_token: OwnerToken,
+    request_span: RequestSpan,
+    ws: WebSocket,
+    session_manager: &State<Arc<RwLock<SessionManager>>>,
+    session_id: String,
+) -> Result<Channel<'static>, Status> {
+    let condvar_details = {
+        let session_manager = session_manager.read();
+        if session_manager.is_err() {
+            return Err(Status::InternalServerError);
+        }
+        let session_manager = session_manager.unwrap();
+        if !session_manager.is_session_owner(&owner_token, &session_id) {
+            return Err(Status::Forbidden);
+        }
+
+        let condvar_details = session_manager.get_condvar_details(&session_id);
+        if condvar_details.is_none() {
+            return Err(Status::NotFound);
+        }
+        condvar_details.unwrap()
+    };
+
+    let session_manager = session_manager.inner().clone();
+    Ok(ws.channel(move |mut stream| Box::pin(async move {
+        let touch_interval = Duration::from_secs(BACKEND_CONFIG.session_poll_timeout_secs);
+        let mut touch = tokio::time::interval(touch_interval);
+
+        loop {
+            tokio::select! {
+                signal = pop_signal(&condvar_details) => {
+                    let message = match signal {
+                        SessionSignal::Answer(offer_id, connection_details) => {
+                            if let Ok(mut session_manager) = session_manager.write() {
+                                session_manager.answer_delivered(&session_id, offer_id);
+                            }
+                            SignalingMessage::Answer { offer_id, connection_details }
+                        }
+                        SessionSignal::Candidate(offer_id, candidate) => SignalingMessage::Candidate { offer_id, candidate },
+                        SessionSignal::Leave(offer_id) => SignalingMessage::Leave { offer_id },
+                        SessionSignal::Expired => {
+                            send_message(&mut stream, &SignalingMessage::Expired).await?;
+                            break;
+                        }
+                        SessionSignal::Closed => {
+                            send_message(&mut stream, &SignalingMessage::Close).await?;
+                            break;
+                        }
+                    };
+                    send_message(&mut stream, &message).await?;
+                }
+                message = stream.next() => {
+                    let message = match message {
+                        Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                        Some(Ok(message)) => message,
+                    };
+
+                    match parse_message(&message) {
+                        Some(SignalingMessage::Offer { connection_details, .. }) => {
+                            let reply = match update_offer(&session_manager, &session_id, connection_details) {
+                                Ok(offer_id) => SignalingMessage::Offer { offer_id, connection_details: String::new() },
+                                Err(status) => SignalingMessage::Error { status: status.code },
+                            };
+                            send_message(&mut stream, &reply).await?;
+                        }
+                        Some(SignalingMessage::Candidate { offer_id, candidate }) => {
+                            let added = match session_manager.write() {
+                                Ok(mut session_manager) => session_manager.add_offer_candidate(&session_id, offer_id, candidate),
+                                Err(_) => false,
+                            };
+                            if !added {
+                                send_message(&mut stream, &SignalingMessage::Error { status: Status::BadRequest.code }).await?;
+                            }
+                        }
+                        Some(SignalingMessage::Close) => {
+                            let removed = match session_manager.write() {
+                                Ok(mut session_manager) => session_manager.remove_session(&session_id),
+                                Err(_) => None,
+                            };
+                            if removed.is_some() {
+                                push_signal(&condvar_details, SessionSignal::Closed).await;
+                            }
+                        }
+                        Some(_) => send_message(&mut stream, &SignalingMessage::Error { status: Status::BadRequest.code }).await?,
+                        None => {}
+                    }
+                }
+                _ = touch.tick() => {
+                    if let Ok(session_manager) = session_manager.read() {
+                        session_manager.touch_session(&session_id);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }.instrument(request_span.span))))
+}
+
+// Ranked below the poll route, which also matches "/api/sessions/poll/signaling"
+#[get("/api/sessions/<session_id>/signaling", rank = 2)]
+pub fn client_channel(
+    _rate_limit: RateLimit,
+    request_span: RequestSpan,
+    ws: WebSocket,
+    session_manager: &State<Arc<RwLock<SessionManager>>>,
+    session_id: String,
+) -> Channel<'static> {
+    let session_manager = session_manager.inner().clone();
+    ws.channel(move |mut stream| Box::pin(async move {
+        let password = loop {
+            match stream.next().await {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return Ok(()),
+                Some(Ok(message)) => match parse_message(&message) {
+                    Some(SignalingMessage::Join { password }) => break password,
+                    Some(_) => send_message(&mut stream, &SignalingMessage::Error { status: Status::BadRequest.code }).await?,
+                    None => {}
+                },
+            }
+        };
+
+        let mut offers = match subscribe_offers(&session_manager, &session_id, &password).await {
+            Ok(offers) => offers,
+            Err(status) => {
+                send_message(&mut stream, &SignalingMessage::Error { status: status.code }).await?;
+                return Ok(());
+            }
+        };
+
+        let mut progress = OfferProgress { offer_id: None, claimed: false, sent_candidates: 0 };
+        forward_offer(&mut stream, &session_manager, &session_id, &mut progress).await?;
+
+        loop {
+            tokio::select! {
+                changed = offers.changed() => {
+                    if changed.is_err() {
+                        send_message(&mut stream, &SignalingMessage::Close).await?;
+                        break;
+                    }
+                    forward_offer(&mut stream, &session_manager, &session_id, &mut progress).await?;
+                }
+                message = stream.next() => {
+                    let message = match message {
+                        Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                        Some(Ok(message)) => message,
+                    };
+
+                    match parse_message(&message) {
+                        // One socket holds one offer, so leaving releases everything it claimed
+                        Some(SignalingMessage::Answer { .. }) if progress.claimed => {
+                            send_message(&mut stream, &SignalingMessage::Error { status: Status::Conflict.code }).await?;
+                        }
+                        Some(SignalingMessage::Answer { offer_id, connection_details }) => {
+                            let joined = match session_manager.write() {
+                                Ok(mut session_manager) => session_manager.join_session(&session_id, offer_id, connection_details.clone()),
+                                Err(_) => Err(Status::InternalServerError),
+                            };
+                            let reply = match joined {
+                                Ok((condvar_details, join_result)) => {
+                                    push_signal(&condvar_details, SessionSignal::Answer(offer_id, connection_details)).await;
+                                    progress.offer_id = Some(offer_id);
+                                    progress.claimed = true;
+                                    SignalingMessage::Joined(join_result)
+                                }
+                                Err(status) => SignalingMessage::Error { status: status.code },
+                            };
+                            send_message(&mut stream, &reply).await?;
+                        }
+                        // Candidates only reach the host once the client owns the offer
+                        Some(SignalingMessage::Candidate { offer_id, candidate }) if progress.claimed && progress.offer_id == Some(offer_id) => {
+                            let condvar_details = match session_manager.write() {
+                                Ok(mut session_manager) => session_manager.add_client_candidate(&session_id, offer_id),
+                                Err(_) => None,
+                            };
+                            if let Some(condvar_details) = condvar_details {
+                                push_signal(&condvar_details, SessionSignal::Candidate(offer_id, candidate)).await;
+                            }
+                        }
+                        Some(SignalingMessage::Candidate { .. }) => {}
+                        Some(_) => send_message(&mut stream, &SignalingMessage::Error { status: Status::BadRequest.code }).await?,
+                        None => {}
+                    }
+                }
+            }
+        }
+
+        if let (true, Some(offer_id)) = (progress.claimed, progress.offer_id) {
+            let condvar_details = match session_manager.write() {
+                Ok(mut session_manager) => session_manager.leave_session(&session_id, offer_id),
+                Err(_) => None,
+            };
+            if let Some(condvar_details) = condvar_details {
+                push_signal(&condvar_details, SessionSignal::Leave(offer_id)).await;
+            }
+        }
+        Ok(())
+    }.instrument(request_span.span)))
+}
+
+fn update_offer(session_manager: &RwLock<SessionManager>, session_id: &str, connection_details: String) -> Result<u32, Status> {
+    let mut session_manager = session_manager.write().map_err(|_| Status::InternalServerError)?;
+    let result = session_manager.update_session(session_id, connection_details).ok_or(Status::NotFound)?;
+    Ok(result.offer_id)
+}
+
+async fn subscribe_offers(session_manager: &RwLock<SessionManager>, session_id: &str, password: &str) -> Result<tokio::sync::watch::Receiver<u32>, Status> {
+    authorize(session_manager, session_id, password).await?;
+    let session_manager = session_manager.read().map_err(|_| Status::InternalServerError)?;
+    session_manager.subscribe_offers(session_id).ok_or(Status::NotFound)
+}
+
+/// Tracks which offer a client is working on and how many of the host's trickled candidates it has seen.
+struct OfferProgress {
+    offer_id: Option<u32>,
+    claimed: bool,
+    sent_candidates: usize,
+}
+
+async fn forward_offer(
+    stream: &mut DuplexStream,
+    session_manager: &RwLock<SessionManager>,
+    session_id: &str,
+    progress: &mut OfferProgress,
+) -> Result<(), Error> {
+    if !progress.claimed {
+        if let Some(offer) = current_offer(session_manager, session_id) {
+            if progress.offer_id != Some(offer.offer_id) {
+                progress.offer_id = Some(offer.offer_id);
+                progress.sent_candidates = 0;
+                send_message(stream, &SignalingMessage::Offer { offer_id: offer.offer_id, connection_details: offer.connection_details }).await?;
+            }
+        }
+    }
+
+    let offer_id = match progress.offer_id {
+        Some(offer_id) => offer_id,
+        None => return Ok(()),
+    };
+    let candidates = match session_manager.read() {
+        Ok(session_manager) => session_manager.get_offer_candidates(session_id, offer_id, progress.sent_candidates),
+        Err(_) => Vec::new(),
+    };
+    for candidate in candidates {
+        progress.sent_candidates += 1;
+        send_message(stream, &SignalingMessage::Candidate { offer_id, candidate }).await?;
+    }
+    Ok(())
+}
+
+fn current_offer(session_manager: &RwLock<SessionManager>, session_id: &str) -> Option<ClientGetDetailsResult> {
+    let session_manager = session_manager.read().ok()?;
+    if !session_manager.is_offer_available(session_id) {
+        return None;
+    }
+    session_manager.get_connection_details(session_id)
+}
+
+pub async fn send_message(stream: &mut DuplexStream, message: &SignalingMessage) -> Result<(), Error> {
+    let message = serde_json::to_string(message).unwrap();
+    stream.send(Message::Text(message)).await
+}
+
+pub fn parse_message(message: &Message) -> Option<SignalingMessage> {
+    match message {
+        Message::Text(text) => serde_json::from_str::<SignalingMessage>(text).ok(),
+        _ => None,
+    }
+}
@@ -0,0 +1,113 @@
+use std::path::{Path, PathBuf};
+
+use once_cell::sync::Lazy;
+use rocket::fs::NamedFile;
+use rocket::get;
+
+use crate::shared::BACKEND_CONFIG;
+
+static WEB_ROOT: Lazy<PathBuf> = Lazy::new(|| PathBuf::from(&BACKEND_CONFIG.web_root));
+static INDEX_PATH: Lazy<PathBuf> = Lazy::new(|| WEB_ROOT.join("index.html"));
+
+pub fn page_available() -> bool {
+    INDEX_PATH.is_file()
+}
+
+#[get("/")]
+pub async fn root() -> Option<NamedFile> {
+    NamedFile::open(&*INDEX_PATH).await.ok()
+}
+
+/// The page links its assets under `/public`, whatever directory `web_root` names.
+#[get("/public/<path..>")]
+pub async fn public_file(path: PathBuf) -> Option<NamedFile> {
+    NamedFile::open(web_root_file(&WEB_ROOT, &path)?).await.ok()
+}
+
+/// Any other path is one of the page's own, like `/receive/<code>`.
+#[get("/<_..>")]
+pub async fn catch_all() -> Option<NamedFile> {
+    NamedFile::open(&*INDEX_PATH).await.ok()
+}
+
+/// Rocket already refuses `..` and hidden segments, resolving both paths also keeps symlinks
+/// from leading out of the web root.
+fn web_root_file(web_root: &Path, path: &Path) -> Option<PathBuf> {
+    let web_root = web_root.canonicalize().ok()?;
+    let file = web_root.join(path).canonicalize().ok()?;
+    if file.starts_with(&web_root) && file.is_file() {
+        Some(file)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    /// A web root holding `index.html` and `pkg/app.wasm`, next to a `config.toml` outside of it.
+    struct Fixture {
+        directory: PathBuf,
+        web_root: PathBuf,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            let directory = std::env::temp_dir().join(format!("file-link-static-{}-{}", name, std::process::id()));
+            let web_root = directory.join("public");
+            fs::create_dir_all(web_root.join("pkg")).unwrap();
+            fs::write(web_root.join("index.html"), "page").unwrap();
+            fs::write(web_root.join("pkg").join("app.wasm"), "wasm").unwrap();
+            fs::write(directory.join("config.toml"), "secret").unwrap();
+            Self { directory, web_root }
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.directory);
+        }
+    }
+
+    #[test]
+    fn resolves_files_in_web_root() {
+        let fixture = Fixture::new("inside");
+        let file = web_root_file(&fixture.web_root, Path::new("pkg/app.wasm")).unwrap();
+        assert_eq!(fs::read_to_string(file).unwrap(), "wasm");
+    }
+
+    #[test]
+    fn refuses_missing_files_and_directories() {
+        let fixture = Fixture::new("missing");
+        assert_eq!(web_root_file(&fixture.web_root, Path::new("pkg/missing.js")), None);
+        assert_eq!(web_root_file(&fixture.web_root, Path::new("pkg")), None);
+    }
+
+    #[test]
+    fn refuses_parent_segments() {
+        let fixture = Fixture::new("parent");
+        assert_eq!(web_root_file(&fixture.web_root, Path::new("../config.toml")), None);
+        assert_eq!(web_root_file(&fixture.web_root, Path::new("pkg/../../config.toml")), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_symlinks_out_of_web_root() {
+        let fixture = Fixture::new("symlink");
+        std::os::unix::fs::symlink(fixture.directory.join("config.toml"), fixture.web_root.join("config.toml")).unwrap();
+        std::os::unix::fs::symlink(&fixture.directory, fixture.web_root.join("up")).unwrap();
+        assert_eq!(web_root_file(&fixture.web_root, Path::new("config.toml")), None);
+        assert_eq!(web_root_file(&fixture.web_root, Path::new("up/config.toml")), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn follows_symlinks_within_web_root() {
+        let fixture = Fixture::new("symlink-inside");
+        std::os::unix::fs::symlink(fixture.web_root.join("pkg").join("app.wasm"), fixture.web_root.join("app.wasm")).unwrap();
+        assert!(web_root_file(&fixture.web_root, Path::new("app.wasm")).is_some());
+    }
+}
@@ -0,0 +1,196 @@
+use std::net::{IpAddr, SocketAddr};
+
+use tokio::net::UdpSocket;
+use tracing::{debug, error, info};
+
+use crate::shared::BACKEND_CONFIG;
+
+const HEADER_LENGTH: usize = 20;
+const MAGIC_COOKIE: u32 = 0x2112_A442;
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS: u16 = 0x0101;
+const XOR_MAPPED_ADDRESS: u16 = 0x0020;
+const FAMILY_IPV4: u8 = 0x01;
+const FAMILY_IPV6: u8 = 0x02;
+/// Large enough for any request over a typical MTU, longer datagrams are no STUN binding requests.
+const MAX_DATAGRAM: usize = 1500;
+
+/// Answers STUN binding requests (RFC 5389) with the address they came from, so peers learn
+/// their server-reflexive candidates without a public STUN server. Authentication,
+/// FINGERPRINT and every other attribute of the request are ignored.
+pub fn spawn_stun_responder() {
+    let backend_config = &*BACKEND_CONFIG;
+    let web_bind_addr: IpAddr = backend_config.web_bind_addr.parse().expect("Invalid IP address");
+    let address = SocketAddr::new(web_bind_addr, backend_config.stun_port);
+
+    tokio::spawn(async move {
+        let socket = match UdpSocket::bind(address).await {
+            Ok(socket) => socket,
+            Err(error) => {
+                error!(%address, %error, "failed to bind the STUN responder, peers only see the configured STUN servers");
+                return;
+            }
+        };
+        info!(%address, "STUN responder listening");
+
+        let mut request = [0u8; MAX_DATAGRAM];
+        loop {
+            let (length, peer) = match socket.recv_from(&mut request).await {
+                Ok(received) => received,
+                Err(error) => {
+                    debug!(%error, "failed to receive STUN datagram");
+                    continue;
+                }
+            };
+
+            let response = match binding_response(&request[..length], peer) {
+                Some(response) => response,
+                None => continue,
+            };
+            if let Err(error) = socket.send_to(&response, peer).await {
+                debug!(%peer, %error, "failed to answer STUN binding request");
+            }
+        }
+    });
+}
+
+/// Builds the Binding Success response for a well-formed Binding Request, anything else gets no answer.
+fn binding_response(request: &[u8], peer: SocketAddr) -> Option<Vec<u8>> {
+    if request.len() < HEADER_LENGTH {
+        return None;
+    }
+    let message_type = u16::from_be_bytes([request[0], request[1]]);
+    let message_length = u16::from_be_bytes([request[2], request[3]]) as usize;
+    let magic_cookie = u32::from_be_bytes([request[4], request[5], request[6], request[7]]);
+    if message_type != BINDING_REQUEST
+        || magic_cookie != MAGIC_COOKIE
+        || !message_length.is_multiple_of(4)
+        || HEADER_LENGTH + message_length != request.len()
+    {
+        return None;
+    }
+    let transaction_id = &request[8..HEADER_LENGTH];
+
+    let attribute = xor_mapped_address(peer, transaction_id);
+    let mut response = Vec::with_capacity(HEADER_LENGTH + 4 + attribute.len());
+    response.extend_from_slice(&BINDING_SUCCESS.to_be_bytes());
+    response.extend_from_slice(&(4 + attribute.len() as u16).to_be_bytes());
+    response.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    response.extend_from_slice(transaction_id);
+    response.extend_from_slice(&XOR_MAPPED_ADDRESS.to_be_bytes());
+    response.extend_from_slice(&(attribute.len() as u16).to_be_bytes());
+    response.extend_from_slice(&attribute);
+    Some(response)
+}
+
+/// Value of an XOR-MAPPED-ADDRESS attribute: the port is XORed with the upper half of the magic
+/// cookie, the address with the magic cookie followed by the transaction id.
+fn xor_mapped_address(peer: SocketAddr, transaction_id: &[u8]) -> Vec<u8> {
+    let mut key = MAGIC_COOKIE.to_be_bytes().to_vec();
+    key.extend_from_slice(transaction_id);
+
+    // A responder bound to "::" sees IPv4 peers as mapped addresses, they have to be reported as IPv4
+    let (family, address) = match peer.ip() {
+        IpAddr::V4(address) => (FAMILY_IPV4, address.octets().to_vec()),
+        IpAddr::V6(address) => match address.to_ipv4_mapped() {
+            Some(address) => (FAMILY_IPV4, address.octets().to_vec()),
+            None => (FAMILY_IPV6, address.octets().to_vec()),
+        },
+    };
+    let port = peer.port() ^ (MAGIC_COOKIE >> 16) as u16;
+
+    let mut value = vec![0, family];
+    value.extend_from_slice(&port.to_be_bytes());
+    value.extend(address.iter().zip(&key).map(|(byte, key)| byte ^ key));
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Transaction id and mapped address of the sample responses in RFC 5769, section 2.2 and 2.3.
+    const TRANSACTION_ID: [u8; 12] = [0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6, 0x86, 0xfa, 0x87, 0xdf, 0xae];
+    const IPV4_PEER: &str = "192.0.2.1:32853";
+    const IPV4_XOR_MAPPED_ADDRESS: [u8; 8] = [0x00, 0x01, 0xa1, 0x47, 0xe1, 0x12, 0xa6, 0x43];
+    const IPV6_PEER: &str = "[2001:db8:1234:5678:11:2233:4455:6677]:32853";
+    const IPV6_XOR_MAPPED_ADDRESS: [u8; 20] = [
+        0x00, 0x02, 0xa1, 0x47, 0x01, 0x13, 0xa9, 0xfa, 0xa5, 0xd3, 0xf1, 0x79, 0xbc, 0x25, 0xf4, 0xb5, 0xbe, 0xd2, 0xb9, 0xd9,
+    ];
+
+    fn request(message_type: u16, magic_cookie: u32, body: &[u8]) -> Vec<u8> {
+        let mut request = Vec::new();
+        request.extend_from_slice(&message_type.to_be_bytes());
+        request.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        request.extend_from_slice(&magic_cookie.to_be_bytes());
+        request.extend_from_slice(&TRANSACTION_ID);
+        request.extend_from_slice(body);
+        request
+    }
+
+    fn peer(address: &str) -> SocketAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn answers_binding_request_with_xor_mapped_address() {
+        let response = binding_response(&request(BINDING_REQUEST, MAGIC_COOKIE, &[]), peer(IPV4_PEER)).unwrap();
+
+        let mut expected = request(BINDING_SUCCESS, MAGIC_COOKIE, &[0x00, 0x20, 0x00, 0x08]);
+        expected[3] = 12;
+        expected.extend_from_slice(&IPV4_XOR_MAPPED_ADDRESS);
+        assert_eq!(response, expected);
+    }
+
+    #[test]
+    fn accepts_attributes_in_the_request() {
+        let software = [0x80, 0x22, 0x00, 0x04, b't', b'e', b's', b't'];
+        assert!(binding_response(&request(BINDING_REQUEST, MAGIC_COOKIE, &software), peer(IPV4_PEER)).is_some());
+    }
+
+    #[test]
+    fn ignores_short_datagram() {
+        let request = request(BINDING_REQUEST, MAGIC_COOKIE, &[]);
+        assert_eq!(binding_response(&request[..HEADER_LENGTH - 1], peer(IPV4_PEER)), None);
+        assert_eq!(binding_response(&[], peer(IPV4_PEER)), None);
+    }
+
+    #[test]
+    fn ignores_wrong_magic_cookie() {
+        assert_eq!(binding_response(&request(BINDING_REQUEST, 0x2112_A443, &[]), peer(IPV4_PEER)), None);
+    }
+
+    #[test]
+    fn ignores_other_message_types() {
+        assert_eq!(binding_response(&request(BINDING_SUCCESS, MAGIC_COOKIE, &[]), peer(IPV4_PEER)), None);
+    }
+
+    #[test]
+    fn ignores_length_mismatch() {
+        let mut truncated = request(BINDING_REQUEST, MAGIC_COOKIE, &[0; 8]);
+        truncated.truncate(HEADER_LENGTH + 4);
+        assert_eq!(binding_response(&truncated, peer(IPV4_PEER)), None);
+
+        let mut trailing = request(BINDING_REQUEST, MAGIC_COOKIE, &[]);
+        trailing.extend_from_slice(&[0; 4]);
+        assert_eq!(binding_response(&trailing, peer(IPV4_PEER)), None);
+
+        let unaligned = request(BINDING_REQUEST, MAGIC_COOKIE, &[0; 6]);
+        assert_eq!(binding_response(&unaligned, peer(IPV4_PEER)), None);
+    }
+
+    #[test]
+    fn encodes_ipv4_address() {
+        assert_eq!(xor_mapped_address(peer(IPV4_PEER), &TRANSACTION_ID), IPV4_XOR_MAPPED_ADDRESS);
+    }
+
+    #[test]
+    fn encodes_ipv6_address() {
+        assert_eq!(xor_mapped_address(peer(IPV6_PEER), &TRANSACTION_ID), IPV6_XOR_MAPPED_ADDRESS);
+    }
+
+    #[test]
+    fn encodes_ipv4_mapped_address_as_ipv4() {
+        assert_eq!(xor_mapped_address(peer("[::ffff:192.0.2.1]:32853"), &TRANSACTION_ID), IPV4_XOR_MAPPED_ADDRESS);
+    }
+}
@@ -1,88 +1,340 @@
-use std::sync::RwLock;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
+use super::admin::*;
+use super::api;
+use super::api_error::api_catcher;
+use super::code_generator::CodeGenerator;
+#[cfg(feature = "embed-assets")]
+use super::embedded;
+use super::file_store::*;
+use super::health::{healthz, readyz, version};
+use super::ice::ice_servers;
+use super::logging::{self, RequestLogger};
+use super::metrics::{metrics, MetricsFairing};
+use super::openapi::{openapi, ApiDoc};
+use super::rate_limiter::RateLimiter;
+use super::relay::{client_relay, host_relay, RelayHub};
+use super::reload::spawn_config_watcher;
 use super::routing::*;
+use super::signaling::*;
+#[cfg(not(feature = "embed-assets"))]
+use super::static_files;
+use super::stun::spawn_stun_responder;
+use super::session::{push_signal, SessionSignal};
+use super::security_headers::SecurityHeaders;
 use super::session_manager::SessionManager;
-use rocket::routes;
-use rocket::config::{Config, TlsConfig, MutualTls};
+use super::session_store::{MemorySessionStore, SessionStore, SqliteSessionStore};
+use rocket::{catchers, routes, Build, Rocket};
+use rocket::config::{Config, LogLevel, TlsConfig, MutualTls};
+use rocket::http::uncased::Uncased;
 use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+use tracing::{error, info};
 use unescape::unescape;
+use utoipa::OpenApi;
+use utoipa_redoc::{Redoc, Servable};
 
-use crate::shared::BACKEND_CONFIG;
+use crate::shared::{BackendConfig, BACKEND_CONFIG};
 use std::net::IpAddr;
 
+#[allow(clippy::module_inception)]
 pub mod webserver {
     use super::*;
 
     pub fn run() {
         let rt = Runtime::new().unwrap();
+        logging::init();
+
         rt.block_on(async {
-            rocket::custom(create_config())
-                .manage(RwLock::new(SessionManager::new()))
-                .mount(
-                    "/",
-                    create_routes(),
-                )
-                .launch()
-                .await
-                .unwrap();
+            let session_manager = create_session_manager();
+            let rate_limiter = create_rate_limiter();
+            let relay_hub = Arc::new(RelayHub::default());
+            let file_store = create_file_store(&session_manager);
+            spawn_session_gc(session_manager.clone(), rate_limiter.clone(), file_store.clone());
+            if BACKEND_CONFIG.stun_enabled {
+                spawn_stun_responder();
+            }
+
+            let (tls_sender, mut tls_changes) = mpsc::unbounded_channel();
+            spawn_config_watcher(session_manager.clone(), rate_limiter.clone(), tls_sender);
+
+            // Sessions live in the managed state, so relaunching with new TLS files keeps them.
+            // Requests in flight and open signaling and relay WebSockets are cut off.
+            let mut tls_config = create_tls_config(&BACKEND_CONFIG);
+            let mut previous_tls_config = None;
+            loop {
+                let rocket = create_rocket(tls_config.clone(), session_manager.clone(), rate_limiter.clone(), relay_hub.clone(), file_store.clone())
+                    .ignite()
+                    .await
+                    .unwrap();
+                let shutdown = rocket.shutdown();
+                let launch = rocket.launch();
+                tokio::pin!(launch);
+
+                info!(address = %BACKEND_CONFIG.web_bind_addr, port = BACKEND_CONFIG.web_port, "starting web server");
+                tokio::select! {
+                    result = &mut launch => match (result, previous_tls_config.take()) {
+                        (Ok(_), _) => break,
+                        (Err(error), Some(previous)) => {
+                            error!(%error, "relaunch with the new TLS files failed, falling back to the previous ones");
+                            tls_config = previous;
+                        }
+                        (Err(error), None) => panic!("{}", error),
+                    },
+                    Some(new_tls_config) = tls_changes.recv() => {
+                        shutdown.notify();
+                        let _ = launch.await;
+                        previous_tls_config = Some(tls_config.replace(new_tls_config));
+                    }
+                }
+            }
         });
     }
 
+    fn create_rocket(
+        tls_config: Option<TlsConfig>,
+        session_manager: Arc<RwLock<SessionManager>>,
+        rate_limiter: Arc<RateLimiter>,
+        relay_hub: Arc<RelayHub>,
+        file_store: Option<Arc<FileStore>>,
+    ) -> Rocket<Build> {
+        let security_headers = SecurityHeaders::new(tls_config.is_some());
+        let mut rocket = rocket::custom(create_config(tls_config))
+            .manage(session_manager)
+            .manage(rate_limiter)
+            .manage(relay_hub)
+            .mount(
+                "/",
+                create_routes(),
+            )
+            .register("/api/v2", catchers![api_catcher])
+            .attach(RequestLogger)
+            .attach(security_headers);
+        if let Some(file_store) = file_store {
+            rocket = rocket.manage(file_store);
+        }
+        if BACKEND_CONFIG.web_serve_metrics {
+            rocket = rocket.attach(MetricsFairing);
+        }
+        rocket
+    }
+
     pub fn unescape_quotes(s: &str) -> String {
         let s = s.trim_matches('"');
         unescape(s).unwrap()
     }
 
+    fn create_session_manager() -> Arc<RwLock<SessionManager>> {
+        let backend_config = &*BACKEND_CONFIG;
+        let session_ttl = Duration::from_secs(backend_config.session_ttl_secs);
+        let session_idle_timeout = Duration::from_secs(backend_config.session_idle_timeout_secs);
+        let max_password_attempts = backend_config.session_max_password_attempts;
+        let store = create_session_store();
+        let code_generator = create_code_generator();
+        Arc::new(RwLock::new(SessionManager::new(session_ttl, session_idle_timeout, max_password_attempts, store, code_generator)))
+    }
+
+    fn create_code_generator() -> CodeGenerator {
+        let backend_config = &*BACKEND_CONFIG;
+        match backend_config.session_code_scheme.as_str() {
+            "random" => CodeGenerator::random(&backend_config.session_code_alphabet, backend_config.session_code_length),
+            "words" => CodeGenerator::words(backend_config.session_code_words),
+            other => panic!("Unknown session code scheme: {}", other),
+        }
+    }
+
+    fn create_session_store() -> Arc<dyn SessionStore> {
+        let backend_config = &*BACKEND_CONFIG;
+        match backend_config.session_store.as_str() {
+            "memory" => Arc::new(MemorySessionStore),
+            "sqlite" => {
+                let store = SqliteSessionStore::open(&backend_config.session_store_path)
+                    .expect("Failed to open session store");
+                Arc::new(store)
+            }
+            other => panic!("Unknown session store: {}", other),
+        }
+    }
+
+    /// Stored shares keep their codes from new sessions for as long as they exist.
+    fn create_file_store(session_manager: &Arc<RwLock<SessionManager>>) -> Option<Arc<FileStore>> {
+        let backend_config = &*BACKEND_CONFIG;
+        if !backend_config.store_enabled {
+            return None;
+        }
+
+        let store_ttl = Duration::from_secs(backend_config.store_ttl_secs);
+        let file_store = FileStore::open(&backend_config.store_path, backend_config.store_quota_bytes, store_ttl)
+            .expect("Failed to open file store");
+        let mut session_manager = session_manager.write().unwrap();
+        for code in file_store.codes() {
+            session_manager.reserve_code(&code);
+        }
+        Some(Arc::new(file_store))
+    }
+
+    fn create_rate_limiter() -> Arc<RateLimiter> {
+        let backend_config = &*BACKEND_CONFIG;
+        let window = Duration::from_secs(backend_config.rate_limit_window_secs);
+        Arc::new(RateLimiter::new(window, backend_config.rate_limit_max_requests))
+    }
+
+    fn spawn_session_gc(session_manager: Arc<RwLock<SessionManager>>, rate_limiter: Arc<RateLimiter>, file_store: Option<Arc<FileStore>>) {
+        let gc_interval = Duration::from_secs(BACKEND_CONFIG.session_gc_interval_secs);
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(gc_interval);
+            loop {
+                interval.tick().await;
+                rate_limiter.remove_stale();
+                let expired_shares = match &file_store {
+                    Some(file_store) => file_store.remove_expired(),
+                    None => Vec::new(),
+                };
+
+                let expired = match session_manager.write() {
+                    Ok(mut session_manager) => {
+                        for code in &expired_shares {
+                            session_manager.release_code(code);
+                        }
+                        session_manager.remove_expired_sessions()
+                    }
+                    Err(_) => continue,
+                };
+
+                for condvar_details in expired {
+                    push_signal(&condvar_details, SessionSignal::Expired).await;
+                }
+            }
+        });
+    }
+
+    #[cfg(not(feature = "embed-assets"))]
+    fn page_routes() -> Vec<rocket::Route> {
+        routes![
+            static_files::root,
+            static_files::public_file,
+            static_files::catch_all
+        ]
+    }
+
+    #[cfg(feature = "embed-assets")]
+    fn page_routes() -> Vec<rocket::Route> {
+        routes![
+            embedded::root,
+            embedded::catch_all
+        ]
+    }
+
     fn create_routes() -> Vec<rocket::Route>{
         let backend_config = &*BACKEND_CONFIG;
-        let mut routes: Vec<rocket::Route> = Vec::new();
+        // Probes and the version stay reachable whatever else is turned off
+        let mut routes: Vec<rocket::Route> = routes![
+            healthz,
+            readyz,
+            version
+        ];
 
-        if backend_config.web_serve_page == "true" {
-            let routes_page: Vec<rocket::Route> = routes![
-                root,
-                catch_all
-            ];
-            routes.extend(routes_page);
+        if backend_config.web_serve_page {
+            routes.extend(page_routes());
         }
-        if backend_config.web_serve_api == "true" {
+        if backend_config.web_serve_page && backend_config.web_serve_api {
+            let routes_docs: Vec<rocket::Route> = Redoc::with_url("/api/docs", ApiDoc::openapi()).into();
+            routes.extend(routes_docs);
+        }
+        if backend_config.web_serve_api {
             let routes_api: Vec<rocket::Route> = routes![
                 create_session,
                 poll_session,
+                update_session,
+                close_session,
                 get_session_details,
-                join_session
+                join_session,
+                host_channel,
+                client_channel,
+                api::create_session,
+                api::poll_session,
+                api::update_session,
+                api::close_session,
+                api::get_session_details,
+                api::join_session,
+                ice_servers,
+                openapi
             ];
             routes.extend(routes_api);
         }
+        if backend_config.web_serve_api && backend_config.relay_enabled {
+            let routes_relay: Vec<rocket::Route> = routes![
+                host_relay,
+                client_relay
+            ];
+            routes.extend(routes_relay);
+        }
+        if backend_config.web_serve_api && backend_config.store_enabled {
+            let routes_store: Vec<rocket::Route> = routes![
+                create_stored_file,
+                upload_stored_chunk,
+                complete_stored_file,
+                delete_stored_share,
+                get_stored_share,
+                download_stored_chunk
+            ];
+            routes.extend(routes_store);
+        }
+        if backend_config.web_serve_admin {
+            let routes_admin: Vec<rocket::Route> = routes![
+                admin_list_sessions,
+                admin_close_session,
+                admin_purge_sessions
+            ];
+            routes.extend(routes_admin);
+        }
+        if backend_config.web_serve_metrics {
+            let routes_metrics: Vec<rocket::Route> = routes![
+                metrics
+            ];
+            routes.extend(routes_metrics);
+        }
         routes
     }
 
-    fn create_config() -> Config {
+    pub fn create_tls_config(backend_config: &BackendConfig) -> Option<TlsConfig> {
+        if !backend_config.web_tls_enabled {
+            return None;
+        }
+
+        let mut tls_config = TlsConfig::from_paths(
+            backend_config.web_tls_certs.clone(),
+            backend_config.web_tls_key.clone()
+        );
+        if !backend_config.web_tls_ca_cert.is_empty() {
+            tls_config = tls_config.with_mutual(MutualTls::from_path(backend_config.web_tls_ca_cert.clone()));
+        }
+        Some(tls_config)
+    }
+
+    fn create_config(tls_config: Option<TlsConfig>) -> Config {
         let backend_config = &*BACKEND_CONFIG;
         let web_bind_addr = backend_config.web_bind_addr.clone();
         let web_bind_addr: IpAddr = web_bind_addr.parse().expect("Invalid IP address");
         let web_port = backend_config.web_port;
-        let web_tls_enabled = backend_config.web_tls_enabled == "true";
-
-        let config_tls = {
-            if web_tls_enabled {
-                let tls_config = TlsConfig::from_paths(
-                    backend_config.web_tls_certs.clone(),
-                    backend_config.web_tls_key.clone()
-                ).with_mutual(MutualTls::from_path(backend_config.web_tls_ca_cert.clone()));
-                Some(tls_config)
-            } else {
-                None
-            }
+        // Client addresses feed rate limits and purges, a forwarding header is only trusted when
+        // configured because anyone can send one
+        let ip_header = match backend_config.web_ip_header.is_empty() {
+            true => None,
+            false => Some(Uncased::from(backend_config.web_ip_header.clone())),
         };
 
-        let config = Config {
+        Config {
             address: web_bind_addr,
             port: web_port,
-            tls: config_tls,
+            tls: tls_config,
+            ip_header,
+            // Rocket's own logger stays off, its records reach the tracing subscriber instead
+            log_level: LogLevel::Off,
 
             ..Config::default()
-        };
-
-        config
+        }
     }
 }
@@ -0,0 +1,56 @@
+use reqwest::Response;
+
+use crate::shared::{ApiError, HostCreate, HostCreateResult, IceServer, IceServers};
+use crate::Result;
+
+/// Without the server's list peers still connect where host candidates suffice, like on a LAN.
+pub async fn get_ice_servers(server: &str) -> Vec<IceServer> {
+    let ice_servers = async {
+        let response = reqwest::get(format!("{}/api/ice-servers", server)).await?;
+        let ice_servers: IceServers = check_status(response).await?.json().await?;
+        Result::Ok(ice_servers.ice_servers)
+    };
+
+    match ice_servers.await {
+        Ok(ice_servers) => ice_servers,
+        Err(error) => {
+            eprintln!("Error fetching ICE servers: {}", error);
+            Vec::new()
+        }
+    }
+}
+
+pub async fn create_session(server: &str, connection_details: String, password: String, compression_level: u8) -> Result<HostCreateResult> {
+    let session_create = HostCreate {
+        connection_details,
+        compression_level,
+        password,
+    };
+    let response = reqwest::Client::new()
+        .post(format!("{}/api/v2/sessions", server))
+        .json(&session_create)
+        .send()
+        .await?;
+    Ok(check_status(response).await?.json().await?)
+}
+
+/// The WebSocket address of a signaling path, on the same host as the REST API.
+pub fn signaling_url(server: &str, path: &str) -> String {
+    let server = match server.split_once("://") {
+        Some(("https", address)) => format!("wss://{}", address),
+        Some((_, address)) => format!("ws://{}", address),
+        None => format!("ws://{}", server),
+    };
+    server + path
+}
+
+async fn check_status(response: Response) -> Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    match response.json::<ApiError>().await {
+        Ok(error) => Err(format!("{} ({})", error.message, status.as_u16()).into()),
+        Err(_) => Err(format!("request failed with status {}", status.as_u16()).into()),
+    }
+}
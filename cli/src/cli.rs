@@ -0,0 +1,45 @@
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+
+#[derive(Parser)]
+#[command(name = "file-link", version, about = "Send and receive files with File Link from the terminal")]
+pub struct Cli {
+    /// File Link server to connect through
+    #[arg(long, env = "FILE_LINK_SERVER", default_value = "http://127.0.0.1:8000", global = true)]
+    pub server: String,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Shares files until interrupted, printing the code and link for the recipients
+    Send {
+        /// Files to share
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+
+        /// Password recipients have to enter
+        #[arg(long, default_value = "")]
+        password: String,
+
+        /// zstd level the chunks are compressed with
+        #[arg(long, default_value_t = 9, value_parser = clap::value_parser!(u8).range(0..=10))]
+        compression: u8,
+    },
+    /// Downloads every file of a session, given its code or share link
+    Receive {
+        /// Session code or share link
+        code: String,
+
+        /// Password of the session, if it has one
+        #[arg(long, default_value = "")]
+        password: String,
+
+        /// Directory the files are saved to
+        #[arg(long, short, default_value = ".")]
+        output: PathBuf,
+    },
+}
@@ -0,0 +1,39 @@
+mod api;
+mod cli;
+mod peer;
+mod receive;
+mod send;
+mod signaling;
+
+#[path = "../../frontend/src/wrtc_protocol.rs"]
+mod wrtc_protocol;
+
+use std::process;
+
+use clap::Parser;
+
+use cli::{Cli, Command};
+
+pub mod shared {
+    use serde::{Deserialize, Serialize};
+    use utoipa::ToSchema;
+    include!("../../shared/api_protocol.rs");
+}
+
+pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    let server = cli.server.trim_end_matches('/').to_string();
+
+    let result = match cli.command {
+        Command::Send { paths, password, compression } => send::run(&server, paths, password, compression).await,
+        Command::Receive { code, password, output } => receive::run(&server, &code, password, output).await,
+    };
+
+    if let Err(error) = result {
+        eprintln!("Error: {}", error);
+        process::exit(1);
+    }
+}
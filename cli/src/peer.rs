@@ -0,0 +1,127 @@
+use std::sync::Arc;
+
+use base64::{engine::general_purpose, Engine};
+use serde::{Deserialize, Serialize};
+use webrtc::api::interceptor_registry::register_default_interceptors;
+use webrtc::api::media_engine::MediaEngine;
+use webrtc::api::APIBuilder;
+use webrtc::ice_transport::ice_candidate::RTCIceCandidateInit;
+use webrtc::ice_transport::ice_server::RTCIceServer;
+use webrtc::interceptor::registry::Registry;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::peer_connection::RTCPeerConnection;
+
+use crate::shared::IceServer;
+use crate::Result;
+
+/// Chunk size the browser host reads files with, before compression.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// `connection_details` as the frontend encodes them: base64 JSON of the session description,
+/// itself JSON like `RTCSessionDescriptionInit`, and the candidates gathered alongside it.
+#[derive(Serialize, Deserialize)]
+struct IceOfferBundle {
+    ice_candidates: Vec<IceCandidate>,
+    offer: String,
+}
+
+/// A trickled candidate, in the frontend's field naming.
+#[derive(Serialize, Deserialize)]
+struct IceCandidate {
+    candidate: String,
+    sdp_mid: String,
+    sdp_m_line_index: u16,
+}
+
+pub async fn new_peer_connection(ice_servers: &[IceServer]) -> Result<Arc<RTCPeerConnection>> {
+    let mut media_engine = MediaEngine::default();
+    media_engine.register_default_codecs()?;
+    let registry = register_default_interceptors(Registry::new(), &mut media_engine)?;
+    let api = APIBuilder::new()
+        .with_media_engine(media_engine)
+        .with_interceptor_registry(registry)
+        .build();
+
+    let ice_servers = ice_servers
+        .iter()
+        .map(|ice_server| RTCIceServer {
+            urls: ice_server.urls.clone(),
+            username: ice_server.username.clone().unwrap_or_default(),
+            credential: ice_server.credential.clone().unwrap_or_default(),
+            ..Default::default()
+        })
+        .collect();
+    let configuration = RTCConfiguration {
+        ice_servers,
+        ..Default::default()
+    };
+    Ok(Arc::new(api.new_peer_connection(configuration).await?))
+}
+
+/// Creates the host's offer. Gathering completes first, so the description carries every
+/// candidate and nothing needs to be trickled afterwards.
+pub async fn create_offer(peer_connection: &RTCPeerConnection) -> Result<String> {
+    let offer = peer_connection.create_offer(None).await?;
+    set_local_description(peer_connection, offer).await
+}
+
+/// Applies a client's answer to the host's offer.
+pub async fn accept_answer(peer_connection: &RTCPeerConnection, connection_details: &str) -> Result<()> {
+    let bundle = decode(connection_details)?;
+    let answer = serde_json::from_str::<RTCSessionDescription>(&bundle.offer)?;
+    peer_connection.set_remote_description(answer).await?;
+    add_candidates(peer_connection, bundle.ice_candidates).await
+}
+
+/// Applies the host's offer and returns the client's answer to it.
+pub async fn accept_offer(peer_connection: &RTCPeerConnection, connection_details: &str) -> Result<String> {
+    let bundle = decode(connection_details)?;
+    let offer = serde_json::from_str::<RTCSessionDescription>(&bundle.offer)?;
+    peer_connection.set_remote_description(offer).await?;
+    add_candidates(peer_connection, bundle.ice_candidates).await?;
+
+    let answer = peer_connection.create_answer(None).await?;
+    set_local_description(peer_connection, answer).await
+}
+
+/// Applies a candidate the remote peer trickled through the signaling channel.
+pub async fn add_candidate(peer_connection: &RTCPeerConnection, candidate: &str) -> Result<()> {
+    let candidate = serde_json::from_str::<IceCandidate>(candidate)?;
+    add_candidates(peer_connection, vec![candidate]).await
+}
+
+async fn add_candidates(peer_connection: &RTCPeerConnection, candidates: Vec<IceCandidate>) -> Result<()> {
+    for candidate in candidates {
+        peer_connection
+            .add_ice_candidate(RTCIceCandidateInit {
+                candidate: candidate.candidate,
+                sdp_mid: Some(candidate.sdp_mid),
+                sdp_mline_index: Some(candidate.sdp_m_line_index),
+                username_fragment: None,
+            })
+            .await?;
+    }
+    Ok(())
+}
+
+async fn set_local_description(peer_connection: &RTCPeerConnection, description: RTCSessionDescription) -> Result<String> {
+    let mut gathering_complete = peer_connection.gathering_complete_promise().await;
+    peer_connection.set_local_description(description).await?;
+    let _ = gathering_complete.recv().await;
+
+    let description = peer_connection
+        .local_description()
+        .await
+        .ok_or("no local description after gathering")?;
+    let bundle = IceOfferBundle {
+        ice_candidates: Vec::new(),
+        offer: serde_json::to_string(&description)?,
+    };
+    Ok(general_purpose::STANDARD.encode(serde_json::to_string(&bundle)?))
+}
+
+fn decode(connection_details: &str) -> Result<IceOfferBundle> {
+    let bundle = general_purpose::STANDARD.decode(connection_details)?;
+    Ok(serde_json::from_slice(&bundle)?)
+}
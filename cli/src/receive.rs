@@ -0,0 +1,264 @@
+use std::collections::{HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc::{self, UnboundedSender};
+use uuid::Uuid;
+use webrtc::data_channel::data_channel_message::DataChannelMessage;
+use webrtc::data_channel::RTCDataChannel;
+use webrtc::ice_transport::ice_connection_state::RTCIceConnectionState;
+use webrtc::peer_connection::RTCPeerConnection;
+
+use crate::peer;
+use crate::shared::{IceServer, SignalingMessage};
+use crate::signaling::Signaling;
+use crate::wrtc_protocol::{FileInfo, FileRequest, FilesUpdate};
+use crate::{api, Result};
+
+const STATUS_UNAUTHORIZED: u16 = 401;
+const STATUS_OFFER_TAKEN: u16 = 409;
+
+/// What the data channels report back to the signaling loop.
+enum ReceiveEvent {
+    MessageChannel(Arc<RTCDataChannel>),
+    Files(FilesUpdate),
+    Chunk(Bytes),
+    Disconnected,
+}
+
+/// Joins a session like the browser client and downloads every file the host shares, one at a time.
+pub async fn run(server: &str, target: &str, password: String, output: PathBuf) -> Result<()> {
+    let (server, code) = parse_target(server, target);
+    let ice_servers = api::get_ice_servers(&server).await;
+    let (events, mut event_receiver) = mpsc::unbounded_channel();
+
+    let url = api::signaling_url(&server, &format!("/api/sessions/{}/signaling", code));
+    let mut signaling = Signaling::connect(&url, None).await?;
+    signaling.send(&SignalingMessage::Join { password }).await?;
+
+    let mut signaling_open = true;
+    let mut connection: Option<(u32, Arc<RTCPeerConnection>)> = None;
+    let mut message_channel: Option<Arc<RTCDataChannel>> = None;
+    let mut downloads = Downloads::new(output);
+
+    loop {
+        tokio::select! {
+            message = signaling.next(), if signaling_open => match message {
+                Some(SignalingMessage::Offer { offer_id, connection_details }) => {
+                    // The previous offer went to another client, answer the new one instead
+                    if let Some((_, peer_connection)) = connection.take() {
+                        let _ = peer_connection.close().await;
+                    }
+                    let peer_connection = start_peer(&ice_servers, &events).await?;
+                    let connection_details = peer::accept_offer(&peer_connection, &connection_details).await?;
+                    signaling.send(&SignalingMessage::Answer { offer_id, connection_details }).await?;
+                    connection = Some((offer_id, peer_connection));
+                }
+                Some(SignalingMessage::Candidate { offer_id, candidate }) => {
+                    if let Some((_, peer_connection)) = connection.as_ref().filter(|(id, _)| *id == offer_id) {
+                        if let Err(error) = peer::add_candidate(peer_connection, &candidate).await {
+                            eprintln!("Error adding candidate: {}", error);
+                        }
+                    }
+                }
+                Some(SignalingMessage::Joined(_)) => println!("Joined session {}, connecting to the host", code),
+                Some(SignalingMessage::Error { status: STATUS_OFFER_TAKEN }) => {
+                    // The server pushes the next offer as soon as the host publishes it
+                    if let Some((_, peer_connection)) = connection.take() {
+                        let _ = peer_connection.close().await;
+                    }
+                }
+                Some(SignalingMessage::Error { status: STATUS_UNAUTHORIZED }) => {
+                    return Err("wrong or missing password, pass it with --password".into());
+                }
+                Some(SignalingMessage::Error { status }) => return Err(format!("signaling error {}", status).into()),
+                Some(SignalingMessage::Close) | None if message_channel.is_none() => return Err("session closed".into()),
+                // Once connected the files keep coming without the server
+                Some(SignalingMessage::Close) | None => signaling_open = false,
+                Some(_) => {}
+            },
+            event = event_receiver.recv() => match event {
+                Some(ReceiveEvent::MessageChannel(channel)) => message_channel = Some(channel),
+                Some(ReceiveEvent::Files(update)) => {
+                    downloads.enqueue(update.files);
+                    downloads.start_next(message_channel.as_deref()).await?;
+                }
+                Some(ReceiveEvent::Chunk(data)) => {
+                    let file_complete = downloads.write_chunk(&data).await?;
+                    if file_complete {
+                        downloads.start_next(message_channel.as_deref()).await?;
+                    }
+                }
+                Some(ReceiveEvent::Disconnected) => return Err("connection to the host lost".into()),
+                None => {}
+            },
+        }
+
+        if downloads.finished() {
+            break;
+        }
+    }
+
+    if let Some((_, peer_connection)) = connection {
+        let _ = peer_connection.close().await;
+    }
+    println!("Received {} file(s)", downloads.saved);
+    Ok(())
+}
+
+/// Accepts a bare code or a share link, whose server then takes precedence over `--server`.
+fn parse_target(server: &str, target: &str) -> (String, String) {
+    match target.split_once("/receive/") {
+        Some((link_server, code)) => {
+            let code = code.split(['#', '?', '/']).next().unwrap_or_default();
+            (link_server.to_string(), code.to_string())
+        }
+        None => (server.to_string(), target.to_string()),
+    }
+}
+
+/// The host opens the channels, so they are picked up as they arrive.
+async fn start_peer(ice_servers: &[IceServer], events: &UnboundedSender<ReceiveEvent>) -> Result<Arc<RTCPeerConnection>> {
+    let peer_connection = peer::new_peer_connection(ice_servers).await?;
+
+    let channel_events = events.clone();
+    peer_connection.on_data_channel(Box::new(move |data_channel: Arc<RTCDataChannel>| {
+        let events = channel_events.clone();
+        match data_channel.label() {
+            "message" => {
+                let _ = events.send(ReceiveEvent::MessageChannel(data_channel.clone()));
+                data_channel.on_message(Box::new(move |message: DataChannelMessage| {
+                    if let Ok(update) = serde_json::from_slice::<FilesUpdate>(&message.data) {
+                        let _ = events.send(ReceiveEvent::Files(update));
+                    }
+                    Box::pin(async {})
+                }));
+            }
+            "data" => {
+                data_channel.on_message(Box::new(move |message: DataChannelMessage| {
+                    let _ = events.send(ReceiveEvent::Chunk(message.data));
+                    Box::pin(async {})
+                }));
+            }
+            _ => {}
+        }
+        Box::pin(async {})
+    }));
+
+    let state_events = events.clone();
+    peer_connection.on_ice_connection_state_change(Box::new(move |state: RTCIceConnectionState| {
+        if state == RTCIceConnectionState::Failed {
+            let _ = state_events.send(ReceiveEvent::Disconnected);
+        }
+        Box::pin(async {})
+    }));
+
+    Ok(peer_connection)
+}
+
+/// The file being received and those still to request.
+struct Downloads {
+    output: PathBuf,
+    queue: VecDeque<FileInfo>,
+    requested: HashSet<Uuid>,
+    current: Option<CurrentFile>,
+    saved: usize,
+}
+
+struct CurrentFile {
+    info: FileInfo,
+    path: PathBuf,
+    file: File,
+    received: u64,
+}
+
+impl Downloads {
+    fn new(output: PathBuf) -> Self {
+        Self {
+            output,
+            queue: VecDeque::new(),
+            requested: HashSet::new(),
+            current: None,
+            saved: 0,
+        }
+    }
+
+    fn enqueue(&mut self, files: Vec<FileInfo>) {
+        for file in files {
+            if self.requested.insert(file.uuid) {
+                self.queue.push_back(file);
+            }
+        }
+    }
+
+    /// Requests the next file unless one is still arriving. Empty files have no chunks and
+    /// are created right away.
+    async fn start_next(&mut self, message_channel: Option<&RTCDataChannel>) -> Result<()> {
+        let message_channel = match message_channel {
+            Some(message_channel) if self.current.is_none() => message_channel,
+            _ => return Ok(()),
+        };
+
+        while let Some(info) = self.queue.pop_front() {
+            let path = unique_path(&self.output, &info.name);
+            let file = File::create(&path).await?;
+            if info.size as u64 == 0 {
+                println!("Saved {}", path.display());
+                self.saved += 1;
+                continue;
+            }
+
+            println!("Receiving {} ({} bytes)", info.name, info.size as u64);
+            let request = FileRequest { uuid: info.uuid };
+            message_channel.send_text(serde_json::to_string(&request)?).await?;
+            self.current = Some(CurrentFile { info, path, file, received: 0 });
+            break;
+        }
+        Ok(())
+    }
+
+    /// Chunks arrive in the browser's framing, each compressed on its own. Returns whether the
+    /// current file is complete.
+    async fn write_chunk(&mut self, data: &[u8]) -> Result<bool> {
+        let current = match self.current.as_mut() {
+            Some(current) => current,
+            None => return Ok(false),
+        };
+
+        let data = zstd::stream::decode_all(data)?;
+        current.file.write_all(&data).await?;
+        current.received += data.len() as u64;
+        if current.received < current.info.size as u64 {
+            return Ok(false);
+        }
+
+        current.file.flush().await?;
+        println!("Saved {}", current.path.display());
+        self.current = None;
+        self.saved += 1;
+        Ok(true)
+    }
+
+    fn finished(&self) -> bool {
+        self.current.is_none() && self.queue.is_empty() && !self.requested.is_empty()
+    }
+}
+
+/// Only the file name of what the host sent counts, and existing files are never overwritten.
+fn unique_path(output: &Path, name: &str) -> PathBuf {
+    let name = Path::new(name)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| "file".to_string());
+
+    let mut path = output.join(&name);
+    let mut counter = 1;
+    while path.exists() {
+        path = output.join(format!("{} ({})", name, counter));
+        counter += 1;
+    }
+    path
+}
@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use tokio::fs::File;
+use tokio::io::AsyncReadExt;
+use tokio::sync::mpsc::{self, UnboundedSender};
+use uuid::Uuid;
+use webrtc::data_channel::data_channel_init::RTCDataChannelInit;
+use webrtc::data_channel::data_channel_message::DataChannelMessage;
+use webrtc::data_channel::RTCDataChannel;
+use webrtc::ice_transport::ice_connection_state::RTCIceConnectionState;
+use webrtc::peer_connection::RTCPeerConnection;
+
+use crate::peer::{self, CHUNK_SIZE};
+use crate::shared::{IceServer, SignalingMessage};
+use crate::signaling::Signaling;
+use crate::wrtc_protocol::{FileInfo, FileRequest, FilesUpdate};
+use crate::{api, Result};
+
+/// Data channel bytes queued before the sender waits for the peer to catch up.
+const MAX_BUFFERED_AMOUNT: usize = 1024 * 1024;
+
+struct SharedFile {
+    info: FileInfo,
+    path: PathBuf,
+}
+
+/// What the peers report back to the signaling loop, for printing.
+enum PeerEvent {
+    Connected(u32),
+    Disconnected(u32),
+    Sent(u32, String),
+    Failed(u32, String, String),
+}
+
+/// Shares the files with every client joining the session, each over its own peer connection,
+/// until interrupted or the session expires.
+pub async fn run(server: &str, paths: Vec<PathBuf>, password: String, compression_level: u8) -> Result<()> {
+    let files = Arc::new(shared_files(paths)?);
+    let ice_servers = api::get_ice_servers(server).await;
+    let (events, mut event_receiver) = mpsc::unbounded_channel();
+
+    // Like in the browser one connection always waits with a published offer for the next client
+    let mut next_client = 1;
+    let mut pending = start_peer(&ice_servers, &files, compression_level, next_client, &events).await?;
+    let offer = peer::create_offer(&pending).await?;
+    let session = api::create_session(server, offer, password, compression_level).await?;
+    let mut pending_offer_id = Some(session.offer_id);
+    let mut peers: HashMap<u32, Arc<RTCPeerConnection>> = HashMap::new();
+
+    println!("Code: {}", session.code);
+    println!("Link: {}/receive/{}", server, session.code);
+
+    let url = api::signaling_url(server, &format!("/api/sessions/{}/signaling/host", session.code));
+    let mut signaling = Signaling::connect(&url, Some(&session.owner_token)).await?;
+
+    // Created once, a Ctrl+C between two turns of the loop is not lost
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+    loop {
+        tokio::select! {
+            message = signaling.next() => match message {
+                Some(SignalingMessage::Offer { offer_id, .. }) => pending_offer_id = Some(offer_id),
+                Some(SignalingMessage::Answer { offer_id, connection_details }) if pending_offer_id == Some(offer_id) => {
+                    let accepted = peer::accept_answer(&pending, &connection_details).await;
+
+                    // The server counts the offer as taken either way, so the next client needs a fresh one
+                    next_client += 1;
+                    let next = start_peer(&ice_servers, &files, compression_level, next_client, &events).await?;
+                    let answered = std::mem::replace(&mut pending, next);
+                    match accepted {
+                        Ok(()) => {
+                            peers.insert(offer_id, answered);
+                        }
+                        Err(error) => {
+                            eprintln!("Error accepting answer: {}", error);
+                            let _ = answered.close().await;
+                        }
+                    }
+                    pending_offer_id = None;
+
+                    let connection_details = peer::create_offer(&pending).await?;
+                    signaling.send(&SignalingMessage::Offer { offer_id: 0, connection_details }).await?;
+                }
+                Some(SignalingMessage::Candidate { offer_id, candidate }) => {
+                    let peer_connection = match peers.get(&offer_id) {
+                        Some(peer_connection) => peer_connection,
+                        None if pending_offer_id == Some(offer_id) => &pending,
+                        None => continue,
+                    };
+                    if let Err(error) = peer::add_candidate(peer_connection, &candidate).await {
+                        eprintln!("Error adding candidate: {}", error);
+                    }
+                }
+                Some(SignalingMessage::Leave { offer_id }) => {
+                    if let Some(peer_connection) = peers.remove(&offer_id) {
+                        let _ = peer_connection.close().await;
+                    }
+                }
+                Some(SignalingMessage::Expired) => {
+                    println!("Session expired");
+                    break;
+                }
+                Some(SignalingMessage::Close) => {
+                    println!("Session closed");
+                    break;
+                }
+                Some(SignalingMessage::Error { status }) => eprintln!("Signaling error: {}", status),
+                Some(_) => {}
+                None => return Err("signaling connection lost".into()),
+            },
+            event = event_receiver.recv() => match event {
+                Some(PeerEvent::Connected(client)) => println!("Client {} connected", client),
+                Some(PeerEvent::Disconnected(client)) => println!("Client {} disconnected", client),
+                Some(PeerEvent::Sent(client, name)) => println!("Client {}: sent {}", client, name),
+                Some(PeerEvent::Failed(client, name, error)) => eprintln!("Client {}: sending {} failed: {}", client, name, error),
+                None => {}
+            },
+            _ = &mut ctrl_c => {
+                signaling.send(&SignalingMessage::Close).await?;
+                break;
+            }
+        }
+    }
+
+    for peer_connection in peers.values().chain(std::iter::once(&pending)) {
+        let _ = peer_connection.close().await;
+    }
+    Ok(())
+}
+
+fn shared_files(paths: Vec<PathBuf>) -> Result<Vec<SharedFile>> {
+    paths
+        .into_iter()
+        .map(|path| {
+            let metadata = std::fs::metadata(&path).map_err(|error| format!("{}: {}", path.display(), error))?;
+            if !metadata.is_file() {
+                return Err(format!("{}: not a file", path.display()).into());
+            }
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .ok_or_else(|| format!("{}: no file name", path.display()))?;
+
+            let info = FileInfo {
+                name,
+                uuid: Uuid::new_v4(),
+                size: metadata.len() as f64,
+            };
+            Ok(SharedFile { info, path })
+        })
+        .collect()
+}
+
+/// A peer connection with the channels the browser host opens: "data" for file chunks and
+/// "message" for the file list and the client's requests.
+async fn start_peer(
+    ice_servers: &[IceServer],
+    files: &Arc<Vec<SharedFile>>,
+    compression_level: u8,
+    client: u32,
+    events: &UnboundedSender<PeerEvent>,
+) -> Result<Arc<RTCPeerConnection>> {
+    let peer_connection = peer::new_peer_connection(ice_servers).await?;
+    let channel_init = || Some(RTCDataChannelInit { ordered: Some(true), ..Default::default() });
+    let data_channel = peer_connection.create_data_channel("data", channel_init()).await?;
+    let message_channel = peer_connection.create_data_channel("message", channel_init()).await?;
+
+    let opened_channel = message_channel.clone();
+    let opened_files = files.clone();
+    message_channel.on_open(Box::new(move || {
+        let message_channel = opened_channel.clone();
+        let files = opened_files.clone();
+        Box::pin(async move {
+            let update = FilesUpdate {
+                files: files.iter().map(|file| file.info.clone()).collect(),
+            };
+            let _ = message_channel.send_text(serde_json::to_string(&update).unwrap()).await;
+        })
+    }));
+
+    let files = files.clone();
+    let message_events = events.clone();
+    message_channel.on_message(Box::new(move |message: DataChannelMessage| {
+        let request = serde_json::from_slice::<FileRequest>(&message.data);
+        let file = request.ok().and_then(|request| files.iter().find(|file| file.info.uuid == request.uuid));
+        if let Some(file) = file {
+            let data_channel = data_channel.clone();
+            let path = file.path.clone();
+            let name = file.info.name.clone();
+            let events = message_events.clone();
+            tokio::spawn(async move {
+                let event = match send_file(&data_channel, path, compression_level).await {
+                    Ok(()) => PeerEvent::Sent(client, name),
+                    Err(error) => PeerEvent::Failed(client, name, error.to_string()),
+                };
+                let _ = events.send(event);
+            });
+        }
+        Box::pin(async {})
+    }));
+
+    let state_events = events.clone();
+    peer_connection.on_ice_connection_state_change(Box::new(move |state: RTCIceConnectionState| {
+        let event = match state {
+            RTCIceConnectionState::Connected => Some(PeerEvent::Connected(client)),
+            RTCIceConnectionState::Disconnected | RTCIceConnectionState::Failed => Some(PeerEvent::Disconnected(client)),
+            _ => None,
+        };
+        if let Some(event) = event {
+            let _ = state_events.send(event);
+        }
+        Box::pin(async {})
+    }));
+
+    Ok(peer_connection)
+}
+
+/// Sends a file in the browser's framing: every chunk compressed on its own with zstd.
+async fn send_file(data_channel: &RTCDataChannel, path: PathBuf, compression_level: u8) -> Result<()> {
+    let mut file = File::open(path).await?;
+    let mut chunk = vec![0u8; CHUNK_SIZE];
+    loop {
+        let read = file.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok(());
+        }
+
+        let compressed = zstd::stream::encode_all(&chunk[..read], compression_level as i32)?;
+        while data_channel.buffered_amount().await > MAX_BUFFERED_AMOUNT {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        data_channel.send(&Bytes::from(compressed)).await?;
+    }
+}
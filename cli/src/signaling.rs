@@ -0,0 +1,49 @@
+use futures::{SinkExt, StreamExt};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+use crate::shared::SignalingMessage;
+use crate::Result;
+
+/// The signaling WebSocket of a session, carrying `SignalingMessage`s as JSON text frames.
+pub struct Signaling {
+    stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+}
+
+impl Signaling {
+    /// Hosts authenticate with their owner token, clients send a `Join` message once connected.
+    pub async fn connect(url: &str, owner_token: Option<&str>) -> Result<Self> {
+        let mut request = url.into_client_request()?;
+        if let Some(owner_token) = owner_token {
+            let authorization = HeaderValue::from_str(&format!("Bearer {}", owner_token))?;
+            request.headers_mut().insert("Authorization", authorization);
+        }
+
+        let (stream, _) = tokio_tungstenite::connect_async(request).await?;
+        Ok(Self { stream })
+    }
+
+    pub async fn send(&mut self, message: &SignalingMessage) -> Result<()> {
+        let message = serde_json::to_string(message)?;
+        self.stream.send(Message::Text(message)).await?;
+        Ok(())
+    }
+
+    /// The next message from the server, `None` once the connection is closed.
+    pub async fn next(&mut self) -> Option<SignalingMessage> {
+        while let Some(message) = self.stream.next().await {
+            match message {
+                Ok(Message::Text(text)) => match serde_json::from_str::<SignalingMessage>(&text) {
+                    Ok(message) => return Some(message),
+                    Err(_) => continue,
+                },
+                Ok(Message::Close(_)) | Err(_) => return None,
+                Ok(_) => continue,
+            }
+        }
+        None
+    }
+}
@@ -13,8 +13,10 @@ mod constants;
 pub mod shared {
     use serde::{Deserialize, Serialize};
     use toml;
+    use utoipa::ToSchema;
     include!("../../shared/api_protocol.rs");
     include!("../../shared/config.rs");
+    include!("../../shared/session_code.rs");
 }
 
 #[function_component(Header)]
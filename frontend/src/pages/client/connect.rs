@@ -1,5 +1,7 @@
 use yew::prelude::*;
 
+use crate::shared::is_valid_session_code;
+
 pub enum Msg {
     SessionConnect,
 }
@@ -11,6 +13,7 @@ pub struct Props {
 
 pub struct Connect {
     input_code: NodeRef,
+    invalid_code: bool,
 }
 
 impl Component for Connect {
@@ -20,6 +23,7 @@ impl Component for Connect {
     fn create(_ctx: &Context<Self>) -> Self {
         Connect {
             input_code: NodeRef::default(),
+            invalid_code: false,
         }
     }
 
@@ -27,11 +31,15 @@ impl Component for Connect {
         match msg {
             Msg::SessionConnect => {
                 let input = self.input_code.cast::<web_sys::HtmlInputElement>().unwrap().value();
-                let code = Connect::extract_code_from_link(&input).unwrap_or(&input);
-                ctx.props().on_connect.emit(code.to_string());
+                let code = Connect::extract_code_from_link(&input).unwrap_or(&input).trim();
+                // The checksum in the code catches typos without asking the server
+                self.invalid_code = !is_valid_session_code(code);
+                if !self.invalid_code {
+                    ctx.props().on_connect.emit(code.to_string());
+                }
             }
         }
-        false
+        true
     }
 
     fn view(&self, ctx: &Context<Self>) -> Html {
@@ -46,6 +54,9 @@ impl Component for Connect {
                                 <button onclick={ctx.link().callback(|_| Msg::SessionConnect)} class="btn btn-outline-secondary" type="button">{"Connect"}</button>
                             </div>
                         </div>
+                        if self.invalid_code {
+                            <div class="text-danger mt-2">{"This link or code looks mistyped, please check it"}</div>
+                        }
                     </div>
                 </div>
             </div>
@@ -3,7 +3,9 @@ use std::collections::HashMap;
 use std::ops::Deref;
 use std::rc::Rc;
 
+use gloo::timers::callback::Timeout;
 use uuid::Uuid;
+use wasm_bindgen::JsValue;
 use web_sys::console;
 use yew::prelude::*;
 
@@ -12,13 +14,20 @@ use crate::pages::client::connect::Connect;
 use crate::pages::client::password::Password;
 use crate::services::api_service::{api_service, ApiServiceMessage};
 use crate::services::download_manager::DownloadManager;
+use crate::services::relay::RelayEndpoint;
+use crate::services::signaling::{SignalingService, SignalingServiceMessage};
+use crate::services::stored_share::{self, StoreKey};
 use crate::services::web_rtc::{ConnectionState, State, WebRTCManager, WebRtcMessage};
-use crate::shared::ClientJoinResult;
+use crate::shared::{ClientJoinResult, IceServer, SignalingMessage, StoredShare};
 use crate::wrtc_protocol::{FileRequest, FilesUpdate};
 
 mod connect;
 mod password;
 
+const STATUS_UNAUTHORIZED: u16 = 401;
+const STATUS_OFFER_TAKEN: u16 = 409;
+const OFFER_RETRY_DELAY_MS: u32 = 1000;
+
 pub struct FileItem {
     state: FileState,
     tag: FileTag,
@@ -33,11 +42,16 @@ pub enum ClientState {
 
 pub enum Msg {
     SessionConnect(String, Option<String>),
+    SessionRetry,
     FileAccept(FileTag),
     FileDownload(FileTag),
+    StoredShare(Result<(StoreKey, StoredShare), u16>),
+    StoredChunk(JsValue, u32),
+    StoredFailed(FileTag),
 
     CallbackWebRtc(WebRtcMessage),
     CallbackApi(ApiServiceMessage),
+    CallbackSignaling(SignalingServiceMessage),
 }
 
 #[derive(Properties, PartialEq)]
@@ -53,9 +67,19 @@ pub struct Client {
     files: HashMap<Uuid, FileItem>,
     session_details: Option<ClientJoinResult>,
     session_code: Option<String>,
+    offer_id: Option<u32>,
     password: Option<String>,
     password_needed: bool,
     fetchin_file: Option<FileTag>,
+    retry_timeout: Option<Timeout>,
+    signaling: Option<SignalingService>,
+    answer_sent: bool,
+    candidates_sent: usize,
+    ice_servers: Option<Vec<IceServer>>,
+    relay_timeout_secs: u64,
+    store_key: Option<String>,
+    store_checked: bool,
+    stored: Option<(StoreKey, StoredShare)>,
 }
 
 impl Component for Client {
@@ -63,44 +87,82 @@ impl Component for Client {
     type Properties = ReceiveProps;
 
     fn create(ctx: &Context<Self>) -> Self {
-        //Direct connect if code is provided
         let code = if !ctx.props().code.is_empty() {
-            let callback: Callback<ApiServiceMessage> = ctx.link().callback(Msg::CallbackApi);
-            api_service::get_session_details(callback, &ctx.props().code, None);
             Some(ctx.props().code.clone())
         } else {
             None
         };
 
-        Self {
+        // The key of stored files is in the fragment of the share link, the server never sees it
+        let store_key = web_sys::window()
+            .and_then(|window| window.location().hash().ok())
+            .map(|hash| hash.trim_start_matches('#').to_string())
+            .filter(|hash| !hash.is_empty());
+
+        let client = Self {
             download_manager: DownloadManager::new(),
             web_rtc_manager: WebRTCManager::new(ctx.link().callback(Msg::CallbackWebRtc)),
             web_rtc_state: ConnectionState::new(),
             files: HashMap::new(),
             session_details: None,
             session_code: code,
+            offer_id: None,
             password: None,
             password_needed: false,
             fetchin_file: None,
-        }
+            retry_timeout: None,
+            signaling: None,
+            answer_sent: false,
+            candidates_sent: 0,
+            ice_servers: None,
+            relay_timeout_secs: 0,
+            store_key,
+            store_checked: false,
+            stored: None,
+        };
+
+        //Direct connect if code is provided, once the ICE servers are known
+        api_service::get_ice_servers(ctx.link().callback(Msg::CallbackApi));
+        client
     }
 
     fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
         match msg {
             Msg::SessionConnect(code, password) => {
-                let callback: Callback<ApiServiceMessage> = ctx.link().callback(Msg::CallbackApi);
-                api_service::get_session_details(callback, &code, password.clone());
                 self.session_code = Some(code);
                 self.password = password;
+                self.store_checked = false;
+                self.connect_session(ctx);
                 true
             }
-            Msg::FileAccept(tag) => self.handle_file_accept(tag),
+            Msg::SessionRetry => {
+                self.retry_timeout = None;
+                if let Some(code) = self.session_code.clone() {
+                    let callback: Callback<ApiServiceMessage> = ctx.link().callback(Msg::CallbackApi);
+                    api_service::get_session_details(callback, &code, self.password.clone());
+                }
+                false
+            }
+            Msg::FileAccept(tag) => self.handle_file_accept(ctx, tag),
             Msg::FileDownload(tag) => {
                 self.download_manager.download(tag);
                 true
             }
+            Msg::StoredShare(result) => self.handle_stored_share(ctx, result),
+            Msg::StoredChunk(data, size) => self.on_chunk(ctx, data, size),
+            Msg::StoredFailed(tag) => {
+                console::log_1(&format!("Error downloading stored file {}", tag.name()).into());
+                self.download_manager.cancel();
+                if let Some(file) = self.files.get_mut(&tag.uuid()) {
+                    file.state = FileState::Pending;
+                    file.progress = 0.0;
+                }
+                self.accept_next_queued(ctx);
+                true
+            }
             Msg::CallbackWebRtc(msg) => self.update_web_rtc(ctx, msg),
             Msg::CallbackApi(msg) => self.update_api(ctx, msg),
+            Msg::CallbackSignaling(msg) => self.update_signaling(ctx, msg),
         }
     }
 
@@ -119,7 +181,7 @@ impl Component for Client {
 }
 
 impl Client {
-    fn handle_file_accept(&mut self, file_tag: FileTag) -> bool {
+    fn handle_file_accept(&mut self, ctx: &Context<Self>, file_tag: FileTag) -> bool {
         let file_item: Option<&mut FileItem> = self.files.get_mut(&file_tag.uuid());
         let file_item = match file_item {
             Some(item) => item,
@@ -136,6 +198,11 @@ impl Client {
         file_item.state = FileState::Transferring;
         self.fetchin_file = Some(file_tag.clone());
 
+        if let Some((store_key, share)) = self.stored.clone() {
+            self.download_stored_file(ctx, store_key, share, file_tag);
+            return true;
+        }
+
         let file_request = FileRequest {
             uuid: file_tag.uuid(),
         };
@@ -146,6 +213,102 @@ impl Client {
         true
     }
 
+    fn on_chunk(&mut self, ctx: &Context<Self>, data: JsValue, size: u32) -> bool {
+        let result = self.download_manager.save_chunk(&data, size);
+
+        if result.is_err() {
+            console::log_1(
+                &format!("Error saving chunk: {:?}", result.clone().err().unwrap()).into(),
+            );
+            return false;
+        }
+
+        let file_tag = self.download_manager.get_file_tag();
+        let file = self.files.get_mut(&file_tag.unwrap().uuid());
+        let file = match file {
+            Some(file) => file,
+            None => return false,
+        };
+
+        if result.unwrap() {
+            file.state = FileState::Done;
+            file.progress = 100.0;
+            self.accept_next_queued(ctx);
+        } else {
+            file.state = FileState::Transferring;
+            file.progress = self.download_manager.get_progress();
+        }
+        true
+    }
+
+    fn accept_next_queued(&mut self, ctx: &Context<Self>) {
+        let queued = self.files.values().find(|file| file.state == FileState::Queued);
+        if let Some(file) = queued {
+            self.handle_file_accept(ctx, file.tag.clone());
+        }
+    }
+
+    /// Looks for files the host left on the server before connecting to the host itself.
+    fn fetch_stored_share(&mut self, ctx: &Context<Self>, code: String) {
+        let store_key = self.store_key.clone().unwrap_or_default();
+        let password = self.password.clone().unwrap_or_default();
+        let callback = ctx.link().callback(Msg::StoredShare);
+        self.store_checked = true;
+        wasm_bindgen_futures::spawn_local(async move {
+            let store_key = match StoreKey::import(&store_key).await {
+                Ok(store_key) => store_key,
+                Err(_) => return callback.emit(Err(400)),
+            };
+            let share = stored_share::fetch_share(&code, &password, &store_key).await;
+            callback.emit(share.map(|share| (store_key, share)));
+        });
+    }
+
+    fn handle_stored_share(&mut self, ctx: &Context<Self>, result: Result<(StoreKey, StoredShare), u16>) -> bool {
+        match result {
+            Ok((store_key, share)) if !share.files.is_empty() => {
+                for file in share.files.iter() {
+                    let uuid = match Uuid::parse_str(&file.uuid) {
+                        Ok(uuid) => uuid,
+                        Err(_) => continue,
+                    };
+                    self.files.insert(uuid, FileItem {
+                        state: FileState::Pending,
+                        tag: FileTag::new(file.name.clone(), file.size as f64, uuid),
+                        progress: 0.0,
+                    });
+                }
+                self.stored = Some((store_key, share));
+                true
+            }
+            Err(STATUS_UNAUTHORIZED) => {
+                self.password_needed = true;
+                true
+            }
+            // Nothing stored (yet), the host may still be online
+            _ => {
+                self.connect_session(ctx);
+                false
+            }
+        }
+    }
+
+    fn download_stored_file(&self, ctx: &Context<Self>, store_key: StoreKey, share: StoredShare, file_tag: FileTag) {
+        let file = match share.files.iter().find(|file| file.uuid == file_tag.uuid().to_string()) {
+            Some(file) => file.clone(),
+            None => return,
+        };
+        let code = self.session_code.clone().unwrap_or_default();
+        let callback_chunk = ctx.link().callback(|(data, size)| Msg::StoredChunk(data, size));
+        let callback_failed = ctx.link().callback(Msg::StoredFailed);
+        wasm_bindgen_futures::spawn_local(async move {
+            let result = stored_share::download_file(&code, &share, &store_key, &file, &callback_chunk).await;
+            if result.is_err() {
+                callback_failed.emit(file_tag);
+            }
+        });
+    }
+
     fn update_web_rtc(&mut self, ctx: &Context<Self>, msg: WebRtcMessage) -> bool {
         match msg {
             WebRtcMessage::Message(data) => {
@@ -156,37 +319,15 @@ impl Client {
                 return self.on_files_updates(update.unwrap());
             }
             WebRtcMessage::Data(data, size) => {
-                let result = self.download_manager.save_chunk(&data, size);
-
-                if result.is_err() {
-                    console::log_1(
-                        &format!("Error saving chunk: {:?}", result.clone().err().unwrap()).into(),
-                    );
-                    return false;
-                }
-
-                let file_tag = self.download_manager.get_file_tag();
-                let file = self.files.get_mut(&file_tag.unwrap().uuid());
-                let file = match file {
-                    Some(file) => file,
-                    None => return false,
-                };
-
-                if result.unwrap() {
-                    file.state = FileState::Done;
-                    file.progress = 100.0;
-                    for (_, file) in self.files.iter() {
-                        if file.state == FileState::Queued {
-                            self.handle_file_accept(file.tag.clone());
-                            break;
-                        }
-                    }
-                } else {
-                    file.state = FileState::Transferring;
-                    file.progress = self.download_manager.get_progress();
-                    return true;
-                }
-                return true;
+                return self.on_chunk(ctx, data, size);
+            }
+            WebRtcMessage::LocalDescription => {
+                self.send_answer();
+                return false;
+            }
+            WebRtcMessage::Candidate => {
+                self.send_candidates();
+                return false;
             }
             WebRtcMessage::UpdateState(state) => {
                 if let State::Client(connection_state) = state.clone() {
@@ -202,21 +343,29 @@ impl Client {
                 self.web_rtc_state = ConnectionState::new();
                 self.session_details = None;
                 self.session_code = None;
+                self.offer_id = None;
                 self.password_needed = false;
+                self.retry_timeout = None;
+                self.signaling = None;
+                self.answer_sent = false;
+                self.candidates_sent = 0;
             }
         }
         true
     }
 
-    fn update_api(&mut self, _: &Context<Self>, msg: ApiServiceMessage) -> bool {
+    fn update_api(&mut self, ctx: &Context<Self>, msg: ApiServiceMessage) -> bool {
         match msg {
             ApiServiceMessage::ClientDetails(result) => {
                 if result.is_err() {
                     let status = result.unwrap_err();
-                    if status == 401 {
+                    if status == STATUS_UNAUTHORIZED {
                         //Unauthorized
                         self.password_needed = true;
                         return true;
+                    } else if status == STATUS_OFFER_TAKEN {
+                        self.schedule_retry(ctx);
+                        return false;
                     } else {
                         console::log_1(
                             &format!("Error getting detail session: {:?}", status).into(),
@@ -225,40 +374,193 @@ impl Client {
                     }
                 }
                 let result = result.unwrap();
-                let details = result.connection_details;
-
-                self.web_rtc_manager
-                    .deref()
-                    .borrow_mut()
-                    .set_state(State::Client(ConnectionState::new()));
-                let result: Result<(), wasm_bindgen::JsValue> =
-                    WebRTCManager::start_web_rtc(&self.web_rtc_manager);
-                if result.is_ok() {
-                    let result = WebRTCManager::validate_offer(&self.web_rtc_manager, &details);
-                    if result.is_err() {
-                        console::log_1(
-                            &format!("Error validating offer: {:?}", result.clone().err()).into(),
-                        );
-                    }
-                }
+                self.handle_offer(result.offer_id, &result.connection_details);
                 false
             }
             ApiServiceMessage::ClientJoin(result) => {
                 if result.is_err() {
                     let status = result.unwrap_err();
+                    if status == STATUS_OFFER_TAKEN {
+                        // Another client answered this offer first, wait for the host to publish a new one
+                        self.reset_web_rtc(ctx);
+                        self.schedule_retry(ctx);
+                        return false;
+                    }
                     console::log_1(&format!("Error joining session: {:?}", status).into());
                     return false;
                 }
                 let result = result.unwrap();
                 self.session_details = Some(result);
+                self.arm_relay();
                 true
             }
+            ApiServiceMessage::IceServers(result) => {
+                // Without the server's list peers still connect where host candidates suffice, like on a LAN
+                self.ice_servers = match result {
+                    Ok(ice_servers) => {
+                        self.relay_timeout_secs = ice_servers.relay_timeout_secs;
+                        Some(ice_servers.ice_servers)
+                    }
+                    Err(status) => {
+                        console::log_1(&format!("Error fetching ICE servers: {:?}", status).into());
+                        Some(Vec::new())
+                    }
+                };
+                self.connect_session(ctx);
+                false
+            }
             _ => false,
         }
     }
 
+    fn update_signaling(&mut self, ctx: &Context<Self>, msg: SignalingServiceMessage) -> bool {
+        match msg {
+            SignalingServiceMessage::Message(SignalingMessage::Offer { offer_id, connection_details }) => {
+                if self.offer_id.is_some() {
+                    // The previous offer went to another client, answer the new one instead
+                    self.reset_web_rtc(ctx);
+                }
+                self.handle_offer(offer_id, &connection_details);
+                false
+            }
+            SignalingServiceMessage::Message(SignalingMessage::Candidate { offer_id, candidate }) => {
+                if self.offer_id == Some(offer_id) {
+                    let result = WebRTCManager::add_remote_candidate(&self.web_rtc_manager, &candidate);
+                    if result.is_err() {
+                        console::log_1(&format!("Error adding candidate: {:?}", result.err()).into());
+                    }
+                }
+                false
+            }
+            SignalingServiceMessage::Message(SignalingMessage::Joined(result)) => {
+                self.session_details = Some(result);
+                self.arm_relay();
+                true
+            }
+            SignalingServiceMessage::Message(SignalingMessage::Error { status }) => {
+                if status == STATUS_OFFER_TAKEN {
+                    // The server pushes the next offer as soon as the host publishes it
+                    self.reset_web_rtc(ctx);
+                    self.offer_id = None;
+                    return false;
+                }
+
+                self.signaling = None;
+                if status == STATUS_UNAUTHORIZED {
+                    self.password_needed = true;
+                    return true;
+                }
+                console::log_1(&format!("Signaling error: {:?}", status).into());
+                false
+            }
+            SignalingServiceMessage::Message(SignalingMessage::Close) => {
+                self.signaling = None;
+                false
+            }
+            SignalingServiceMessage::Message(_) => false,
+            SignalingServiceMessage::Closed => {
+                // Fall back to the REST endpoints if the socket drops before joining
+                if self.signaling.take().is_some() && self.session_details.is_none() {
+                    self.reset_web_rtc(ctx);
+                    if let Some(code) = self.session_code.clone() {
+                        let callback: Callback<ApiServiceMessage> = ctx.link().callback(Msg::CallbackApi);
+                        api_service::get_session_details(callback, &code, self.password.clone());
+                    }
+                }
+                false
+            }
+        }
+    }
+
+    fn connect_session(&mut self, ctx: &Context<Self>) {
+        let code = match self.session_code.clone() {
+            Some(code) if self.ice_servers.is_some() => code,
+            _ => return,
+        };
+        if self.store_key.is_some() && !self.store_checked {
+            self.fetch_stored_share(ctx, code);
+            return;
+        }
+
+        let callback = ctx.link().callback(Msg::CallbackSignaling);
+        self.signaling = SignalingService::connect_client(callback, &code);
+        match &self.signaling {
+            Some(signaling) => signaling.send(SignalingMessage::Join {
+                password: self.password.clone().unwrap_or_default(),
+            }),
+            None => {
+                let callback: Callback<ApiServiceMessage> = ctx.link().callback(Msg::CallbackApi);
+                api_service::get_session_details(callback, &code, self.password.clone());
+            }
+        }
+    }
+
+    fn handle_offer(&mut self, offer_id: u32, details: &str) {
+        self.offer_id = Some(offer_id);
+
+        self.web_rtc_manager
+            .deref()
+            .borrow_mut()
+            .set_state(State::Client(ConnectionState::new()));
+        let ice_servers = self.ice_servers.as_deref().unwrap_or_default();
+        let result: Result<(), wasm_bindgen::JsValue> =
+            WebRTCManager::start_web_rtc(&self.web_rtc_manager, ice_servers);
+        if result.is_ok() {
+            let result = WebRTCManager::validate_offer(&self.web_rtc_manager, details);
+            if result.is_err() {
+                console::log_1(
+                    &format!("Error validating offer: {:?}", result.clone().err()).into(),
+                );
+            }
+        }
+    }
+
+    /// The offer is ours once joined, so the relay may pair us with the host from then on.
+    fn arm_relay(&self) {
+        let (code, offer_id, session_details) = match (self.session_code.clone(), self.offer_id, &self.session_details) {
+            (Some(code), Some(offer_id), Some(session_details)) => (code, offer_id, session_details),
+            _ => return,
+        };
+        let relay_token = session_details.relay_token.clone();
+        let endpoint = RelayEndpoint::Client { code, relay_token, offer_id };
+        WebRTCManager::arm_relay(&self.web_rtc_manager, endpoint, self.relay_timeout_secs);
+    }
+
+    fn reset_web_rtc(&mut self, ctx: &Context<Self>) {
+        self.web_rtc_manager = WebRTCManager::new(ctx.link().callback(Msg::CallbackWebRtc));
+        self.web_rtc_state = ConnectionState::new();
+        self.answer_sent = false;
+        self.candidates_sent = 0;
+    }
+
+    /// Sends the answer as soon as it exists, the candidates follow one by one over the signaling channel.
+    fn send_answer(&mut self) {
+        let (signaling, offer_id) = match (&self.signaling, self.offer_id) {
+            (Some(signaling), Some(offer_id)) => (signaling, offer_id),
+            _ => return,
+        };
+
+        let web_rtc_manager = self.web_rtc_manager.deref().borrow();
+        self.candidates_sent = web_rtc_manager.ice_candidate_count();
+        let answer = web_rtc_manager.create_encoded_offer();
+        signaling.send(SignalingMessage::Answer { offer_id, connection_details: answer });
+        self.answer_sent = true;
+    }
+
+    fn send_candidates(&mut self) {
+        let (signaling, offer_id) = match (&self.signaling, self.offer_id) {
+            (Some(signaling), Some(offer_id)) if self.answer_sent => (signaling, offer_id),
+            _ => return,
+        };
+
+        let candidates = self.web_rtc_manager.deref().borrow().encoded_candidates(self.candidates_sent);
+        self.candidates_sent += candidates.len();
+        for candidate in candidates {
+            signaling.send(SignalingMessage::Candidate { offer_id, candidate });
+        }
+    }
+
     fn view_connected(&self, ctx: &Context<Self>) -> Html {
-        let session_details = self.session_details.as_ref().unwrap();
         let section_table = {
             html! {
                 <div class="table-wrapper table-responsive">
@@ -287,18 +589,7 @@ impl Client {
             <div class="container mt-5">
                 <div class="row mb-3">
                     <div class="info-panel bg-light p-3 rounded text-center d-flex justify-content-around align-items-center w-100">
-                        <p class="d-flex align-items-center mb-0">
-                            <span class="pl-3 pr-1 font-weight-bold">{"Connection:"}</span>
-                            <span class="text-success">{"🟢"}</span>//todo: Add timeout indicator
-                        </p>
-                        <p class="d-flex align-items-center mb-0">
-                            <span class="pl-3 pr-1 font-weight-bold">{"Password:"}</span>
-                            <span>{format!("{}", if session_details.has_password {"🔓"} else {"🔒"})}</span>
-                        </p>
-                        <p class="d-flex align-items-center mb-0">
-                            <span class="pl-3 pr-1 font-weight-bold">{"Compression:"}</span>
-                            <span>{session_details.compression_level}</span>
-                        </p>
+                        {self.view_info(ctx)}
                     </div>
                 </div>
                 {if self.files.len() > 0 {section_table} else {html!{}}}
@@ -306,6 +597,43 @@ impl Client {
         }
     }
 
+    fn view_info(&self, _ctx: &Context<Self>) -> Html {
+        if let Some((_, share)) = &self.stored {
+            return html! {
+                <>
+                <p class="d-flex align-items-center mb-0">
+                    <span class="pl-3 pr-1 font-weight-bold">{"Connection:"}</span>
+                    <span class="text-success">{"🟢"}</span>
+                    <span class="pl-1">{"stored"}</span>
+                </p>
+                <p class="d-flex align-items-center mb-0">
+                    <span class="pl-3 pr-1 font-weight-bold">{"Expires in:"}</span>
+                    <span>{format!("{}h {}m", share.expires_in_secs / 3600, share.expires_in_secs % 3600 / 60)}</span>
+                </p>
+                </>
+            };
+        }
+
+        let session_details = self.session_details.as_ref().unwrap();
+        html! {
+            <>
+            <p class="d-flex align-items-center mb-0">
+                <span class="pl-3 pr-1 font-weight-bold">{"Connection:"}</span>
+                <span class="text-success">{"🟢"}</span>//todo: Add timeout indicator
+                <span class="pl-1">{if self.web_rtc_state.relayed {"relayed"} else {"direct"}}</span>
+            </p>
+            <p class="d-flex align-items-center mb-0">
+                <span class="pl-3 pr-1 font-weight-bold">{"Password:"}</span>
+                <span>{format!("{}", if session_details.has_password {"🔓"} else {"🔒"})}</span>
+            </p>
+            <p class="d-flex align-items-center mb-0">
+                <span class="pl-3 pr-1 font-weight-bold">{"Compression:"}</span>
+                <span>{session_details.compression_level}</span>
+            </p>
+            </>
+        }
+    }
+
     fn view_file_row(ctx: &Context<Self>, index: usize, file: &FileItem) -> Html {
         let file_tag = file.tag.clone();
         let control_pannel = {
@@ -357,7 +685,13 @@ impl Client {
                         console::log_1(&"Session code is not set".into());
                         return;
                     }
+                    if self.signaling.is_some() {
+                        // The answer went out with the local description already
+                        return;
+                    }
+
                     let session_code = self.session_code.clone().unwrap();
+                    let offer_id = self.offer_id.expect("Offer id is not set");
                     let answer = self.web_rtc_manager.deref().borrow().create_encoded_offer();
 
                     let callback: Callback<ApiServiceMessage> =
@@ -366,6 +700,7 @@ impl Client {
                         callback,
                         session_code,
                         self.password.clone(),
+                        offer_id,
                         answer,
                     );
                 }
@@ -373,6 +708,11 @@ impl Client {
         }
     }
 
+    fn schedule_retry(&mut self, ctx: &Context<Self>) {
+        let callback = ctx.link().callback(|_| Msg::SessionRetry);
+        self.retry_timeout = Some(Timeout::new(OFFER_RETRY_DELAY_MS, move || callback.emit(())));
+    }
+
     fn on_files_updates(&mut self, files_update: FilesUpdate) -> bool {
         // Add new files
         for file in files_update.clone().files {
@@ -404,7 +744,7 @@ impl Client {
     }
 
     fn current_state(&self) -> ClientState {
-        if self.web_rtc_connected() && self.session_details.is_some() {
+        if self.stored.is_some() || (self.web_rtc_connected() && self.session_details.is_some()) {
             ClientState::Connected
         } else if !self.password_needed || self.session_code.is_none() {
             ClientState::Connect
@@ -414,9 +754,6 @@ impl Client {
     }
 
     fn web_rtc_connected(&self) -> bool {
-        matches!(
-            self.web_rtc_state.ice_connection_state,
-            Some(web_sys::RtcIceConnectionState::Connected)
-        )
+        self.web_rtc_state.is_connected()
     }
 }
@@ -1,33 +1,54 @@
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::ops::Deref;
 use std::rc::Rc;
 
+use gloo::events::EventListener;
+use gloo::timers::callback::Timeout;
 use uuid::Uuid;
 use wasm_bindgen::JsCast;
-use web_sys::{console, File, HtmlInputElement};
+use web_sys::{console, File, HtmlInputElement, RtcDataChannelState};
 use yew::platform::spawn_local;
 use yew::{Html, html, Context, Component, NodeRef};
 
 use drop_files::DropFiles;
 
-use crate::file_tag::{FileState, FileTag, convert_bytes_to_readable_format};
+use crate::file_tag::{FileTag, convert_bytes_to_readable_format};
 use crate::pages::host::slider::Slider;
 use crate::wrtc_protocol::{FilesUpdate, FileInfo, FileRequest};
 use crate::services::web_rtc::{State, ConnectionState, WebRtcMessage, WebRTCManager};
 use crate::services::api_service::{api_service, ApiServiceMessage};
+use crate::services::relay::RelayEndpoint;
+use crate::services::signaling::{SignalingService, SignalingServiceMessage};
+use crate::services::stored_share::{self, StoreKey};
+use crate::shared::{IceServer, IceServers, SignalingMessage};
 
 mod drop_files;
 mod slider;
 
 const COMPRESSION_DEFAULT: u8 = 9;
+const STATUS_SESSION_GONE: u16 = 410;
+const FEATURE_STORE: &str = "store";
 
 #[derive(Clone)]
 pub struct FileItem {
-    state: FileState,
     pub tag: FileTag,
     js_file: File,
-    progress: f64,
+    transfers: BTreeMap<u32, f64>,
+    stored: Option<StoreProgress>,
+}
+
+#[derive(Clone, PartialEq)]
+pub enum StoreProgress {
+    Uploading(f64),
+    Failed,
+}
+
+pub struct Peer {
+    web_rtc_manager: Rc<RefCell<WebRTCManager>>,
+    web_rtc_state: ConnectionState,
+    offer_id: Option<u32>,
+    candidates_sent: usize,
 }
 
 pub enum HostState {
@@ -41,30 +62,44 @@ pub enum Msg {
     CopyShareLink,
     Update(Vec<File>),
     CompressionUpdate(u8),
-    TransferUpdate((FileTag, f64)),
+    TransferUpdate((u32, FileTag, f64)),
     FileRemove(FileTag),
+    Store(Vec<File>),
+    StoreKeyReady(Option<StoreKey>),
+    StoreUpdate(Uuid, StoreProgress),
 
-    CallbackWebRtc(WebRtcMessage),
+    CallbackWebRtc(u32, WebRtcMessage),
     CallbackApi(ApiServiceMessage),
+    CallbackSignaling(SignalingServiceMessage),
 }
 
 pub struct Host {
-    web_rtc_manager: Rc<RefCell<WebRTCManager>>,
-    web_rtc_state: ConnectionState,
+    peers: HashMap<u32, Peer>,
+    pending_peer: Option<u32>,
+    next_peer_id: u32,
     files: HashMap<Uuid, FileItem>,
     origin: String,
     code: String,
+    owner_token: String,
     compression_level: u8,
     password: String,
     node_password: NodeRef,
     node_share: NodeRef,
+    unload_listener: Option<EventListener>,
+    signaling: Option<SignalingService>,
+    ice_servers: Option<Vec<IceServer>>,
+    ice_servers_refresh: Option<Timeout>,
+    relay_timeout_secs: u64,
+    start_requested: bool,
+    store_available: bool,
+    store_key: Option<StoreKey>,
 }
 
 impl Component for Host {
     type Message = Msg;
     type Properties = ();
-     
-    
+
+
     fn create(ctx: &Context<Self>) -> Self {
         let origin = web_sys::window()
             .expect("no global `window` exists")
@@ -72,17 +107,29 @@ impl Component for Host {
             .origin()  // This gets the origin
             .unwrap_or_else(|_| "Error getting origin".to_string());
 
+        api_service::get_ice_servers(ctx.link().callback(Msg::CallbackApi));
+        api_service::get_version(ctx.link().callback(Msg::CallbackApi));
 
         Host {
-            web_rtc_manager: WebRTCManager::new(ctx.link().callback(Msg::CallbackWebRtc)),
-            web_rtc_state: ConnectionState::new(),
+            peers: HashMap::new(),
+            pending_peer: None,
+            next_peer_id: 0,
             files: HashMap::new(),
             origin,
             code: String::new(),
+            owner_token: String::new(),
             compression_level: COMPRESSION_DEFAULT,
             password: String::new(),
             node_password: NodeRef::default(),
             node_share: NodeRef::default(),
+            unload_listener: None,
+            signaling: None,
+            ice_servers: None,
+            ice_servers_refresh: None,
+            relay_timeout_secs: 0,
+            start_requested: false,
+            store_available: false,
+            store_key: None,
         }
     }
 
@@ -97,33 +144,32 @@ impl Component for Host {
                 true
             }
             Msg::SessionStart => {
+                if self.ice_servers.is_none() {
+                    // Started once the ICE servers arrive
+                    self.start_requested = true;
+                    return false;
+                }
+
                 self.password = if let Some(input) = self.node_password.cast::<HtmlInputElement>() {
                     input.value()
                 } else {
                     "".to_string()
                 };
-                
-                if let Some(state) = self.web_rtc_state.ice_gathering_state {
-                    if state == web_sys::RtcIceGatheringState::Complete {
+
+                if let Some(peer) = self.pending_peer.and_then(|peer_id| self.peers.get(&peer_id)) {
+                    if peer.web_rtc_manager.deref().borrow().get_offer().is_some() {
                         self.create_session(ctx);
                         return true;
                     }
                 }
-                
-                self.web_rtc_manager.deref().borrow_mut().set_state(State::Server(ConnectionState::new()));
-                let result: Result<(), wasm_bindgen::JsValue> = WebRTCManager::start_web_rtc(&self.web_rtc_manager);
-                if result.is_err() {
-                    console::log_1(&result.err().unwrap());
-                }
+
+                self.start_pending_peer(ctx);
                 true
             }
-            Msg::TransferUpdate((file_tag, progress)) => {
+            Msg::TransferUpdate((peer_id, file_tag, progress)) => {
                 let file = self.files.get_mut(&file_tag.uuid);
                 if let Some(file) = file {
-                    file.progress = progress;
-                    if progress >= 1.0 {
-                        file.state = FileState::Done;
-                    }
+                    file.transfers.insert(peer_id, progress);
                 }
                 true
             }
@@ -132,12 +178,34 @@ impl Component for Host {
                 self.web_rtc_send_update();
                 true
             }
-            Msg::CallbackWebRtc(msg) => {
-                self.update_web_rtc(ctx, msg)
+            Msg::Store(files) => {
+                for uuid in self.handle_files(files) {
+                    self.store_file(ctx, uuid);
+                }
+                true
+            }
+            Msg::StoreKeyReady(store_key) => {
+                if store_key.is_none() {
+                    console::log_1(&"Error generating the store key".into());
+                }
+                self.store_key = store_key;
+                true
+            }
+            Msg::StoreUpdate(uuid, progress) => {
+                if let Some(file) = self.files.get_mut(&uuid) {
+                    file.stored = Some(progress);
+                }
+                true
+            }
+            Msg::CallbackWebRtc(peer_id, msg) => {
+                self.update_web_rtc(ctx, peer_id, msg)
             }
             Msg::CallbackApi(msg) => {
                 self.update_api_service(ctx, msg)
             }
+            Msg::CallbackSignaling(msg) => {
+                self.update_signaling(ctx, msg)
+            }
             Msg::CopyShareLink => {
                 if let Some(input) = self.node_share.cast::<web_sys::HtmlInputElement>() {
                     input.select();
@@ -158,30 +226,205 @@ impl Component for Host {
             HostState::Connected => self.view_session_handle(ctx),
         }
     }
+
+    fn destroy(&mut self, _ctx: &Context<Self>) {
+        self.close_session();
+    }
 }
 
 impl Host {
-    fn create_session(&self, ctx: &Context<Self>) {
+    fn create_session(&mut self, ctx: &Context<Self>) {
         let callback = ctx.link().callback(Msg::CallbackApi);
-        let answer = self.web_rtc_manager.deref().borrow().create_encoded_offer();
+        let answer = match self.pending_peer_offer() {
+            Some(answer) => answer,
+            None => return,
+        };
         api_service::create_session(callback, answer, self.password.clone(), self.compression_level);
     }
 
-    fn handle_files(&mut self, files: Vec<File>) {
-        files.into_iter().for_each(|file| {
+    fn update_session(&mut self, ctx: &Context<Self>) {
+        let answer = match self.pending_peer_offer() {
+            Some(answer) => answer,
+            None => return,
+        };
+        if let Some(signaling) = &self.signaling {
+            signaling.send(SignalingMessage::Offer { offer_id: 0, connection_details: answer });
+            return;
+        }
+        let callback = ctx.link().callback(Msg::CallbackApi);
+        api_service::update_session(callback, self.code.clone(), self.owner_token.clone(), answer);
+    }
+
+    fn connect_signaling(&mut self, ctx: &Context<Self>) {
+        let callback = ctx.link().callback(Msg::CallbackSignaling);
+        self.signaling = SignalingService::connect_host(callback, &self.code, &self.owner_token);
+        if self.signaling.is_none() {
+            api_service::poll_session(ctx.link().callback(Msg::CallbackApi), self.code.clone(), self.owner_token.clone());
+        }
+    }
+
+    fn pending_peer_offer(&mut self) -> Option<String> {
+        let peer = self.peers.get_mut(&self.pending_peer?)?;
+        let web_rtc_manager = peer.web_rtc_manager.deref().borrow();
+        web_rtc_manager.get_offer()?;
+
+        // Candidates bundled with the offer do not need to be trickled afterwards
+        peer.candidates_sent = web_rtc_manager.ice_candidate_count();
+        Some(web_rtc_manager.create_encoded_offer())
+    }
+
+    fn send_candidates(&mut self, peer_id: u32) {
+        let signaling = match &self.signaling {
+            Some(signaling) => signaling,
+            None => return,
+        };
+        let peer = match self.peers.get_mut(&peer_id) {
+            Some(peer) => peer,
+            None => return,
+        };
+        let offer_id = match peer.offer_id {
+            Some(offer_id) => offer_id,
+            None => return,
+        };
+
+        let candidates = peer.web_rtc_manager.deref().borrow().encoded_candidates(peer.candidates_sent);
+        peer.candidates_sent += candidates.len();
+        for candidate in candidates {
+            signaling.send(SignalingMessage::Candidate { offer_id, candidate });
+        }
+    }
+
+    /// Prepares a fresh peer connection whose offer is published for the next client to join.
+    fn start_pending_peer(&mut self, ctx: &Context<Self>) {
+        let peer_id = self.next_peer_id;
+        self.next_peer_id += 1;
+
+        let web_rtc_manager = WebRTCManager::new(ctx.link().callback(move |msg| Msg::CallbackWebRtc(peer_id, msg)));
+        web_rtc_manager.deref().borrow_mut().set_state(State::Server(ConnectionState::new()));
+        let ice_servers = self.ice_servers.as_deref().unwrap_or_default();
+        let result: Result<(), wasm_bindgen::JsValue> = WebRTCManager::start_web_rtc(&web_rtc_manager, ice_servers);
+        if result.is_err() {
+            console::log_1(&result.err().unwrap());
+        }
+
+        self.peers.insert(peer_id, Peer {
+            web_rtc_manager,
+            web_rtc_state: ConnectionState::new(),
+            offer_id: None,
+            candidates_sent: 0,
+        });
+        self.pending_peer = Some(peer_id);
+    }
+
+    fn remove_peer(&mut self, peer_id: u32) {
+        self.peers.remove(&peer_id);
+        for file in self.files.values_mut() {
+            file.transfers.remove(&peer_id);
+        }
+    }
+
+    fn reset(&mut self, _ctx: &Context<Self>) {
+        self.close_session();
+        self.peers = HashMap::new();
+        self.pending_peer = None;
+        self.files = HashMap::new();
+        self.code = String::new();
+        self.owner_token = String::new();
+        self.compression_level = COMPRESSION_DEFAULT;
+        self.store_key = None;
+    }
+
+    fn close_session(&mut self) {
+        self.unload_listener = None;
+        if !self.code.is_empty() {
+            match self.signaling.take() {
+                Some(signaling) => signaling.send(SignalingMessage::Close),
+                None => api_service::close_session(self.code.clone(), self.owner_token.clone()),
+            }
+            self.code = String::new();
+        }
+    }
+
+    fn register_unload_listener(&mut self) {
+        let window = web_sys::window().expect("no global `window` exists");
+        let code = self.code.clone();
+        let owner_token = self.owner_token.clone();
+        self.unload_listener = Some(EventListener::new(&window, "pagehide", move |event| {
+            // A page entering the back/forward cache may still be returned to, its session stays
+            let persisted = event
+                .dyn_ref::<web_sys::PageTransitionEvent>()
+                .is_some_and(|event| event.persisted());
+            if !persisted {
+                api_service::close_session_on_unload(&code, &owner_token);
+            }
+        }));
+    }
+
+    fn handle_files(&mut self, files: Vec<File>) -> Vec<Uuid> {
+        let uuids = files.into_iter().map(|file| {
             let item = FileItem {
-                state: FileState::Pending,
                 tag: FileTag::from(file.clone()),
                 js_file: file.clone(),
-                progress: 0.0,
+                transfers: BTreeMap::new(),
+                stored: None,
             };
-            self.files.insert(item.tag.uuid, item);
-        });
+            let uuid = item.tag.uuid;
+            self.files.insert(uuid, item);
+            uuid
+        }).collect();
 
         self.web_rtc_send_update();
+        uuids
+    }
+
+    /// The key is generated along with the session, so the share link carries it from the start.
+    fn create_store_key(&mut self, ctx: &Context<Self>) {
+        if !self.store_available || self.code.is_empty() || self.store_key.is_some() {
+            return;
+        }
+        let callback = ctx.link().callback(Msg::StoreKeyReady);
+        spawn_local(async move {
+            callback.emit(StoreKey::generate().await.ok());
+        });
+    }
+
+    /// Uploads a file to the server, where clients can still download it after this tab closed.
+    fn store_file(&mut self, ctx: &Context<Self>, uuid: Uuid) {
+        let store_key = match &self.store_key {
+            Some(store_key) => store_key.clone(),
+            None => return,
+        };
+        let file = match self.files.get_mut(&uuid) {
+            Some(file) => file,
+            None => return,
+        };
+        file.stored = Some(StoreProgress::Uploading(0.0));
+
+        let callback_progress = ctx.link().callback(move |progress| Msg::StoreUpdate(uuid, StoreProgress::Uploading(progress)));
+        let callback_failed = ctx.link().callback(move |_: ()| Msg::StoreUpdate(uuid, StoreProgress::Failed));
+        let code = self.code.clone();
+        let owner_token = self.owner_token.clone();
+        let file = file.clone();
+        spawn_local(async move {
+            let result = stored_share::upload_file(&code, &owner_token, &store_key, &file.tag, file.js_file.deref(), &callback_progress).await;
+            if let Err(status) = result {
+                console::log_1(&format!("Error storing file: {:?}", status).into());
+                callback_failed.emit(());
+            }
+        });
     }
 
     fn web_rtc_send_update(&self) {
+        let message = self.files_update_message();
+        for peer in self.peers.values() {
+            peer.web_rtc_manager
+            .deref()
+            .borrow()
+            .send_message(&message);
+        }
+    }
+
+    fn files_update_message(&self) -> String {
         let files: Vec<FileInfo> = self.files.iter().map(|(_, file)| {
             FileInfo {
                 uuid: file.tag.uuid(),
@@ -191,30 +434,37 @@ impl Host {
         }).collect();
 
         let update = FilesUpdate{files};
-        let message = serde_json::to_string(&update).unwrap();
-        self.web_rtc_manager
-        .deref()
-        .borrow()
-        .send_message(&message);
+        serde_json::to_string(&update).unwrap()
+    }
+
+    /// Whether messages reach the peer, over its message channel or the relay.
+    fn peer_reachable(connection_state: &ConnectionState) -> bool {
+        connection_state.relayed || connection_state.message_channel_state == Some(RtcDataChannelState::Open)
     }
 
     fn web_rtc_connected(&self) -> bool {
-        matches!(self.web_rtc_state.ice_connection_state, Some(web_sys::RtcIceConnectionState::Connected))
+        self.peers.values().any(Self::peer_connected)
+    }
+
+    fn peer_connected(peer: &Peer) -> bool {
+        peer.web_rtc_state.is_connected()
     }
-    
-    fn web_rtc_send_file(&mut self, ctx: &Context<Self>, uuid: Uuid) {
-        // let file = self.files.iter().find(|(_, file)| file.tag.uuid() == uuid);	
+
+    fn web_rtc_send_file(&mut self, ctx: &Context<Self>, peer_id: u32, uuid: Uuid) {
+        let web_rtc_manager = match self.peers.get(&peer_id) {
+            Some(peer) => peer.web_rtc_manager.clone(),
+            None => return,
+        };
         let file = self.files.get_mut(&uuid);
         let file = match file {
             Some(file) => file,
             None => return,
         };
 
-        file.state = FileState::Transferring;
-        
-        let callback_update = ctx.link().callback(|(tag, progress)| Msg::TransferUpdate((tag, progress)));
-        let mut file = file.clone();
-        let web_rtc_manager = self.web_rtc_manager.clone();
+        file.transfers.insert(peer_id, 0.0);
+
+        let callback_update = ctx.link().callback(move |(tag, progress)| Msg::TransferUpdate((peer_id, tag, progress)));
+        let file = file.clone();
         spawn_local(async move {
             let blob = file.js_file.deref();
 
@@ -229,7 +479,7 @@ impl Host {
                     console::log_1(&format!("Failed to slice chunk").into());
                     return;
                 }
-                
+
                 let chunk = chunk.unwrap();
                 let sent_success = web_rtc_manager
                     .deref()
@@ -241,14 +491,14 @@ impl Host {
                     console::log_1(&format!("Failed to send chunk").into());
                     return;
                 }
-                file.progress = end / blob.size();
+                let progress = end / blob.size();
                 offset += CHUNK_SIZE;
-                callback_update.emit((file.tag.clone(), file.progress));
+                callback_update.emit((file.tag.clone(), progress));
             }
         });
     }
 
-    fn update_web_rtc(&mut self, ctx: &Context<Self>, msg: WebRtcMessage) -> bool {
+    fn update_web_rtc(&mut self, ctx: &Context<Self>, peer_id: u32, msg: WebRtcMessage) -> bool {
         match msg {
             WebRtcMessage::Message(data) => {
                 let update: Result<FileRequest, serde_json::Error> = serde_json::from_str(&data);
@@ -256,47 +506,99 @@ impl Host {
                     return false;
                 }
 
-                self.web_rtc_send_file(ctx, update.unwrap().uuid);
+                self.web_rtc_send_file(ctx, peer_id, update.unwrap().uuid);
                 true
             }
             WebRtcMessage::Data(_, _) => {
                 //Host should not receive data
                 false
             }
+            WebRtcMessage::LocalDescription => {
+                if self.pending_peer != Some(peer_id) {
+                    return false;
+                }
+                // Publish right away and trickle the candidates, long polling waits for gathering to complete
+                if self.code.is_empty() {
+                    self.create_session(ctx);
+                } else if self.signaling.is_some() {
+                    self.update_session(ctx);
+                }
+                false
+            }
+            WebRtcMessage::Candidate => {
+                self.send_candidates(peer_id);
+                false
+            }
             WebRtcMessage::UpdateState(state) => {
                 let mut update = false;
                 if let State::Server(connection_state) = state.clone() {
-                    if connection_state.ice_gathering_state != self.web_rtc_state.ice_gathering_state {
+                    let peer = match self.peers.get_mut(&peer_id) {
+                        Some(peer) => peer,
+                        None => return false,
+                    };
+
+                    if connection_state.ice_gathering_state != peer.web_rtc_state.ice_gathering_state {
                         if let Some(state) = connection_state.ice_gathering_state {
-                            if state == web_sys::RtcIceGatheringState::Complete {
-                                self.create_session(ctx);
+                            if state == web_sys::RtcIceGatheringState::Complete && self.pending_peer == Some(peer_id) &&
+                            self.signaling.is_none() && !self.code.is_empty() {
+                                self.update_session(ctx);
                             }
                         }
                         update = true
                     }
-                    
-                    if connection_state.ice_connection_state != self.web_rtc_state.ice_connection_state {
+
+                    let peer = match self.peers.get_mut(&peer_id) {
+                        Some(peer) => peer,
+                        None => return update,
+                    };
+                    if connection_state.ice_connection_state != peer.web_rtc_state.ice_connection_state {
                         if let Some(state) = connection_state.ice_connection_state {
-                            if state == web_sys::RtcIceConnectionState::Connected ||
-                            state == web_sys::RtcIceConnectionState::Disconnected {
+                            if state == web_sys::RtcIceConnectionState::Connected {
+                                update = true;
+                            }
+                            if state == web_sys::RtcIceConnectionState::Disconnected ||
+                            state == web_sys::RtcIceConnectionState::Failed ||
+                            state == web_sys::RtcIceConnectionState::Closed {
 
                                 update = true;
                             }
                         }
                     }
-                    
-                    self.web_rtc_state = connection_state;
+                    if connection_state.relayed != peer.web_rtc_state.relayed {
+                        update = true;
+                    }
+
+                    // A peer whose direct connection broke down stays while the relay can still take over
+                    let disconnected = matches!(
+                        connection_state.ice_connection_state,
+                        Some(web_sys::RtcIceConnectionState::Disconnected | web_sys::RtcIceConnectionState::Failed | web_sys::RtcIceConnectionState::Closed)
+                    ) && !connection_state.relayed && !peer.web_rtc_manager.deref().borrow().relay_armed();
+
+                    // Peers joining later still need the files shared so far
+                    let reachable = Self::peer_reachable(&connection_state) && !Self::peer_reachable(&peer.web_rtc_state);
+
+                    peer.web_rtc_state = connection_state;
+                    if reachable {
+                        let message = self.files_update_message();
+                        self.peers[&peer_id].web_rtc_manager.deref().borrow().send_message(&message);
+                    }
+                    if disconnected && self.pending_peer != Some(peer_id) {
+                        self.remove_peer(peer_id);
+                    }
                 };
                 update
             }
             WebRtcMessage::Reset => {
-                self.web_rtc_manager = WebRTCManager::new(ctx.link().callback(Msg::CallbackWebRtc));
-                self.web_rtc_state = ConnectionState::new();
-                self.files = HashMap::new();
-                self.code = String::new();
-                self.compression_level = COMPRESSION_DEFAULT;
-                
-                false
+                if self.code.is_empty() {
+                    self.reset(ctx);
+                    return false;
+                }
+
+                self.remove_peer(peer_id);
+                if self.pending_peer == Some(peer_id) {
+                    self.start_pending_peer(ctx);
+                }
+                true
             }
         }
     }
@@ -305,29 +607,187 @@ impl Host {
         match msg {
             ApiServiceMessage::HostCreate(result) => {
                 if result.is_err() {
-                    return false;
+                    console::log_1(&format!("Error creating session: {:?}", result.unwrap_err()).into());
+                    if let Some(peer_id) = self.pending_peer.take() {
+                        self.remove_peer(peer_id);
+                    }
+                    return true;
                 }
                 let result = result.unwrap();
                 self.code = result.code;
-
-                api_service::poll_session(_ctx.link().callback(Msg::CallbackApi), self.code.clone());
+                self.owner_token = result.owner_token;
+                self.register_unload_listener();
+                self.connect_signaling(_ctx);
+                self.set_pending_offer_id(result.offer_id);
+                self.create_store_key(_ctx);
                 true
             },
+            ApiServiceMessage::HostUpdate(result) => {
+                if result.is_err() {
+                    console::log_1(&format!("Error updating session: {:?}", result.unwrap_err()).into());
+                    return false;
+                }
+                self.set_pending_offer_id(result.unwrap().offer_id);
+                false
+            },
             ApiServiceMessage::HostPoll(result) => {
                 if result.is_err() {
+                    let status = result.unwrap_err();
+                    if status == STATUS_SESSION_GONE {
+                        return self.handle_session_gone(_ctx);
+                    }
+                    console::log_1(&format!("Error polling session: {:?}", status).into());
                     return false;
                 }
                 let result = result.unwrap();
-                
-                let _ = WebRTCManager::validate_answer(&self.web_rtc_manager, &result.connection_details);
+                api_service::poll_session(_ctx.link().callback(Msg::CallbackApi), self.code.clone(), self.owner_token.clone());
+                self.handle_answer(_ctx, result.offer_id, &result.connection_details)
+            },
+            ApiServiceMessage::IceServers(result) => {
+                self.handle_ice_servers(_ctx, result);
+                false
+            },
+            ApiServiceMessage::Version(result) => {
+                if let Ok(version) = result {
+                    self.store_available = version.features.iter().any(|feature| feature == FEATURE_STORE);
+                    self.create_store_key(_ctx);
+                }
                 true
             },
             _ => false,
         }
     }
 
+    fn update_signaling(&mut self, ctx: &Context<Self>, msg: SignalingServiceMessage) -> bool {
+        match msg {
+            SignalingServiceMessage::Message(SignalingMessage::Offer { offer_id, .. }) => {
+                self.set_pending_offer_id(offer_id);
+                false
+            }
+            SignalingServiceMessage::Message(SignalingMessage::Answer { offer_id, connection_details }) => {
+                self.handle_answer(ctx, offer_id, &connection_details)
+            }
+            SignalingServiceMessage::Message(SignalingMessage::Candidate { offer_id, candidate }) => {
+                if let Some(peer_id) = self.find_peer(offer_id) {
+                    let result = WebRTCManager::add_remote_candidate(&self.peers[&peer_id].web_rtc_manager, &candidate);
+                    if result.is_err() {
+                        console::log_1(&format!("Error adding candidate: {:?}", result.err()).into());
+                    }
+                }
+                false
+            }
+            SignalingServiceMessage::Message(SignalingMessage::Leave { offer_id }) => {
+                let peer_id = self.find_peer(offer_id);
+                match peer_id {
+                    Some(peer_id) if self.pending_peer != Some(peer_id) => {
+                        self.remove_peer(peer_id);
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            SignalingServiceMessage::Message(SignalingMessage::Close | SignalingMessage::Expired) => {
+                self.signaling = None;
+                self.handle_session_gone(ctx)
+            }
+            SignalingServiceMessage::Message(SignalingMessage::Error { status }) => {
+                console::log_1(&format!("Signaling error: {:?}", status).into());
+                false
+            }
+            SignalingServiceMessage::Message(_) => false,
+            SignalingServiceMessage::Closed => {
+                // Keep the session reachable through long polling if the socket drops
+                if self.signaling.take().is_some() && !self.code.is_empty() {
+                    api_service::poll_session(ctx.link().callback(Msg::CallbackApi), self.code.clone(), self.owner_token.clone());
+
+                    // Long polling clients only see bundled candidates, so republish the full offer
+                    let gathered = self.pending_peer
+                        .and_then(|peer_id| self.peers.get(&peer_id))
+                        .map_or(false, |peer| peer.web_rtc_state.ice_gathering_state == Some(web_sys::RtcIceGatheringState::Complete));
+                    if gathered {
+                        self.update_session(ctx);
+                    }
+                }
+                false
+            }
+        }
+    }
+
+    fn handle_answer(&mut self, ctx: &Context<Self>, offer_id: u32, connection_details: &str) -> bool {
+        let peer_id = match self.find_peer(offer_id) {
+            Some(peer_id) => peer_id,
+            None => return false,
+        };
+        // Answers are redelivered after a server restart, only the pending peer still needs one
+        if self.pending_peer != Some(peer_id) {
+            return false;
+        }
+
+        let web_rtc_manager = &self.peers[&peer_id].web_rtc_manager;
+        let _ = WebRTCManager::validate_answer(web_rtc_manager, connection_details);
+        let endpoint = RelayEndpoint::Host { code: self.code.clone(), owner_token: self.owner_token.clone(), offer_id };
+        WebRTCManager::arm_relay(web_rtc_manager, endpoint, self.relay_timeout_secs);
+        self.start_pending_peer(ctx);
+        true
+    }
+
+    /// Without the server's list peers still connect where host candidates suffice, like on a LAN.
+    fn handle_ice_servers(&mut self, ctx: &Context<Self>, result: Result<IceServers, u16>) {
+        match result {
+            Ok(ice_servers) => {
+                // Later peers need TURN credentials that have not expired yet
+                if ice_servers.ttl_secs > 0 {
+                    let callback = ctx.link().callback(Msg::CallbackApi);
+                    let delay_ms = (ice_servers.ttl_secs * 500).min(u32::MAX as u64) as u32;
+                    self.ice_servers_refresh = Some(Timeout::new(delay_ms, move || api_service::get_ice_servers(callback)));
+                }
+                self.relay_timeout_secs = ice_servers.relay_timeout_secs;
+                self.ice_servers = Some(ice_servers.ice_servers);
+            }
+            Err(status) => {
+                console::log_1(&format!("Error fetching ICE servers: {:?}", status).into());
+                if self.ice_servers.is_none() {
+                    self.ice_servers = Some(Vec::new());
+                }
+            }
+        }
+
+        if self.start_requested {
+            self.start_requested = false;
+            ctx.link().send_message(Msg::SessionStart);
+        }
+    }
+
+    fn handle_session_gone(&mut self, ctx: &Context<Self>) -> bool {
+        if self.code.is_empty() {
+            return false;
+        }
+        console::log_1(&"Session expired".into());
+        self.code = String::new();
+        self.reset(ctx);
+        true
+    }
+
+    fn find_peer(&self, offer_id: u32) -> Option<u32> {
+        self.peers
+            .iter()
+            .find(|(_, peer)| peer.offer_id == Some(offer_id))
+            .map(|(peer_id, _)| *peer_id)
+    }
+
+    fn set_pending_offer_id(&mut self, offer_id: u32) {
+        let peer_id = match self.pending_peer {
+            Some(peer_id) => peer_id,
+            None => return,
+        };
+        if let Some(peer) = self.peers.get_mut(&peer_id) {
+            peer.offer_id = Some(offer_id);
+        }
+        self.send_candidates(peer_id);
+    }
+
     fn view_session_create(&self, ctx: &Context<Self>) -> Html {
-        let creation_disabled = self.web_rtc_state.ice_gathering_state == Some(web_sys::RtcIceGatheringState::Gathering);
+        let creation_disabled = self.pending_peer.is_some();
         html! {
             <div class="container mt-5">
                 <div class="row justify-content-center">
@@ -354,58 +814,84 @@ impl Host {
     }
 
     fn view_session_share(&self, ctx: &Context<Self>) -> Html {
-        let url = format!("{}/receive/{}", self.origin, self.code);
-
         html! {
             <div class="container mt-5">
                 <div class="row justify-content-center">
                     <div class="col-md-6">
                         <h2 class="text-center mb-4">{"Share the link"}</h2>
-                        <div class="input-group">
-                            <input type="text" ref={self.node_share.clone()} class="form-control" value={url} readonly={true} />
-                            <div class="input-group-append">
-                                <button onclick={ctx.link().callback(|_| Msg::CopyShareLink)} class="btn btn-outline-secondary" type="button">{"Copy"}</button>
-                            </div>
-                        </div>
+                        {self.view_share_link(ctx)}
+                        {self.view_store(ctx)}
                     </div>
                 </div>
+                {if self.files.len() > 0 {self.view_files_table(ctx)} else {html!{}}}
             </div>
         }
     }
 
-    fn view_session_handle(&self, ctx: &Context<Self>) -> Html {
-        let section_table = {
-            html! {
-                <div class="table-wrapper table-responsive">
-                    <table class="table custom-table table-bordered">
-                        <thead>
-                            <tr>
-                                <th>{"#"}</th>
-                                <th>{"Name"}</th>
-                                <th>{"Size"}</th>
-                                <th></th>
-                            </tr>
-                        </thead>
-                        <tbody>
-                            {
-                                for self.files.iter().enumerate().map(|(index, (_, file))| {
-                                    let tag = file.tag.clone();
-                                    html! {
-                                        <tr>
-                                            <td>{index}</td>
-                                            <td class="table-name">{&tag.name()}</td>
-                                            <td>{convert_bytes_to_readable_format(tag.size() as u64)}</td>
-                                            <td>{Self::view_control_pannel(ctx, file)}</td>
-                                        </tr>
-                                    }
-                                })
-                            }
-                        </tbody>
-                    </table>
+    fn view_share_link(&self, ctx: &Context<Self>) -> Html {
+        let mut url = format!("{}/receive/{}", self.origin, self.code);
+        if let Some(store_key) = &self.store_key {
+            url = format!("{}#{}", url, store_key.encoded());
+        }
+
+        html! {
+            <div class="input-group">
+                <input type="text" ref={self.node_share.clone()} class="form-control" value={url} readonly={true} />
+                <div class="input-group-append">
+                    <button onclick={ctx.link().callback(|_| Msg::CopyShareLink)} class="btn btn-outline-secondary" type="button">{"Copy"}</button>
                 </div>
-            }
-        };
+            </div>
+        }
+    }
 
+    fn view_store(&self, ctx: &Context<Self>) -> Html {
+        if !self.store_available || self.store_key.is_none() {
+            return html! {};
+        }
+
+        html! {
+            <div class="mt-3">
+                <p class="mb-1 text-secondary">{"Keep files on the server, clients can download them after you left:"}</p>
+                <DropFiles onupdate={ctx.link().callback(Msg::Store)} />
+            </div>
+        }
+    }
+
+    fn view_files_table(&self, ctx: &Context<Self>) -> Html {
+        html! {
+            <div class="table-wrapper table-responsive">
+                <table class="table custom-table table-bordered">
+                    <thead>
+                        <tr>
+                            <th>{"#"}</th>
+                            <th>{"Name"}</th>
+                            <th>{"Size"}</th>
+                            <th></th>
+                        </tr>
+                    </thead>
+                    <tbody>
+                        {
+                            for self.files.iter().enumerate().map(|(index, (_, file))| {
+                                let tag = file.tag.clone();
+                                html! {
+                                    <tr>
+                                        <td>{index}</td>
+                                        <td class="table-name">{&tag.name()}</td>
+                                        <td>{convert_bytes_to_readable_format(tag.size() as u64)}</td>
+                                        <td>{Self::view_control_pannel(ctx, file)}</td>
+                                    </tr>
+                                }
+                            })
+                        }
+                    </tbody>
+                </table>
+            </div>
+        }
+    }
+
+    fn view_session_handle(&self, ctx: &Context<Self>) -> Html {
+        let clients = self.peers.values().filter(|peer| Self::peer_connected(peer)).count();
+        let relayed = self.peers.values().filter(|peer| peer.web_rtc_state.relayed).count();
         html! {
             <div class="container mt-5 d-flex flex-column justify-content-center align-items-center">
                 <div class="col-md-9 info-panel bg-light p-3 rounded text-center mb-3">
@@ -413,51 +899,78 @@ impl Host {
                         <p class="d-flex align-items-center mb-0">
                             <span class="pl-3 pr-1 font-weight-bold">{"Connection:"}</span>
                             <span class="text-success">{"🟢"}</span>//todo: Add timeout indicator
+                            <span class="pl-1">{if relayed > 0 {format!("relayed ({})", relayed)} else {"direct".to_string()}}</span>
+                        </p>
+                        <p class="d-flex align-items-center mb-0">
+                            <span class="pl-3 pr-1 font-weight-bold">{"Clients:"}</span>
+                            <span>{clients}</span>
                         </p>
                         <p class="d-flex align-items-center mb-0">
-                            <span class="pl-3 pr-1 font-weight-bold">{"Password:"}</span> 
+                            <span class="pl-3 pr-1 font-weight-bold">{"Password:"}</span>
                             <span>{format!("{}", if self.password.is_empty() {"🔓"} else {"🔒"})}</span>
                         </p>
                         <p class="d-flex align-items-center mb-0">
-                            <span class="pl-3 pr-1 font-weight-bold">{"Compression:"}</span> 
+                            <span class="pl-3 pr-1 font-weight-bold">{"Compression:"}</span>
                             <span>{self.compression_level}</span>
                         </p>
                     </div>
+                    <div class="mt-2">
+                        {self.view_share_link(ctx)}
+                    </div>
                     <div class="mt-2">
                         <DropFiles onupdate={ctx.link().callback(Msg::Update)} />
                     </div>
+                    {self.view_store(ctx)}
                 </div>
-                {if self.files.len() > 0 {section_table} else {html!{}}}
+                {if self.files.len() > 0 {self.view_files_table(ctx)} else {html!{}}}
             </div>
-        }        
+        }
     }
 
     fn view_control_pannel(ctx: &Context<Self>, file: &FileItem) -> Html {
-        match file.state {
-            FileState::Pending => {
-                let tag = file.tag.clone();
-                html! {
-                    <button class="btn btn-outline-secondary" onclick={ctx.link().callback(move |_| Msg::FileRemove(tag.clone()))}>{ "Remove" }</button>
-                }
-            }
-            FileState::Transferring => {
-                html! {
-                    <div class="progress" style="height: 25px;">
-                        <div class="progress-bar" role="progressbar" style={format!("width: {}%", (file.progress*100.0) as u32)} aria-valuenow={format!("{}%", (file.progress*100.0) as u32)} aria-valuemin="0" aria-valuemax="100">
-                            <span style="color: white; text-shadow: 1px 1px 3px rgba(0, 0, 0, 0.6);">{format!("Progress: {}%", (file.progress*100.0) as u32)}</span>
-                        </div>
-                    </div>
-                }
-            }
-            FileState::Done => {
-                html! {
-                    <p>{ "Done" }</p>
-                }
-            }
-            _ => {
-                html! {
-                }
+        if file.transfers.is_empty() && file.stored.is_none() {
+            let tag = file.tag.clone();
+            return html! {
+                <button class="btn btn-outline-secondary" onclick={ctx.link().callback(move |_| Msg::FileRemove(tag.clone()))}>{ "Remove" }</button>
+            };
+        }
+
+        let stored = match file.stored {
+            Some(StoreProgress::Uploading(progress)) if progress >= 1.0 => html! {
+                <p class="mb-1">{"Stored"}</p>
+            },
+            Some(StoreProgress::Uploading(progress)) => Self::view_progress("Storing", progress),
+            Some(StoreProgress::Failed) => html! {
+                <p class="mb-1 text-danger">{"Storing failed"}</p>
+            },
+            None => html! {},
+        };
+
+        html! {
+            <>
+            {stored}
+            {
+                for file.transfers.iter().map(|(peer_id, progress)| {
+                    if *progress >= 1.0 {
+                        html! {
+                            <p class="mb-1">{ format!("Client {}: Done", peer_id + 1) }</p>
+                        }
+                    } else {
+                        Self::view_progress(&format!("Client {}", peer_id + 1), *progress)
+                    }
+                })
             }
+            </>
+        }
+    }
+
+    fn view_progress(label: &str, progress: f64) -> Html {
+        html! {
+            <div class="progress mb-1" style="height: 25px;">
+                <div class="progress-bar" role="progressbar" style={format!("width: {}%", (progress*100.0) as u32)} aria-valuenow={format!("{}%", (progress*100.0) as u32)} aria-valuemin="0" aria-valuemax="100">
+                    <span style="color: white; text-shadow: 1px 1px 3px rgba(0, 0, 0, 0.6);">{format!("{}: {}%", label, (progress*100.0) as u32)}</span>
+                </div>
+            </div>
         }
     }
 
@@ -472,4 +985,4 @@ impl Host {
             HostState::Sharing
         }
     }
-}
\ No newline at end of file
+}
@@ -1,19 +1,23 @@
-use gloo::net::http::Request;
+use gloo::net::http::{Request, Response};
 use wasm_bindgen::JsValue;
 use yew::Callback;
 
 use crate::constants::{HOST_ADDRESS, PORT};
 use crate::shared::{
-    ClientGetDetails, ClientGetDetailsResult, ClientJoin, ClientJoinResult, HostCreate, HostCreateResult, HostPollResult,
+    ApiError, ClientGetDetails, ClientGetDetailsResult, ClientJoin, ClientJoinResult, HostCreate, HostCreateResult, HostPollResult,
+    HostUpdate, HostUpdateResult, IceServers, VersionInfo,
 };
 
 const POLL_WAIT_TIME_ONE_TIMOUT: u64 = 1000;
 
 pub enum ApiServiceMessage {
     HostCreate(Result<HostCreateResult, u16>),
+    HostUpdate(Result<HostUpdateResult, u16>),
     HostPoll(Result<HostPollResult, u16>),
     ClientDetails(Result<ClientGetDetailsResult, u16>),
     ClientJoin(Result<ClientJoinResult, u16>),
+    IceServers(Result<IceServers, u16>),
+    Version(Result<VersionInfo, u16>),
 }
 
 pub mod api_service {
@@ -33,10 +37,8 @@ pub mod api_service {
             compression_level,
             password,
         };
-        let session_create_str =
-            serde_json::to_string(&session_create).expect("Serialization failed");
-        let url = get_host_address() + "/api/sessions";
-        let request = Request::post(&url).json(&session_create_str);
+        let url = get_host_address() + "/api/v2/sessions";
+        let request = Request::post(&url).json(&session_create);
 
         let callback_result = move |response: Result<String, u16>| {
             if response.is_err() {
@@ -66,12 +68,48 @@ pub mod api_service {
         execute_api_call(callback_result, request.unwrap());
     }
 
-    pub fn poll_session(callback: Callback<ApiServiceMessage>, code: String) {
-        let url = get_host_address() + "/api/sessions/poll/" + &code;
+    pub fn update_session(callback: Callback<ApiServiceMessage>, code: String, owner_token: String, connection_details: String) {
+        let session_update = HostUpdate { connection_details };
+        let url = get_host_address() + "/api/v2/sessions/" + &code;
+        let request = Request::put(&url)
+            .header("Authorization", &bearer(&owner_token))
+            .json(&session_update);
 
         let callback_result = move |response: Result<String, u16>| {
             if response.is_err() {
-                console::log_1(&JsValue::from_str(&format!("Error: {:?}", response.err())));
+                let status = response.unwrap_err();
+                callback.emit(ApiServiceMessage::HostUpdate(Err(status)));
+                return;
+            }
+
+            let response = response.unwrap();
+            let response = serde_json::from_str::<HostUpdateResult>(&response);
+            if response.is_err() {
+                console::log_1(&JsValue::from_str(&format!(
+                    "Error updating session: {:?}",
+                    response.err()
+                )));
+                return;
+            }
+            let response = response.unwrap();
+            callback.emit(ApiServiceMessage::HostUpdate(Ok(response)));
+        };
+
+        if request.is_err() {
+            console::log_1(&JsValue::from_str(&format!("Error: {:?}", request.err())));
+            return;
+        }
+
+        execute_api_call(callback_result, request.unwrap());
+    }
+
+    pub fn poll_session(callback: Callback<ApiServiceMessage>, code: String, owner_token: String) {
+        let url = get_host_address() + "/api/v2/sessions/poll/" + &code;
+
+        let callback_result = move |response: Result<String, u16>| {
+            if response.is_err() {
+                let status = response.unwrap_err();
+                callback.emit(ApiServiceMessage::HostPoll(Err(status)));
                 return;
             }
 
@@ -88,10 +126,58 @@ pub mod api_service {
             callback.emit(ApiServiceMessage::HostPoll(Ok(response)));
         };
 
-        let request_builder = move |url: &str| Request::get(url).build().expect("Request build failed");
+        let request_builder = move |url: &str| {
+            Request::get(url)
+                .header("Authorization", &bearer(&owner_token))
+                .build()
+                .expect("Request build failed")
+        };
         execute_api_poll(callback_result, url, request_builder);
     }
 
+    pub fn close_session(code: String, owner_token: String) {
+        let url = get_host_address() + "/api/v2/sessions/" + &code;
+        let request = Request::delete(&url)
+            .header("Authorization", &bearer(&owner_token))
+            .build();
+
+        let callback_result = move |response: Result<String, u16>| {
+            if response.is_err() {
+                console::log_1(&JsValue::from_str(&format!(
+                    "Error closing session: {:?}",
+                    response.err()
+                )));
+            }
+        };
+
+        if request.is_err() {
+            console::log_1(&JsValue::from_str(&format!("Error: {:?}", request.err())));
+            return;
+        }
+
+        execute_api_call(callback_result, request.unwrap());
+    }
+
+    /// Closes the session while the page unloads. Requests started through gloo are cancelled
+    /// with the page, a `keepalive` fetch is sent regardless.
+    pub fn close_session_on_unload(code: &str, owner_token: &str) {
+        let url = get_host_address() + "/api/v2/sessions/" + code;
+        let headers = js_sys::Object::new();
+        let mut init = web_sys::RequestInit::new();
+        init.method("DELETE").headers(&headers);
+        // web-sys has no setter for `keepalive` yet
+        let fields = js_sys::Reflect::set(&headers, &JsValue::from_str("Authorization"), &JsValue::from_str(&bearer(owner_token)))
+            .and_then(|_| js_sys::Reflect::set(&init, &JsValue::from_str("keepalive"), &JsValue::TRUE));
+        if fields.is_err() {
+            console::log_1(&JsValue::from_str(&format!("Error: {:?}", fields.err())));
+            return;
+        }
+
+        if let Some(window) = web_sys::window() {
+            let _ = window.fetch_with_str_and_init(&url, &init);
+        }
+    }
+
     pub fn get_session_details(
         callback: Callback<ApiServiceMessage>,
         code: &str,
@@ -101,9 +187,8 @@ pub mod api_service {
             code: code.to_string(),
             password: password.unwrap_or("".to_string()),
         };
-        let session_join_str = serde_json::to_string(&session_join).expect("Serialization failed");
-        let url = get_host_address() + "/api/sessions/details";
-        let request = Request::post(&url).json(&session_join_str);
+        let url = get_host_address() + "/api/v2/sessions/details";
+        let request = Request::post(&url).json(&session_join);
 
         let callback_result = move |response: Result<String, u16>| {
             if response.is_err() {
@@ -137,16 +222,17 @@ pub mod api_service {
         callback: Callback<ApiServiceMessage>,
         code: String,
         password: Option<String>,
+        offer_id: u32,
         connection_details: String,
     ) {
         let session_join = ClientJoin {
             code,
             password: password.unwrap_or("".to_string()),
+            offer_id,
             connection_details,
         };
-        let session_join_str = serde_json::to_string(&session_join).expect("Serialization failed");
-        let url = get_host_address() + "/api/sessions/join";
-        let request = Request::post(&url).json(&session_join_str);
+        let url = get_host_address() + "/api/v2/sessions/join";
+        let request = Request::post(&url).json(&session_join);
 
         let callback_result = move |response: Result<String, u16>| {
             if response.is_err() {
@@ -176,6 +262,72 @@ pub mod api_service {
         execute_api_call(callback_result, request.unwrap());
     }
 
+    pub fn get_ice_servers(callback: Callback<ApiServiceMessage>) {
+        let url = get_host_address() + "/api/ice-servers";
+        let request = Request::get(&url).build();
+
+        let callback_result = move |response: Result<String, u16>| {
+            if response.is_err() {
+                let status = response.unwrap_err();
+                callback.emit(ApiServiceMessage::IceServers(Err(status)));
+                return;
+            }
+
+            let response = response.unwrap();
+            let response = serde_json::from_str::<IceServers>(&response);
+            if response.is_err() {
+                console::log_1(&JsValue::from_str(&format!(
+                    "Error fetching ICE servers: {:?}",
+                    response.err()
+                )));
+                callback.emit(ApiServiceMessage::IceServers(Err(500)));
+                return;
+            }
+            let response = response.unwrap();
+            callback.emit(ApiServiceMessage::IceServers(Ok(response)));
+        };
+
+        if request.is_err() {
+            console::log_1(&JsValue::from_str(&format!("Error: {:?}", request.err())));
+            return;
+        }
+
+        execute_api_call(callback_result, request.unwrap());
+    }
+
+    pub fn get_version(callback: Callback<ApiServiceMessage>) {
+        let url = get_host_address() + "/api/version";
+        let request = Request::get(&url).build();
+
+        let callback_result = move |response: Result<String, u16>| {
+            if response.is_err() {
+                let status = response.unwrap_err();
+                callback.emit(ApiServiceMessage::Version(Err(status)));
+                return;
+            }
+
+            let response = response.unwrap();
+            let response = serde_json::from_str::<VersionInfo>(&response);
+            if response.is_err() {
+                console::log_1(&JsValue::from_str(&format!(
+                    "Error fetching version: {:?}",
+                    response.err()
+                )));
+                callback.emit(ApiServiceMessage::Version(Err(500)));
+                return;
+            }
+            let response = response.unwrap();
+            callback.emit(ApiServiceMessage::Version(Ok(response)));
+        };
+
+        if request.is_err() {
+            console::log_1(&JsValue::from_str(&format!("Error: {:?}", request.err())));
+            return;
+        }
+
+        execute_api_call(callback_result, request.unwrap());
+    }
+
     fn execute_api_call(callback: impl FnOnce(Result<String, u16>) + 'static, request: Request) {
         wasm_bindgen_futures::spawn_local(async move {
             let response = request.send().await;
@@ -188,6 +340,7 @@ pub mod api_service {
             
             if response.status() != 200 {
                 let status = response.status();
+                log_api_error(response).await;
                 return callback(Err(status));
             }
             let response = response.text().await;
@@ -198,7 +351,7 @@ pub mod api_service {
         });
     }
 
-    fn execute_api_poll(callback: impl FnOnce(Result<String, u16>) + Clone + 'static, url: String, request_builder: impl FnOnce(&str) -> Request + std::marker::Copy + 'static) {
+    fn execute_api_poll(callback: impl FnOnce(Result<String, u16>) + Clone + 'static, url: String, request_builder: impl Fn(&str) -> Request + 'static) {
         wasm_bindgen_futures::spawn_local(async move {
             let request = request_builder(&url);
             let response = request.send().await;
@@ -217,6 +370,7 @@ pub mod api_service {
             
             if response.status() != 200 {
                 let status = response.status();
+                log_api_error(response).await;
                 return callback(Err(status));
             }
             let response = response.text().await;
@@ -227,7 +381,17 @@ pub mod api_service {
         });
     }
 
-    fn get_host_address() -> String {
+    pub async fn log_api_error(response: Response) {
+        if let Ok(error) = response.json::<ApiError>().await {
+            console::log_1(&JsValue::from_str(&format!("API error {}: {}", error.code, error.message)));
+        }
+    }
+
+    pub fn bearer(owner_token: &str) -> String {
+        format!("Bearer {}", owner_token)
+    }
+
+    pub fn get_host_address() -> String {
         let address = HOST_ADDRESS.to_string() + ":" + &PORT.to_string();
         address
     }
@@ -118,6 +118,11 @@ impl DownloadManager {
         self.chunk_counter = 0;
     }
 
+    /// Gives up on the current file, accepting it again overwrites the chunks saved so far.
+    pub fn cancel(&mut self) {
+        self.file_tag = None;
+    }
+
     pub fn save_chunk(&mut self, chunk: &JsValue, size: u32) -> Result<bool, JsValue> {
         if self.file_tag.is_none() {
             return Err(JsValue::from_str("No file tag set"));
@@ -1,4 +1,7 @@
 pub mod api_service;
 pub mod compression;
 pub mod download_manager;
+pub mod relay;
+pub mod signaling;
+pub mod stored_share;
 pub mod web_rtc;
\ No newline at end of file
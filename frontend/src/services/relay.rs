@@ -0,0 +1,105 @@
+use futures::channel::mpsc::{self, UnboundedSender};
+use futures::{SinkExt, StreamExt};
+use gloo::net::websocket::futures::WebSocket;
+use gloo::net::websocket::Message;
+use wasm_bindgen::JsValue;
+use web_sys::console;
+use yew::Callback;
+
+use super::signaling::get_signaling_address;
+use crate::shared::SignalingMessage;
+
+pub enum RelayServiceMessage {
+    Open,
+    Message(String),
+    Data(Vec<u8>),
+    Closed,
+}
+
+/// Which side of an offer the relay connects, with what it needs to authorize.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RelayEndpoint {
+    Host { code: String, owner_token: String, offer_id: u32 },
+    Client { code: String, relay_token: String, offer_id: u32 },
+}
+
+/// WebSocket pipe to the peer through the server, carrying what the data channels would.
+/// Text frames are messages, binary frames data chunks. Dropping the service closes the socket.
+pub struct RelayService {
+    sender: UnboundedSender<Message>,
+}
+
+impl RelayService {
+    pub fn connect(callback: Callback<RelayServiceMessage>, endpoint: &RelayEndpoint) -> Option<Self> {
+        // Browsers cannot set headers on WebSocket requests, so the tokens go in the query
+        let path = match endpoint {
+            RelayEndpoint::Host { code, owner_token, offer_id } => {
+                format!("/api/sessions/{}/relay/{}/host?token={}", code, offer_id, owner_token)
+            }
+            RelayEndpoint::Client { code, relay_token, offer_id } => {
+                format!("/api/sessions/{}/relay/{}?token={}", code, offer_id, relay_token)
+            }
+        };
+
+        let url = get_signaling_address() + &path;
+        let web_socket = WebSocket::open(&url);
+        if web_socket.is_err() {
+            console::log_1(&JsValue::from_str(&format!("Error opening relay connection: {:?}", web_socket.err())));
+            return None;
+        }
+
+        let (mut write, mut read) = web_socket.unwrap().split();
+        let (sender, mut receiver) = mpsc::unbounded::<Message>();
+
+        wasm_bindgen_futures::spawn_local(async move {
+            while let Some(message) = receiver.next().await {
+                if write.send(message).await.is_err() {
+                    break;
+                }
+            }
+            let _ = write.close().await;
+        });
+
+        wasm_bindgen_futures::spawn_local(async move {
+            // The server answers with a signaling message once the peer has joined too
+            let mut open = false;
+            while let Some(message) = read.next().await {
+                match message {
+                    Ok(Message::Text(message)) if open => callback.emit(RelayServiceMessage::Message(message)),
+                    Ok(Message::Bytes(data)) if open => callback.emit(RelayServiceMessage::Data(data)),
+                    Ok(Message::Text(message)) => match serde_json::from_str::<SignalingMessage>(&message) {
+                        Ok(SignalingMessage::Relay) => {
+                            open = true;
+                            callback.emit(RelayServiceMessage::Open);
+                        }
+                        Ok(SignalingMessage::Error { status }) => {
+                            console::log_1(&JsValue::from_str(&format!("Relay error: {:?}", status)));
+                            break;
+                        }
+                        _ => continue,
+                    },
+                    Ok(Message::Bytes(_)) => continue,
+                    Err(_) => break,
+                }
+            }
+            callback.emit(RelayServiceMessage::Closed);
+        });
+
+        Some(Self { sender })
+    }
+
+    pub fn send_message(&self, message: &str) {
+        self.send(Message::Text(message.to_string()));
+    }
+
+    pub fn send_data(&self, data: Vec<u8>) {
+        self.send(Message::Bytes(data));
+    }
+
+    fn send(&self, message: Message) {
+        let result = self.sender.unbounded_send(message);
+        if result.is_err() {
+            console::log_1(&JsValue::from_str("Relay connection is closed"));
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use futures::channel::mpsc::{self, UnboundedSender};
+use futures::{SinkExt, StreamExt};
+use gloo::net::websocket::futures::WebSocket;
+use gloo::net::websocket::Message;
+use wasm_bindgen::JsValue;
+use web_sys::console;
+use yew::Callback;
+
+use crate::constants::{HOST_ADDRESS, PORT};
+use crate::shared::SignalingMessage;
+
+pub enum SignalingServiceMessage {
+    Message(SignalingMessage),
+    Closed,
+}
+
+/// WebSocket connection to the signaling server. Dropping the service closes the socket.
+pub struct SignalingService {
+    sender: UnboundedSender<SignalingMessage>,
+}
+
+impl SignalingService {
+    pub fn connect_host(callback: Callback<SignalingServiceMessage>, code: &str, owner_token: &str) -> Option<Self> {
+        // Browsers cannot set headers on WebSocket requests, so the owner token goes in the query
+        Self::connect(callback, format!("/api/sessions/{}/signaling/host?token={}", code, owner_token))
+    }
+
+    pub fn connect_client(callback: Callback<SignalingServiceMessage>, code: &str) -> Option<Self> {
+        Self::connect(callback, format!("/api/sessions/{}/signaling", code))
+    }
+
+    pub fn send(&self, message: SignalingMessage) {
+        let result = self.sender.unbounded_send(message);
+        if result.is_err() {
+            console::log_1(&JsValue::from_str("Signaling connection is closed"));
+        }
+    }
+
+    fn connect(callback: Callback<SignalingServiceMessage>, path: String) -> Option<Self> {
+        let url = get_signaling_address() + &path;
+        let web_socket = WebSocket::open(&url);
+        if web_socket.is_err() {
+            console::log_1(&JsValue::from_str(&format!("Error opening signaling connection: {:?}", web_socket.err())));
+            return None;
+        }
+
+        let (mut write, mut read) = web_socket.unwrap().split();
+        let (sender, mut receiver) = mpsc::unbounded::<SignalingMessage>();
+
+        wasm_bindgen_futures::spawn_local(async move {
+            while let Some(message) = receiver.next().await {
+                let message = serde_json::to_string(&message).expect("Serialization failed");
+                if write.send(Message::Text(message)).await.is_err() {
+                    break;
+                }
+            }
+            let _ = write.close().await;
+        });
+
+        wasm_bindgen_futures::spawn_local(async move {
+            while let Some(message) = read.next().await {
+                let message = match message {
+                    Ok(Message::Text(message)) => message,
+                    Ok(Message::Bytes(_)) => continue,
+                    Err(_) => break,
+                };
+
+                let message = serde_json::from_str::<SignalingMessage>(&message);
+                if message.is_err() {
+                    console::log_1(&JsValue::from_str(&format!("Error parsing signaling message: {:?}", message.err())));
+                    continue;
+                }
+                callback.emit(SignalingServiceMessage::Message(message.unwrap()));
+            }
+            callback.emit(SignalingServiceMessage::Closed);
+        });
+
+        Some(Self { sender })
+    }
+}
+
+pub fn get_signaling_address() -> String {
+    let address = HOST_ADDRESS.replacen("http", "ws", 1) + ":" + &PORT.to_string();
+    address
+}
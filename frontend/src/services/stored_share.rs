@@ -0,0 +1,240 @@
+use base64::{engine::general_purpose, Engine};
+use gloo::net::http::{Request, Response};
+use js_sys::{Array, Object, Reflect, Uint8Array};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{console, AesGcmParams, AesKeyGenParams, Blob, CryptoKey, SubtleCrypto};
+use yew::Callback;
+
+use super::api_service::api_service::{bearer, get_host_address, log_api_error};
+use super::compression::compression;
+use crate::file_tag::FileTag;
+use crate::shared::{ClientGetDetails, StoredFileCreate, StoredFileInfo, StoredShare};
+
+/// Plaintext per stored chunk, the server takes up to 2 MiB of ciphertext.
+const CHUNK_SIZE: f64 = 1024.0 * 1024.0;
+const IV_LENGTH: usize = 12;
+const ALGORITHM: &str = "AES-GCM";
+
+/// Key for the chunks and names of stored files. It travels in the fragment of the share link,
+/// which browsers never send to the server.
+#[derive(Clone, PartialEq)]
+pub struct StoreKey {
+    key: CryptoKey,
+    encoded: String,
+}
+
+impl StoreKey {
+    pub async fn generate() -> Result<Self, JsValue> {
+        let subtle = subtle()?;
+        let params = AesKeyGenParams::new(ALGORITHM, 256);
+        let key = JsFuture::from(subtle.generate_key_with_object(&params, true, &key_usages())?).await?;
+        let key: CryptoKey = key.dyn_into()?;
+
+        let raw = JsFuture::from(subtle.export_key("raw", &key)?).await?;
+        let encoded = general_purpose::URL_SAFE_NO_PAD.encode(Uint8Array::new(&raw).to_vec());
+        Ok(Self { key, encoded })
+    }
+
+    pub async fn import(encoded: &str) -> Result<Self, JsValue> {
+        let raw = general_purpose::URL_SAFE_NO_PAD
+            .decode(encoded)
+            .map_err(|_| JsValue::from_str("Invalid store key"))?;
+        let algorithm = Object::new();
+        Reflect::set(&algorithm, &"name".into(), &ALGORITHM.into())?;
+
+        let raw = Uint8Array::from(raw.as_slice());
+        let key = JsFuture::from(subtle()?.import_key_with_object("raw", &raw, &algorithm, false, &key_usages())?).await?;
+        Ok(Self { key: key.dyn_into()?, encoded: encoded.to_string() })
+    }
+
+    pub fn encoded(&self) -> &str {
+        &self.encoded
+    }
+
+    /// Returns the random IV followed by the ciphertext, which only decrypts with the same `additional_data`.
+    async fn encrypt(&self, data: &mut [u8], additional_data: &[u8]) -> Result<Vec<u8>, JsValue> {
+        let mut iv = [0u8; IV_LENGTH];
+        web_sys::window()
+            .ok_or(JsValue::from_str("No global `window` exists"))?
+            .crypto()?
+            .get_random_values_with_u8_array(&mut iv)?;
+
+        let mut params = AesGcmParams::new(ALGORITHM, &Uint8Array::from(&iv[..]));
+        params.additional_data(&Uint8Array::from(additional_data));
+        let ciphertext = JsFuture::from(subtle()?.encrypt_with_object_and_u8_array(&params, &self.key, data)?).await?;
+        let mut sealed = iv.to_vec();
+        sealed.extend(Uint8Array::new(&ciphertext).to_vec());
+        Ok(sealed)
+    }
+
+    async fn decrypt(&self, sealed: &mut [u8], additional_data: &[u8]) -> Result<Vec<u8>, JsValue> {
+        if sealed.len() < IV_LENGTH {
+            return Err(JsValue::from_str("Stored chunk is too short"));
+        }
+        let (iv, ciphertext) = sealed.split_at_mut(IV_LENGTH);
+
+        let mut params = AesGcmParams::new(ALGORITHM, &Uint8Array::from(&iv[..]));
+        params.additional_data(&Uint8Array::from(additional_data));
+        let plaintext = JsFuture::from(subtle()?.decrypt_with_object_and_u8_array(&params, &self.key, ciphertext)?).await?;
+        Ok(Uint8Array::new(&plaintext).to_vec())
+    }
+
+    async fn encrypt_name(&self, uuid: &str, name: &str) -> Result<String, JsValue> {
+        let sealed = self.encrypt(&mut name.as_bytes().to_vec(), &name_additional_data(uuid)).await?;
+        Ok(general_purpose::STANDARD.encode(sealed))
+    }
+
+    async fn decrypt_name(&self, uuid: &str, name: &str) -> Result<String, JsValue> {
+        let mut sealed = general_purpose::STANDARD
+            .decode(name)
+            .map_err(|_| JsValue::from_str("Invalid stored file name"))?;
+        let name = self.decrypt(&mut sealed, &name_additional_data(uuid)).await?;
+        String::from_utf8(name).map_err(|_| JsValue::from_str("Invalid stored file name"))
+    }
+}
+
+/// Binds an encrypted name to its file, so the server cannot swap names between files.
+fn name_additional_data(uuid: &str) -> Vec<u8> {
+    let mut additional_data = vec![uuid.len() as u8];
+    additional_data.extend_from_slice(uuid.as_bytes());
+    additional_data
+}
+
+/// Binds an encrypted chunk to its file and position, and marks the last one, so the server can
+/// neither reorder, swap nor drop chunks without decryption failing.
+fn chunk_additional_data(uuid: &str, index: u32, last: bool) -> Vec<u8> {
+    let mut additional_data = name_additional_data(uuid);
+    additional_data.extend_from_slice(&index.to_be_bytes());
+    additional_data.push(last as u8);
+    additional_data
+}
+
+/// Uploads a file for later download: announces it under its encrypted name, then sends it in
+/// compressed and encrypted chunks, reporting the progress after each one. Even an empty file
+/// gets a chunk, the last one being marked is what tells recipients they have the whole file.
+pub async fn upload_file(code: &str, owner_token: &str, key: &StoreKey, tag: &FileTag, blob: &Blob, progress: &Callback<f64>) -> Result<(), u16> {
+    let files_url = format!("{}/api/v2/stored/{}/files", get_host_address(), code);
+    let uuid = tag.uuid().to_string();
+    let name = key.encrypt_name(&uuid, tag.name()).await.map_err(log_js_error)?;
+    let stored_file = StoredFileCreate {
+        uuid: uuid.clone(),
+        name,
+        size: blob.size() as u64,
+    };
+    let request = Request::post(&files_url)
+        .header("Authorization", &bearer(owner_token))
+        .json(&stored_file);
+    send(request).await?;
+
+    let mut offset = 0.0;
+    let mut index = 0;
+    loop {
+        let end = (offset + CHUNK_SIZE).min(blob.size());
+        let last = end >= blob.size();
+        let chunk = blob.slice_with_f64_and_f64(offset, end).map_err(log_js_error)?;
+        let chunk = JsFuture::from(chunk.array_buffer()).await.map_err(log_js_error)?;
+        let chunk = Uint8Array::new(&chunk).to_vec();
+
+        let mut chunk = compression::compress(chunk, 10).map_err(|_| 500u16)?;
+        let sealed = key.encrypt(&mut chunk, &chunk_additional_data(&uuid, index, last)).await.map_err(log_js_error)?;
+        let request = Request::put(&format!("{}/{}/{}", files_url, uuid, index))
+            .header("Authorization", &bearer(owner_token))
+            .body(Uint8Array::from(sealed.as_slice()));
+        send(request).await?;
+
+        if last {
+            break;
+        }
+        offset = end;
+        index += 1;
+        progress.emit(end / blob.size());
+    }
+
+    let request = Request::post(&format!("{}/{}/complete", files_url, uuid))
+        .header("Authorization", &bearer(owner_token))
+        .build();
+    send(request).await?;
+    progress.emit(1.0);
+    Ok(())
+}
+
+/// Looks up the stored files of a session, with their names decrypted.
+pub async fn fetch_share(code: &str, password: &str, key: &StoreKey) -> Result<StoredShare, u16> {
+    let details = ClientGetDetails {
+        code: code.to_string(),
+        password: password.to_string(),
+    };
+    let request = Request::post(&(get_host_address() + "/api/v2/stored/details")).json(&details);
+    let response = send(request).await?;
+    let mut share = response.json::<StoredShare>().await.map_err(|_| 500u16)?;
+
+    for file in share.files.iter_mut() {
+        file.name = key.decrypt_name(&file.uuid, &file.name).await.map_err(log_js_error)?;
+    }
+    Ok(share)
+}
+
+/// Downloads a stored file chunk by chunk, handing each one over decrypted and decompressed
+/// like the data channel does. Only a chunk count ending in the chunk the host marked as last
+/// decrypts completely, so a truncated file fails instead of arriving short.
+pub async fn download_file(code: &str, share: &StoredShare, key: &StoreKey, file: &StoredFileInfo, chunk: &Callback<(JsValue, u32)>) -> Result<(), u16> {
+    if file.chunks == 0 {
+        console::log_1(&JsValue::from_str("Stored file has no chunks"));
+        return Err(500);
+    }
+
+    for index in 0..file.chunks {
+        let url = format!(
+            "{}/api/v2/stored/{}/files/{}/{}?access_token={}",
+            get_host_address(),
+            code,
+            file.uuid,
+            index,
+            share.access_token
+        );
+        let response = send(Request::get(&url).build()).await?;
+        let mut sealed = response.binary().await.map_err(|_| 500u16)?;
+
+        let additional_data = chunk_additional_data(&file.uuid, index, index + 1 == file.chunks);
+        let compressed = key.decrypt(&mut sealed, &additional_data).await.map_err(log_js_error)?;
+        let data = compression::decompress(compressed).map_err(|_| 500u16)?;
+        let data = Uint8Array::from(data.as_slice());
+        chunk.emit((JsValue::from(data.buffer()), data.byte_length()));
+    }
+    Ok(())
+}
+
+async fn send(request: Result<Request, gloo::net::Error>) -> Result<Response, u16> {
+    if request.is_err() {
+        console::log_1(&JsValue::from_str(&format!("Error: {:?}", request.err())));
+        return Err(500);
+    }
+
+    let response = request.unwrap().send().await;
+    if response.is_err() {
+        return Err(500);
+    }
+
+    let response = response.unwrap();
+    if response.status() != 200 {
+        let status = response.status();
+        log_api_error(response).await;
+        return Err(status);
+    }
+    Ok(response)
+}
+
+fn subtle() -> Result<SubtleCrypto, JsValue> {
+    let window = web_sys::window().ok_or(JsValue::from_str("No global `window` exists"))?;
+    Ok(window.crypto()?.subtle())
+}
+
+fn key_usages() -> Array {
+    Array::of2(&"encrypt".into(), &"decrypt".into())
+}
+
+fn log_js_error(error: JsValue) -> u16 {
+    console::log_1(&JsValue::from_str(&format!("Stored file error: {:?}", error)));
+    500
+}
@@ -5,10 +5,11 @@
 use wasm_bindgen::{JsCast, JsValue};
 
 use std::cell::RefCell;
-use std::rc::Rc;
+use std::rc::{Rc, Weak};
 use std::str;
 
 use base64::{self, engine::general_purpose, Engine};
+use gloo::timers::callback::Timeout;
 use js_sys::{Array, Object, Reflect, JSON, ArrayBuffer};
 use serde::{Deserialize, Serialize};
 use wasm_bindgen::closure::Closure;
@@ -22,17 +23,19 @@ use web_sys::{
 use yew::callback::Callback;
 
 use super::compression::compression;
+use super::relay::{RelayEndpoint, RelayService, RelayServiceMessage};
+use crate::shared::IceServer;
 
 type SingleArgClosure = Closure<dyn FnMut(JsValue)>;
 type SingleArgJsFn = Box<dyn FnMut(JsValue)>;
 
-const STUN_SERVER: &str = "stun:stun.l.google.com:19302";
-
 #[derive(Clone, Debug, PartialEq)]
 pub enum WebRtcMessage {
     Message(String),
     Data(JsValue, u32),
     UpdateState(State),
+    LocalDescription,
+    Candidate,
     Reset,
 }
 
@@ -48,6 +51,7 @@ pub struct ConnectionState {
     pub ice_connection_state: Option<RtcIceConnectionState>,
     pub message_channel_state: Option<RtcDataChannelState>,
     pub data_channel_state: Option<RtcDataChannelState>,
+    pub relayed: bool,
 }
 
 impl ConnectionState {
@@ -57,8 +61,14 @@ impl ConnectionState {
             ice_connection_state: None,
             message_channel_state: None,
             data_channel_state: None,
+            relayed: false,
         }
     }
+
+    /// Connected directly or through the server relay.
+    pub fn is_connected(&self) -> bool {
+        self.relayed || self.ice_connection_state == Some(RtcIceConnectionState::Connected)
+    }
 }
 
 #[derive(PartialEq, Eq, Clone, Debug)]
@@ -92,6 +102,11 @@ pub struct WebRTCManager {
     exit_offer_or_answer_early: bool,
     ice_candidates: Vec<IceCandidate>,
     offer: Option<String>,
+    remote_description_set: bool,
+    remote_candidates: Vec<IceCandidate>,
+    relay_endpoint: Option<RelayEndpoint>,
+    relay_timeout: Option<Timeout>,
+    relay: Option<RelayService>,
 }
 
 impl WebRTCManager {
@@ -104,7 +119,12 @@ impl WebRTCManager {
             message_channel: None,
             ice_candidates: Vec::new(),
             offer: None,
+            remote_description_set: false,
+            remote_candidates: Vec::new(),
             exit_offer_or_answer_early: false,
+            relay_endpoint: None,
+            relay_timeout: None,
+            relay: None,
         }))
     }
 
@@ -113,7 +133,13 @@ impl WebRTCManager {
             State::Server(ref connection_state) | State::Client(ref connection_state) => connection_state.clone(),
             _ => panic!("Not implemented"),
         };
-        
+
+        if connection_state.relayed {
+            if let Some(relay) = &self.relay {
+                relay.send_message(message_content);
+            }
+            return;
+        }
         if connection_state.message_channel_state != Some(RtcDataChannelState::Open) {
             return;
         }
@@ -130,8 +156,8 @@ impl WebRTCManager {
             State::Server(ref connection_state) | State::Client(ref connection_state) => connection_state.clone(),
             _ => panic!("Not implemented"),
         };
-        
-        if connection_state.data_channel_state != Some(RtcDataChannelState::Open) {
+
+        if !connection_state.relayed && connection_state.data_channel_state != Some(RtcDataChannelState::Open) {
             return false;
         }
 
@@ -143,6 +169,15 @@ impl WebRTCManager {
         if compression_level > 0 {
             data_content = compression::compress(data_content.clone(), compression_level).unwrap();
         }
+        if connection_state.relayed {
+            return match &self.relay {
+                Some(relay) => {
+                    relay.send_data(data_content);
+                    true
+                }
+                None => false,
+            };
+        }
         let compressed_js_array = js_sys::Uint8Array::from(&data_content[..]);
         self.data_channel
             .as_ref()
@@ -152,6 +187,91 @@ impl WebRTCManager {
         true
     }
 
+    /// Falls back to the server relay when no direct connection comes up within `timeout_secs`,
+    /// or as soon as ICE gives up. A timeout of 0 means the server has no relay.
+    pub fn arm_relay(web_rtc_manager: &Rc<RefCell<Self>>, endpoint: RelayEndpoint, timeout_secs: u64) {
+        if timeout_secs == 0 {
+            return;
+        }
+
+        // The manager owns the timer, a strong reference in it would keep both alive forever
+        let manager = Rc::downgrade(web_rtc_manager);
+        let delay_ms = (timeout_secs * 1000).min(u32::MAX as u64) as u32;
+        let relay_timeout = Timeout::new(delay_ms, move || {
+            if let Some(manager) = manager.upgrade() {
+                Self::start_relay(&manager);
+            }
+        });
+
+        let mut manager = web_rtc_manager.borrow_mut();
+        manager.relay_endpoint = Some(endpoint);
+        manager.relay_timeout = Some(relay_timeout);
+    }
+
+    /// Whether a failed direct connection still has the relay to fall back on.
+    pub fn relay_armed(&self) -> bool {
+        self.relay_endpoint.is_some()
+    }
+
+    fn start_relay(web_rtc_manager: &Rc<RefCell<Self>>) {
+        let endpoint = {
+            let mut manager = web_rtc_manager.borrow_mut();
+            manager.relay_timeout = None;
+            let connected = match manager.state {
+                State::Server(ref connection_state) | State::Client(ref connection_state) => connection_state.ice_connection_state == Some(RtcIceConnectionState::Connected),
+                _ => false,
+            };
+            if connected || manager.relay.is_some() {
+                return;
+            }
+            match manager.relay_endpoint.clone() {
+                Some(endpoint) => endpoint,
+                None => return,
+            }
+        };
+
+        let manager: Weak<RefCell<Self>> = Rc::downgrade(web_rtc_manager);
+        let callback = Callback::from(move |message| {
+            if let Some(manager) = manager.upgrade() {
+                Self::on_relay_message(&manager, message);
+            }
+        });
+        match RelayService::connect(callback, &endpoint) {
+            Some(relay) => web_rtc_manager.borrow_mut().relay = Some(relay),
+            None => Self::on_relay_message(web_rtc_manager, RelayServiceMessage::Closed),
+        }
+    }
+
+    fn on_relay_message(web_rtc_manager: &Rc<RefCell<Self>>, message: RelayServiceMessage) {
+        match message {
+            RelayServiceMessage::Open => {
+                Self::update_state(web_rtc_manager, |connection_state, _| connection_state.relayed = true);
+            }
+            RelayServiceMessage::Message(message) => {
+                let callback = web_rtc_manager.borrow().callback.clone();
+                callback.emit(WebRtcMessage::Message(message));
+            }
+            RelayServiceMessage::Data(data) => {
+                let is_client = matches!(web_rtc_manager.borrow().state, State::Client(_));
+                if is_client {
+                    Self::transfer_bytes(web_rtc_manager, data);
+                } else {
+                    let data = js_sys::Uint8Array::from(data.as_slice());
+                    let callback = web_rtc_manager.borrow().callback.clone();
+                    callback.emit(WebRtcMessage::Data(JsValue::from(data.buffer()), data.byte_length()));
+                }
+            }
+            RelayServiceMessage::Closed => {
+                {
+                    let mut manager = web_rtc_manager.borrow_mut();
+                    manager.relay = None;
+                    manager.relay_endpoint = None;
+                }
+                Self::update_state(web_rtc_manager, |connection_state, _| connection_state.relayed = false);
+            }
+        }
+    }
+
     fn get_state(&self) -> State {
         self.state.clone()
     }
@@ -168,6 +288,34 @@ impl WebRTCManager {
         self.ice_candidates.clone()
     }
 
+    pub fn ice_candidate_count(&self) -> usize {
+        self.ice_candidates.len()
+    }
+
+    pub fn encoded_candidates(&self, skip: usize) -> Vec<String> {
+        self.ice_candidates
+            .iter()
+            .skip(skip)
+            .map(|candidate| serde_json::to_string(candidate).unwrap())
+            .collect()
+    }
+
+    /// Applies a candidate trickled in by the remote peer, holding it back until the
+    /// remote description has been set.
+    pub fn add_remote_candidate(
+        web_rtc_manager: &Rc<RefCell<WebRTCManager>>,
+        str: &str,
+    ) -> Result<(), OfferError> {
+        let candidate = serde_json::from_str::<IceCandidate>(str).map_err(|_| OfferError::SerializationError)?;
+
+        if !web_rtc_manager.borrow().remote_description_set {
+            web_rtc_manager.borrow_mut().remote_candidates.push(candidate);
+            return Ok(());
+        }
+        Self::set_candidates(web_rtc_manager.clone(), &[candidate]);
+        Ok(())
+    }
+
     pub fn validate_offer(
         web_rtc_manager: &Rc<RefCell<WebRTCManager>>,
         str: &str,
@@ -230,7 +378,7 @@ impl WebRTCManager {
         ice_offer_bundle: IceOfferBundle,
     ) -> SingleArgClosure {
         let set_candidates_function: SingleArgJsFn = Box::new(move |_: JsValue| {
-            WebRTCManager::set_candidates(web_rtc_manager.clone(), &ice_offer_bundle);
+            WebRTCManager::set_remote_candidates(web_rtc_manager.clone(), &ice_offer_bundle);
         });
         Closure::wrap(set_candidates_function)
     }
@@ -248,6 +396,7 @@ impl WebRTCManager {
                 .set_local_description(&answer);
             web_rtc_manager.borrow_mut().offer =
                 Some(String::from(JSON::stringify(&answer).unwrap()));
+            web_rtc_manager.borrow().callback.emit(WebRtcMessage::LocalDescription);
         });
         Closure::wrap(function)
     }
@@ -294,12 +443,12 @@ impl WebRTCManager {
     ) -> SingleArgClosure {
         let ice_offer_bundle = Rc::new(ice_offer_bundle);
         Closure::wrap(Box::new(move |_: JsValue| {
-            Self::set_candidates(web_rtc_manager.clone(), &*ice_offer_bundle);
+            Self::set_remote_candidates(web_rtc_manager.clone(), &*ice_offer_bundle);
         }) as SingleArgJsFn)
     }
 
-    pub fn start_web_rtc(web_rtc_manager: &Rc<RefCell<Self>>) -> Result<(), JsValue> {
-        let rtc_peer_connection = Self::initialize_rtc_peer_connection()?;
+    pub fn start_web_rtc(web_rtc_manager: &Rc<RefCell<Self>>, ice_servers: &[IceServer]) -> Result<(), JsValue> {
+        let rtc_peer_connection = Self::initialize_rtc_peer_connection(ice_servers)?;
         let state = web_rtc_manager.borrow().state.clone();
         match state {
             State::Server(_) => Self::setup_as_server(web_rtc_manager.clone(), rtc_peer_connection.clone()),
@@ -309,13 +458,22 @@ impl WebRTCManager {
         Self::setup_rtc_event_listeners(&web_rtc_manager, rtc_peer_connection)
     }
     
-    fn initialize_rtc_peer_connection() -> Result<RtcPeerConnection, JsValue> {
-        let ice_servers = Array::new();
-        let server_entry = Object::new();
-        Reflect::set(&server_entry, &"urls".into(), &STUN_SERVER.into())?;
-        ice_servers.push(&*server_entry);
+    fn initialize_rtc_peer_connection(ice_servers: &[IceServer]) -> Result<RtcPeerConnection, JsValue> {
+        let server_entries = Array::new();
+        for ice_server in ice_servers {
+            let urls: Array = ice_server.urls.iter().map(|url| JsValue::from_str(url)).collect();
+            let server_entry = Object::new();
+            Reflect::set(&server_entry, &"urls".into(), &urls)?;
+            if let Some(username) = &ice_server.username {
+                Reflect::set(&server_entry, &"username".into(), &username.into())?;
+            }
+            if let Some(credential) = &ice_server.credential {
+                Reflect::set(&server_entry, &"credential".into(), &credential.into())?;
+            }
+            server_entries.push(&*server_entry);
+        }
         let mut rtc_configuration = RtcConfiguration::new();
-        rtc_configuration.ice_servers(&ice_servers);
+        rtc_configuration.ice_servers(&server_entries);
         RtcPeerConnection::new_with_configuration(&rtc_configuration)
     }
     
@@ -389,13 +547,18 @@ impl WebRTCManager {
     
             let set_local_description_exception_handler = Self::get_exception_handler(web_rtc_manager.clone(), "set_local_description closure has encountered an exception".into());
             let _promise = web_rtc_manager.borrow_mut().rtc_peer_connection.as_ref().unwrap().set_local_description(&rtc_session_description).catch(&set_local_description_exception_handler);
+            web_rtc_manager.borrow().callback.emit(WebRtcMessage::LocalDescription);
         });
         Closure::wrap(function)
     }
 
     fn transfer_array_buffer(web_rtc_manager: Rc<RefCell<Self>>, buffer: &ArrayBuffer) {
         let data_content = js_sys::Uint8Array::new(buffer);
-        let data_decompressed = compression::decompress(data_content.to_vec()).unwrap();
+        Self::transfer_bytes(&web_rtc_manager, data_content.to_vec());
+    }
+
+    fn transfer_bytes(web_rtc_manager: &Rc<RefCell<Self>>, data_content: Vec<u8>) {
+        let data_decompressed = compression::decompress(data_content).unwrap();
         let decompressed_js_array = js_sys::Uint8Array::from(data_decompressed.as_slice());
         
         web_rtc_manager.borrow().callback.emit(WebRtcMessage::Data(
@@ -472,6 +635,7 @@ impl WebRTCManager {
                         sdp_m_line_index: candidate.sdp_m_line_index().unwrap(),
                     };
                     web_rtc_manager.borrow_mut().ice_candidates.push(saved_candidate);
+                    web_rtc_manager.borrow().callback.emit(WebRtcMessage::Candidate);
                 }
             }
         });
@@ -489,9 +653,23 @@ impl WebRTCManager {
         general_purpose::STANDARD.encode(serialized)
     }
 
-    fn set_candidates(
+    fn set_remote_candidates(
         web_rtc_manager: Rc<RefCell<Self>>,
         ice_offer_bundle: &IceOfferBundle,
+    ) {
+        let trickled_candidates = {
+            let mut manager = web_rtc_manager.borrow_mut();
+            manager.remote_description_set = true;
+            std::mem::take(&mut manager.remote_candidates)
+        };
+
+        Self::set_candidates(web_rtc_manager.clone(), &ice_offer_bundle.ice_candidates);
+        Self::set_candidates(web_rtc_manager, &trickled_candidates);
+    }
+
+    fn set_candidates(
+        web_rtc_manager: Rc<RefCell<Self>>,
+        candidates: &[IceCandidate],
     ) {
         let manager = web_rtc_manager.borrow();
     
@@ -509,7 +687,7 @@ impl WebRTCManager {
             "add_candidate closure has encountered an exception".into(),
         );
     
-        for candidate in &ice_offer_bundle.ice_candidates {
+        for candidate in candidates {
             let ice_candidate = Self::create_ice_candidate(candidate);
             
             let _promise = rtc_peer_connection
@@ -558,9 +736,18 @@ impl WebRTCManager {
     fn get_on_ice_connection_state_change_closure(
         web_rtc_manager: Rc<RefCell<Self>>,
     ) -> SingleArgClosure {
-        Self::get_closure(web_rtc_manager, |connection_state, inner| {
-            connection_state.ice_connection_state = Some(inner.rtc_peer_connection.as_ref().unwrap().ice_connection_state());
-        })
+        Closure::wrap(Box::new(move |_event: JsValue| {
+            let ice_connection_state = web_rtc_manager.borrow().rtc_peer_connection.as_ref().unwrap().ice_connection_state();
+            match ice_connection_state {
+                // Once connected directly the timeout is moot, the relay only takes over if ICE fails later
+                RtcIceConnectionState::Connected => web_rtc_manager.borrow_mut().relay_timeout = None,
+                RtcIceConnectionState::Failed => Self::start_relay(&web_rtc_manager),
+                _ => {}
+            }
+            Self::update_state(&web_rtc_manager, |connection_state, _| {
+                connection_state.ice_connection_state = Some(ice_connection_state);
+            });
+        }) as SingleArgJsFn)
     }
 
     fn get_on_ice_gathering_state_change_closure(
@@ -579,28 +766,38 @@ impl WebRTCManager {
         F: 'static + FnMut(&mut ConnectionState, &WebRTCManager),
     {
         Closure::wrap(Box::new(move |_event: JsValue| {
-            let new_state = {
-                let inner = web_rtc_manager.borrow();
-                let mut temp_state = inner.get_state();
-            
-                match &mut temp_state {
-                    State::Server(connection_state) | State::Client(connection_state) => {
-                        action(connection_state, &*inner);
-                    }
-                    _ => {}
-                }
-            
-                temp_state
-            }; 
-            
-            web_rtc_manager.borrow_mut().set_state(new_state);
-            let web_rtc_state = web_rtc_manager.borrow().get_state();
-            web_rtc_manager
-                .borrow()
-                .callback
-                .emit(WebRtcMessage::UpdateState(web_rtc_state));
+            Self::update_state(&web_rtc_manager, &mut action);
         }) as SingleArgJsFn)
     }
+
+    fn update_state<F>(
+        web_rtc_manager: &Rc<RefCell<Self>>,
+        action: F,
+    )
+    where
+        F: FnOnce(&mut ConnectionState, &WebRTCManager),
+    {
+        let new_state = {
+            let inner = web_rtc_manager.borrow();
+            let mut temp_state = inner.get_state();
+        
+            match &mut temp_state {
+                State::Server(connection_state) | State::Client(connection_state) => {
+                    action(connection_state, &*inner);
+                }
+                _ => {}
+            }
+        
+            temp_state
+        }; 
+        
+        web_rtc_manager.borrow_mut().set_state(new_state);
+        let web_rtc_state = web_rtc_manager.borrow().get_state();
+        web_rtc_manager
+            .borrow()
+            .callback
+            .emit(WebRtcMessage::UpdateState(web_rtc_state));
+    }
     
     fn get_exception_handler(
         web_rtc_manager: Rc<RefCell<WebRTCManager>>,
@@ -1,41 +1,174 @@
-#[derive(Clone, Serialize, Deserialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug, ToSchema)]
 pub struct HostCreate {
     pub connection_details: String,
     pub compression_level: u8,
     pub password: String,
 }
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug, ToSchema)]
 pub struct HostCreateResult {
     pub code: String,
+    pub offer_id: u32,
+    pub owner_token: String,
 }
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug, ToSchema)]
+pub struct HostUpdate {
+    pub connection_details: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, ToSchema)]
+pub struct HostUpdateResult {
+    pub offer_id: u32,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, ToSchema)]
 pub struct HostPollResult {
+    pub offer_id: u32,
     pub connection_details: String,
 }
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug, ToSchema)]
 pub struct ClientGetDetails {
     pub code: String,
     pub password: String,
 }
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug, ToSchema)]
 pub struct ClientGetDetailsResult {
+    pub offer_id: u32,
     pub connection_details: String,
 }
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug, ToSchema)]
 pub struct ClientJoin {
     pub code: String,
     pub password: String,
+    pub offer_id: u32,
     pub connection_details: String,
 }
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug, ToSchema)]
 pub struct ClientJoinResult {
     pub compression_level: u8,
     pub has_password: bool,
     pub connection_details: String,
-}
\ No newline at end of file
+    /* Only handed to the client that claimed the offer, it opens the client side of the relay */
+    pub relay_token: String,
+}
+
+/* Bumped whenever request or message structures change incompatibly */
+pub const API_PROTOCOL_VERSION: u32 = 2;
+
+#[derive(Clone, Serialize, Deserialize, Debug, ToSchema)]
+pub struct VersionInfo {
+    pub version: String,
+    pub protocol_version: u32,
+    pub features: Vec<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, ToSchema)]
+pub struct Readiness {
+    pub session_store: bool,
+    pub static_assets: bool,
+}
+
+/* One entry of RTCConfiguration.iceServers, TURN entries carry time-limited credentials */
+#[derive(Clone, Serialize, Deserialize, Debug, ToSchema)]
+pub struct IceServer {
+    pub urls: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credential: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, ToSchema)]
+pub struct IceServers {
+    pub ice_servers: Vec<IceServer>,
+    /* Seconds until TURN credentials expire, 0 without TURN servers */
+    pub ttl_secs: u64,
+    /* Seconds to wait for a direct connection before falling back to the server relay, 0 without relay */
+    pub relay_timeout_secs: u64,
+}
+
+/* Host -> Server: announces a file kept for later downloads, its encrypted chunks follow one by one */
+#[derive(Clone, Serialize, Deserialize, Debug, ToSchema)]
+pub struct StoredFileCreate {
+    pub uuid: String,
+    /* Encrypted by the host like the chunks, the server cannot read it */
+    pub name: String,
+    /* Size before compression and encryption */
+    pub size: u64,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, ToSchema)]
+pub struct StoredFileInfo {
+    pub uuid: String,
+    pub name: String,
+    pub size: u64,
+    pub chunks: u32,
+}
+
+/* Server -> Client: completely uploaded files of a session, even after the host left */
+#[derive(Clone, Serialize, Deserialize, Debug, ToSchema)]
+pub struct StoredShare {
+    pub files: Vec<StoredFileInfo>,
+    /* Required to download chunks, so the password is only checked once */
+    pub access_token: String,
+    pub expires_in_secs: u64,
+}
+
+/* Body of every failed /api/v2 request */
+#[derive(Clone, Serialize, Deserialize, Debug, ToSchema)]
+pub struct ApiError {
+    pub code: String,
+    pub message: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct AdminSessionInfo {
+    pub code: String,
+    pub age_secs: u64,
+    pub idle_secs: u64,
+    pub has_password: bool,
+    pub compression_level: u8,
+    pub offer_id: u32,
+    pub offer_claimed: bool,
+    pub owner_ip: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct AdminPurge {
+    pub older_than_secs: Option<u64>,
+    pub owner_ip: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct AdminPurgeResult {
+    pub closed: usize,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, ToSchema)]
+pub enum SignalingMessage {
+    /* Host -> Server: publish a new offer, Server -> Host: offer accepted, Server -> Client: offer available */
+    Offer { offer_id: u32, connection_details: String },
+    /* Client -> Server: answer an offer, Server -> Host: answer of a joined client */
+    Answer { offer_id: u32, connection_details: String },
+    /* Host/Client -> Server: trickled ICE candidate for an offer, Server -> Host/Client: candidate of the remote peer */
+    Candidate { offer_id: u32, candidate: String },
+    /* Client -> Server */
+    Join { password: String },
+    /* Server -> Client */
+    Joined(ClientJoinResult),
+    /* Server -> Host */
+    Leave { offer_id: u32 },
+    /* Host -> Server: close the session, Server -> Host/Client: session closed */
+    Close,
+    /* Server -> Host: session ran past its lifetime or idle timeout, sent instead of Close */
+    Expired,
+    /* Server -> Host/Client: both sides joined the relay, every later frame comes from the peer */
+    Relay,
+    /* Server -> Host/Client */
+    Error { status: u16 },
+}
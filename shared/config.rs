@@ -1,34 +1,109 @@
-use once_cell::sync::Lazy;
-use std::{fs, process};
+use once_cell::sync::{Lazy, OnceCell};
+use std::{env, fs, process};
 
 const CONFIG_FILE_PATH: &str = "config.toml";
+const CONFIG_ENV_PREFIX: &str = "FILE_LINK_";
+/// The repository's `config.toml`, compiled in to provide every key a config file leaves out.
+const BUNDLED_CONFIG: &str = include_str!("../config.toml");
+
+static CONFIG_SOURCE: OnceCell<ConfigSource> = OnceCell::new();
 
 pub static BACKEND_CONFIG: Lazy<BackendConfig> = Lazy::new(|| {
-    let config: Config = strict_load_config(CONFIG_FILE_PATH);
+    let config: Config = strict_load_config(config_source());
     config.backend_config
 });
 
 pub static FRONTEND_CONFIG: Lazy<FrontendConfig> = Lazy::new(|| {
-    let config: Config = strict_load_config(CONFIG_FILE_PATH);
+    let config: Config = strict_load_config(config_source());
     config.frontend_config
 });
 
+/// Where the configuration comes from. Values are layered: the file, then `FILE_LINK_*`
+/// environment variables, then `overrides`, each replacing keys of `backend_config`.
+#[derive(Debug, Clone)]
+pub struct ConfigSource {
+    pub path: String,
+    pub overrides: Vec<(String, String)>,
+}
+
+impl Default for ConfigSource {
+    fn default() -> Self {
+        Self {
+            path: CONFIG_FILE_PATH.to_string(),
+            overrides: Vec::new(),
+        }
+    }
+}
+
+/// Has to happen before the configuration is first read, later calls are ignored.
+pub fn set_config_source(source: ConfigSource) {
+    let _ = CONFIG_SOURCE.set(source);
+}
+
+pub fn config_source() -> &'static ConfigSource {
+    CONFIG_SOURCE.get_or_init(ConfigSource::default)
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Config {
     pub backend_config: BackendConfig,
     pub frontend_config: FrontendConfig,
 }
 
+/// Keys missing from the file take their value from the bundled `config.toml`, so config files
+/// written before a key existed keep loading.
 #[derive(Debug, Deserialize)]
 pub struct BackendConfig {
     pub web_bind_addr: String,
     pub web_port: u16,
-    pub web_serve_page: String,
-    pub web_serve_api: String,
-    pub web_tls_enabled: String,
+    #[serde(deserialize_with = "deserialize_flag")]
+    pub web_serve_page: bool,
+    #[serde(deserialize_with = "deserialize_flag")]
+    pub web_serve_api: bool,
+    #[serde(deserialize_with = "deserialize_flag")]
+    pub web_serve_metrics: bool,
+    #[serde(deserialize_with = "deserialize_flag")]
+    pub web_serve_admin: bool,
+    #[serde(deserialize_with = "deserialize_flag")]
+    pub web_tls_enabled: bool,
     pub web_tls_certs: String,
     pub web_tls_key: String,
     pub web_tls_ca_cert: String,
+    pub web_root: String,
+    pub web_ip_header: String,
+    pub session_ttl_secs: u64,
+    pub session_idle_timeout_secs: u64,
+    pub session_poll_timeout_secs: u64,
+    pub session_gc_interval_secs: u64,
+    pub session_max_password_attempts: u32,
+    pub session_code_scheme: String,
+    pub session_code_alphabet: String,
+    pub session_code_length: usize,
+    pub session_code_words: usize,
+    pub rate_limit_window_secs: u64,
+    pub rate_limit_max_requests: u32,
+    pub session_store: String,
+    pub session_store_path: String,
+    pub ice_stun_urls: Vec<String>,
+    pub ice_turn_urls: Vec<String>,
+    pub ice_turn_secret: String,
+    pub ice_turn_credential_ttl_secs: u64,
+    #[serde(deserialize_with = "deserialize_flag")]
+    pub stun_enabled: bool,
+    pub stun_port: u16,
+    #[serde(deserialize_with = "deserialize_flag")]
+    pub relay_enabled: bool,
+    pub relay_ice_timeout_secs: u64,
+    #[serde(deserialize_with = "deserialize_flag")]
+    pub store_enabled: bool,
+    pub store_path: String,
+    pub store_quota_bytes: u64,
+    pub store_ttl_secs: u64,
+    pub log_level: String,
+    pub log_format: String,
+    pub admin_token: String,
+    #[serde(deserialize_with = "deserialize_flag")]
+    pub admin_client_cert: bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -36,16 +111,92 @@ pub struct FrontendConfig {
     pub api_address: String,
 }
 
-fn load_config<T: serde::de::DeserializeOwned>(path: &str) -> Result<T, Box<dyn std::error::Error>> {
-    let content = fs::read_to_string(path)?;
-    let config: T = toml::from_str(&content)?;
+/// Accepts `true`/`false` as well as the quoted `"true"`/`"false"` older config files use.
+fn deserialize_flag<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Flag {
+        Bool(bool),
+        String(String),
+    }
+
+    match Flag::deserialize(deserializer)? {
+        Flag::Bool(flag) => Ok(flag),
+        Flag::String(flag) if flag == "true" => Ok(true),
+        Flag::String(flag) if flag == "false" => Ok(false),
+        Flag::String(flag) => Err(serde::de::Error::custom(format!("expected true or false, got \"{}\"", flag))),
+    }
+}
+
+pub fn load_config<T: serde::de::DeserializeOwned>(source: &ConfigSource) -> Result<T, Box<dyn std::error::Error>> {
+    let content = fs::read_to_string(&source.path)
+        .map_err(|error| format!("Cannot read {}: {}", source.path, error))?;
+    let mut config: toml::Table = toml::from_str(&content)
+        .map_err(|error| format!("{} is not valid TOML: {}", source.path, error))?;
+
+    let bundled: toml::Table = toml::from_str(BUNDLED_CONFIG).expect("Bundled config.toml is not valid TOML");
+    for (section, bundled_section) in bundled {
+        let section = config
+            .entry(section.clone())
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+            .as_table_mut()
+            .ok_or(format!("[{}] has to be a table", section))?;
+        if let toml::Value::Table(bundled_section) = bundled_section {
+            for (key, value) in bundled_section {
+                section.entry(key).or_insert(value);
+            }
+        }
+    }
+
+    let backend_config = config
+        .get_mut("backend_config")
+        .and_then(toml::Value::as_table_mut)
+        .ok_or("[backend_config] has to be a table")?;
+
+    let env_overrides = env::vars().filter_map(|(key, value)| {
+        let key = key.strip_prefix(CONFIG_ENV_PREFIX)?.to_lowercase();
+        Some((key, value))
+    });
+    for (key, value) in env_overrides.chain(source.overrides.iter().cloned()) {
+        let value = parse_override(&key, &value, backend_config.get(&key))?;
+        backend_config.insert(key, value);
+    }
+
+    let config = toml::Value::Table(config)
+        .try_into()
+        .map_err(|error| format!("Invalid configuration: {}", error))?;
     Ok(config)
 }
 
-fn strict_load_config<T: serde::de::DeserializeOwned>(path: &str) -> T {
-    load_config::<T>(path).unwrap_or_else(|error| {
+/// Turns an override into the type the file uses for the key, so `FILE_LINK_WEB_PORT=9000`
+/// stays a number while `FILE_LINK_ADMIN_TOKEN=1234` stays a string. Lists are comma separated.
+fn parse_override(key: &str, value: &str, current: Option<&toml::Value>) -> Result<toml::Value, String> {
+    let parsed = match current {
+        Some(toml::Value::String(_)) => Some(toml::Value::String(value.to_string())),
+        Some(toml::Value::Integer(_)) => value.parse::<i64>().ok().map(toml::Value::Integer),
+        Some(toml::Value::Boolean(_)) => value.parse::<bool>().ok().map(toml::Value::Boolean),
+        Some(toml::Value::Array(_)) => Some(toml::Value::Array(
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(|item| toml::Value::String(item.to_string()))
+                .collect(),
+        )),
+        Some(_) => None,
+        None => Some(match (value.parse::<bool>(), value.parse::<i64>()) {
+            (Ok(flag), _) => toml::Value::Boolean(flag),
+            (_, Ok(number)) => toml::Value::Integer(number),
+            _ => toml::Value::String(value.to_string()),
+        }),
+    };
+    parsed.ok_or_else(|| format!("Override for {} does not match its type in the config file: \"{}\"", key, value))
+}
+
+fn strict_load_config<T: serde::de::DeserializeOwned>(source: &ConfigSource) -> T {
+    load_config::<T>(source).unwrap_or_else(|error| {
         let msg = format!("Failed to load config: {}", error);
         eprintln!("{}", msg);
         process::exit(1);
     })
-}
\ No newline at end of file
+}
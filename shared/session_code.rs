@@ -0,0 +1,35 @@
+/// Characters the checksum of a session code is drawn from, independent of the alphabet the
+/// server generates codes with so clients can check any code without knowing the configuration.
+const SESSION_CODE_CHECKSUM_ALPHABET: &[u8] = b"23456789abcdefghjkmnpqrstuvwxyz";
+
+/// Separates the words of word-list codes, random codes never contain it.
+pub const SESSION_CODE_WORD_SEPARATOR: char = '-';
+
+fn session_code_hash(body: &str) -> u32 {
+    // FNV-1a, spreads single character typos and swaps over the whole checksum range
+    body.bytes().fold(0x811c9dc5u32, |hash, byte| (hash ^ byte as u32).wrapping_mul(0x01000193))
+}
+
+/// Checksum character appended to random codes.
+pub fn session_code_checksum_char(body: &str) -> char {
+    let index = session_code_hash(body) as usize % SESSION_CODE_CHECKSUM_ALPHABET.len();
+    SESSION_CODE_CHECKSUM_ALPHABET[index] as char
+}
+
+/// Checksum number leading word-list codes, as in "61-purple-sailboat".
+pub fn session_code_checksum_number(words: &str) -> u32 {
+    session_code_hash(words) % 100
+}
+
+/// Checks the checksum embedded in a session code, so typos are caught before a request is made.
+pub fn is_valid_session_code(code: &str) -> bool {
+    if let Some((number, words)) = code.split_once(SESSION_CODE_WORD_SEPARATOR) {
+        return number == session_code_checksum_number(words).to_string();
+    }
+
+    let mut body = code.chars();
+    match body.next_back() {
+        Some(checksum) => !body.as_str().is_empty() && checksum == session_code_checksum_char(body.as_str()),
+        None => false,
+    }
+}